@@ -13,12 +13,15 @@ use tagtools::{bit::chans_to_mask, cfg::{self, SaveTags::Save, Single::Channel},
 use tokio::runtime::Builder;
 use tokio::sync::mpsc;
 
-const WIN_DEFAULT: u32 = 1;
+/// Window asked of the tagger for patterns that don't give one
+pub const WIN_DEFAULT: u32 = 1;
 
 pub struct RawChannelState {
     pub invm: u16,
     pub dels: Vec<u32>,
     pub thrs: Vec<f64>,
+    /// Global window of the tagger in logic mode, used by every pattern
+    pub window: Option<u32>,
 }
 
 struct Client {
@@ -229,7 +232,15 @@ impl Client {
                             let w = if win == 0 { None } else { Some(win) };
                             pats.push((chans_to_mask(&[ch_a, ch_b]), w));
                         }
+                        cfg::Coincidence::ChannelsWinSubtracted((ch_a, ch_b, win)) => {
+                            // The singles are needed to estimate the accidentals
+                            let w = if win == 0 { None } else { Some(win) };
+                            pats.push((chans_to_mask(&[ch_a, ch_b]), w));
+                            pats.push((chans_to_mask(&[ch_a]), None));
+                            pats.push((chans_to_mask(&[ch_b]), None));
+                        }
                         cfg::Coincidence::ChannelsCounts(_) => {}
+                        cfg::Coincidence::ChannelsCountsSubtracted(_) => {}
                     }
                 }
 
@@ -315,7 +326,13 @@ impl Client {
                 let invm = rdr.reborrow().get_inversionmask();
                 let dels: Vec<u32> = rdr.reborrow().get_delays().unwrap().iter().collect();
                 let thrs: Vec<f64> = rdr.reborrow().get_thresholds().unwrap().iter().collect();
-                let raw_settings = RawChannelState { invm, dels, thrs };
+                // Subscribing may have set the global window, so ask after
+                let window_reply = publisher.get_window_request().send().promise.await?;
+                let window = match window_reply.get()?.get_w() {
+                    0 => None,
+                    w => Some(w),
+                };
+                let raw_settings = RawChannelState { invm, dels, thrs, window };
                 Ok(Box::new(raw_settings))
             }
         ).await
@@ -1,4 +1,6 @@
 use argh::FromArgs;
+use std::collections::HashMap;
+use tagtools::{bit, cfg, pat};
 
 #[derive(Debug, FromArgs, Clone)]
 /// CLI tool to save time tag and pattern count data using a declarative runfile
@@ -12,10 +14,106 @@ pub struct CliArgs {
     /// server address
     #[argh(option, default = "String::from(\"127.0.0.1:6969\")")]
     pub addr: String,
+    /// calibrate input delays against this reference channel, from the tags
+    /// of the singles channels, instead of saving the run
+    #[argh(option)]
+    pub calibrate_delays: Option<u8>,
+    /// apply the delays found with --calibrate-delays to the time tagger
+    #[argh(switch)]
+    pub apply_delays: bool,
     /// config file path
     #[argh(positional)]
     pub config: String,
 }
 
 pub mod client;
-pub mod save;
\ No newline at end of file
+pub mod save;
+
+/// Coincidence counts with accidentals subtracted, for each pair declared as
+/// `cfg::Coincidence::ChannelsWinSubtracted`, from the pattern counts of the
+/// run keyed by mask and window as reported by the tagger. Accidentals are
+/// estimated from the singles over `run_time`, in tagtools::TSTEP, in the
+/// window the tagger actually used: its `global_window` in logic mode, and
+/// otherwise the one asked for.
+pub fn subtracted_coincidences(
+    declared: &[cfg::Coincidence],
+    pats: &HashMap<(u16, Option<u32>), u64>,
+    global_window: Option<u32>,
+    run_time: i64,
+) -> Vec<cfg::Coincidence> {
+    // Singles don't depend on the window they were asked with
+    let singles = |ch: u8| {
+        pats.iter()
+            .find(|((p, _), _)| *p == bit::chans_to_mask(&[ch]))
+            .map(|(_, &cts)| cts)
+    };
+    let mut subtracted = Vec::new();
+    for c in declared {
+        if let cfg::Coincidence::ChannelsWinSubtracted((ch_a, ch_b, win)) = *c {
+            // A pair without a window is asked for, and reported, with the default
+            let asked = if win == 0 { client::WIN_DEFAULT } else { win };
+            let pair = pats.get(&(bit::chans_to_mask(&[ch_a, ch_b]), Some(asked)));
+            if let (Some(&cts), Some(sa), Some(sb)) = (pair, singles(ch_a), singles(ch_b)) {
+                let win = global_window.unwrap_or(asked);
+                let acc = pat::accidentals_singles_counts(sa, sb, win.into(), run_time);
+                subtracted.push(cfg::Coincidence::ChannelsCountsSubtracted(
+                    (ch_a, ch_b, win, cts, cts as f64 - acc.value)
+                ));
+            }
+        }
+    }
+    subtracted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn counts() -> HashMap<(u16, Option<u32>), u64> {
+        [
+            ((bit::chans_to_mask(&[1]), Some(1)), 1_000_000),
+            ((bit::chans_to_mask(&[2]), Some(1)), 2_000_000),
+            ((bit::chans_to_mask(&[1, 2]), Some(1)), 5_000),
+            ((bit::chans_to_mask(&[1, 2]), Some(16)), 9_000),
+        ].into_iter().collect()
+    }
+
+    #[test]
+    fn default_window_accidentals() {
+        let declared = [cfg::Coincidence::ChannelsWinSubtracted((1, 2, 0))];
+        // 1e6 and 2e6 singles over 1e10 tagtools::TSTEP, in a window of 1
+        let subtracted = subtracted_coincidences(&declared, &counts(), None, 10_000_000_000);
+        match subtracted[..] {
+            [cfg::Coincidence::ChannelsCountsSubtracted((1, 2, win, cts, sub))] => {
+                assert_eq!((1, 5_000), (win, cts));
+                assert!((sub - 4_800.).abs() < 1e-6);
+            }
+            _ => panic!("unexpected {:?}", subtracted),
+        }
+    }
+
+    #[test]
+    fn exact_and_global_windows() {
+        let declared = [
+            cfg::Coincidence::ChannelsWinSubtracted((1, 2, 16)),
+            cfg::Coincidence::ChannelsWinSubtracted((1, 2, 4)),
+        ];
+        // The pair at window 4 wasn't counted, so it can't be subtracted
+        let subtracted = subtracted_coincidences(&declared, &counts(), None, 10_000_000_000);
+        match subtracted[..] {
+            [cfg::Coincidence::ChannelsCountsSubtracted((1, 2, 16, 9_000, sub))] => {
+                assert!((sub - 5_800.).abs() < 1e-6);
+            }
+            _ => panic!("unexpected {:?}", subtracted),
+        }
+        // In logic mode, the accidentals are in the global window
+        let declared = [cfg::Coincidence::ChannelsWinSubtracted((1, 2, 0))];
+        let subtracted = subtracted_coincidences(&declared, &counts(), Some(8), 10_000_000_000);
+        match subtracted[..] {
+            [cfg::Coincidence::ChannelsCountsSubtracted((1, 2, 8, 5_000, sub))] => {
+                assert!((sub - 3_400.).abs() < 1e-6);
+            }
+            _ => panic!("unexpected {:?}", subtracted),
+        }
+    }
+}
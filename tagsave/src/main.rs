@@ -5,11 +5,11 @@ use tagsave::CliArgs;
 use parking_lot::Mutex;
 use std::collections::HashMap;
 use std::fs::{File, OpenOptions};
-use std::net::ToSocketAddrs;
+use std::net::{SocketAddr, ToSocketAddrs};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 use std::io::{BufReader, BufWriter, Write};
-use tagtools::{CHAN16, Tag, bit, cfg};
+use tagtools::{CHAN16, COUNTER_STEP, TSTEP, Tag, bit, cfg, pat};
 use tagsave::client::{ClientHandle, ClientMessage};
 use tagsave::save::{SaveHandle, SaveMessage, SaveTags};
 
@@ -34,6 +34,9 @@ async fn main() -> Result<()> {
         )?;
         return Ok(())
     }
+    if args.apply_delays && args.calibrate_delays.is_none() {
+        bail!("--apply-delays needs --calibrate-delays");
+    }
 
     // Load address
     let addr = args
@@ -44,7 +47,7 @@ async fn main() -> Result<()> {
         .expect("could not parse address");
 
     // Load the run file
-    let config: tagtools::cfg::Run;
+    let mut config: tagtools::cfg::Run;
     let cfg_path = std::path::PathBuf::from(args.config.clone());
     {
         let f = File::open(cfg_path.as_path())?;
@@ -52,6 +55,16 @@ async fn main() -> Result<()> {
         config = serde_json::from_reader(rdr)?;
     }
 
+    // Calibrating delays needs the tags, but doesn't save anything
+    let client_config = match args.calibrate_delays {
+        Some(_) => cfg::Run { save_tags: Some(cfg::SaveTags::Save(true)), ..config.clone() },
+        None => config.clone(),
+    };
+    if args.calibrate_delays.is_some() {
+        config.save_tags = None;
+    }
+    let mut calibration_tags = Vec::<Tag>::new();
+
     // Timestamp to save under, reflect the beginning time rather than the end
     let ts = Utc::now();
     // This is the only cross-platform ISO 8601 compliant timestamp format
@@ -98,13 +111,28 @@ async fn main() -> Result<()> {
     let filepath: Option<std::path::PathBuf> = Some(tags_path);
 
     let mut duration = 0u64;
+    let mut pats_duration = 0u64;
     let timestamp = Utc::now();
+
+    // Start the tags file with the run as declared, so it stands on its own
+    if config.save_tags == Some(cfg::SaveTags::Save(true)) {
+        let run = cfg::Run {
+            timestamp: Some(timestamp),
+            version: String::from(GIT_VERSION),
+            ..config.clone()
+        };
+        let meta = cfg::Metadata {
+            timestamp,
+            ..cfg::Metadata::new(GIT_VERSION, run, config.channel_settings.clone())
+        };
+        save.sender.send(SaveMessage::Metadata(Box::new(meta)))?;
+    }
     
     let first_tick = Instant::now();
     let mut last_tick = first_tick;
 
     // Start client thread, connect to server
-    let client = ClientHandle::new(addr, config.clone());
+    let client = ClientHandle::new(addr, client_config);
 
     pb.set_prefix("Acquiring");
 
@@ -122,6 +150,8 @@ async fn main() -> Result<()> {
             Some(data) => {
                 for mut chunk in data {
                     duration += chunk.tagpat.duration;
+                    // Patterns are counted over the same duration, even without tags
+                    pats_duration += chunk.pats.first().map_or(0, |p| p.duration);
                     (*tags).append(&mut chunk.tagpat.tags);
                     for lpat in chunk.pats {
                         if let None = (*pats).get(&(lpat.patmask, lpat.window)) {
@@ -136,6 +166,10 @@ async fn main() -> Result<()> {
             None => {},
         }
 
+        if args.calibrate_delays.is_some() {
+            calibration_tags.extend_from_slice(&tags);
+        }
+
         // Save tags to disk
         if config.save_tags == Some(cfg::SaveTags::Save(true)) {
             match save.sender.send(
@@ -145,7 +179,8 @@ async fn main() -> Result<()> {
             )
             {
                 Ok(()) => {},
-                Err(_) => {},
+                // The save thread stopped on an error, which `finish` returns
+                Err(_) => break,
             }
         }
 
@@ -196,6 +231,13 @@ async fn main() -> Result<()> {
 
     let raw_settings = client.join_handle.join().unwrap()?;
 
+    if let Some(reference) = args.calibrate_delays {
+        calibrate_delays(&pb, addr, &config, reference, &calibration_tags, &raw_settings.dels, args.apply_delays)?;
+        save.finish()?;
+        pb.finish();
+        return Ok(())
+    }
+
     // Now record the run record to disk
     let mut record = cfg::Run{
         // name:            from declaration
@@ -223,6 +265,14 @@ async fn main() -> Result<()> {
             );
         }
     }
+    // Accidentals from the singles rates over the whole run, in tagtools::TSTEP
+    let run_time = (pats_duration as f64 * COUNTER_STEP / TSTEP) as i64;
+    record.coincidences.extend(tagsave::subtracted_coincidences(
+        &config.coincidences,
+        &pats,
+        raw_settings.window,
+        run_time,
+    ));
     if config.save_tags == Some(cfg::SaveTags::Save(true)) {
         record.save_tags = Some(cfg::SaveTags::TagFile(filepath.clone().unwrap()));
     }
@@ -260,7 +310,7 @@ async fn main() -> Result<()> {
         let mut  wtr = BufWriter::new(f);
         wtr.write_all(json_record.as_bytes())?;
 
-        save.sender.send(SaveMessage::Reset)?;
+        save.finish()?;
         
         pb.set_prefix("Saved");
         pb.println(    format!("Data saved to {}", rcd_path.file_name().unwrap().to_string_lossy()));
@@ -272,3 +322,64 @@ async fn main() -> Result<()> {
 
     Ok(())
 }
+
+/// Find the coincidence peaks between the reference and the other singles
+/// channels, print the aligned input delays, and optionally apply them
+fn calibrate_delays(
+    pb: &ProgressBar,
+    addr: SocketAddr,
+    config: &cfg::Run,
+    reference: u8,
+    tags: &[Tag],
+    current: &[u32],
+    apply: bool,
+) -> Result<()> {
+    let channels: Vec<u8> = config.singles
+        .iter()
+        .filter_map(|s| if let cfg::Single::Channel(ch) = s { Some(*ch) } else { None })
+        .collect();
+    if !channels.contains(&reference) {
+        bail!("Reference channel {} is not among the singles channels", reference);
+    }
+    let peaks = pat::calibrate_delays(tags, reference, &channels, 1, pat::CALIBRATION_RANGE);
+    let current = channels
+        .iter()
+        .map(|&ch| (ch, current[ch as usize - 1]))
+        .collect();
+    let delays = pat::aligned_delays(reference, &peaks, &current);
+
+    pb.set_prefix("Calibrated");
+    for &ch in &channels {
+        let found = match peaks.get(&ch) {
+            Some(peak) => format!("peak at {:+.3} ns", peak.centroid * TSTEP * 1e9),
+            None if ch == reference => String::from("reference"),
+            None => String::from("no peak found"),
+        };
+        let delay = match delays.get(&ch) {
+            Some(d) => format!("{} -> {}", current[&ch], d),
+            None => format!("{}", current[&ch]),
+        };
+        pb.println(format!("Channel {:>2}: delay {:>14} ({})", ch, delay, found));
+    }
+
+    if apply {
+        let settings = cfg::Run {
+            channel_settings: delays
+                .iter()
+                .map(|(&channel, &delay)| cfg::ChannelSettings {
+                    channel,
+                    invert: None,
+                    delay: Some(delay),
+                    threshold: None,
+                })
+                .collect(),
+            ..Default::default()
+        };
+        // The client applies the channel settings as soon as it connects
+        let client = ClientHandle::new(addr, settings);
+        client.sender.send(ClientMessage::Shutdown)?;
+        client.join_handle.join().unwrap()?;
+        pb.println("Delays applied");
+    }
+    Ok(())
+}
@@ -3,15 +3,44 @@ use either::Either;
 use parking_lot::RwLock;
 use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
+use std::fs::File;
+use std::io::BufReader;
 use tagtools::{CHAN16, Tag};
-use timetag::error_text;
-use timetag::ffi::{new_time_tagger, new_logic_counter, FfiTag};
 
 #[allow(unused_imports)]
 use tracing::{debug, error, info, span, warn, Instrument, Level};
 
 use crate::{CliArgs, Event, InputSetting};
 use crate::data::{LogicData, RawData, RawTags, WIN_DEFAULT};
+use crate::device::{error_text, TagDevice};
+use crate::replay::Replay;
+use crate::sim::{SimConfig, Simulator};
+
+/// Select the simulator, a replayed file or the hardware device from the CLI args
+fn device(args: &CliArgs) -> Result<Box<dyn TagDevice>> {
+    if let Some(path) = &args.replay {
+        if args.sim {
+            bail!("--sim and --replay can't be used together");
+        }
+        info!("replaying {} at speed {}", path, args.replay_speed);
+        return Ok(Box::new(Replay::new(path.into(), args.replay_speed)));
+    }
+    if !args.sim {
+        #[cfg(feature = "hardware")]
+        return Ok(Box::new(crate::device::Hardware::new()));
+        #[cfg(not(feature = "hardware"))]
+        bail!("built without hardware support, use --sim");
+    }
+    let config = match &args.sim_config {
+        Some(path) => {
+            let rdr = BufReader::new(File::open(path)?);
+            serde_json::from_reader(rdr)?
+        }
+        None => SimConfig::default(),
+    };
+    info!("using simulated time tagger");
+    Ok(Box::new(Simulator::new(config)))
+}
 
 /// Create and manage time tagger, providing data to the server thread
 pub fn main(
@@ -26,11 +55,11 @@ pub fn main(
     let span = span!(Level::INFO, "controller");
     let _enter = span.enter();
 
-    let tt = new_time_tagger();
-    if let Err(_) = tt.open() {
+    let tt = device(&args)?;
+    if let Err(e) = tt.open() {
         let span = span!(Level::ERROR, "connection");
         let _enter = span.enter();
-        error!("Could not connect to time tagger");
+        error!("Could not connect to time tagger: {}", e);
         bail!("tagger connection failed");
     }
     info!("tagger connected");
@@ -68,15 +97,7 @@ pub fn main(
                     Err(_) => return Ok(true),
                     Ok(_) => {
                         let dur = tt.freeze_single_counter();
-                        let tags: Arc<Vec<Tag>> = Arc::new(tt
-                            .read_tags()
-                            .iter()
-                            // BUG: does this map cause the discrepancy in times?
-                            .map(|t: &FfiTag| Tag {
-                                time: t.time,
-                                channel: t.channel,
-                            })
-                            .collect());
+                        let tags: Arc<Vec<Tag>> = Arc::new(tt.read_tags());
 
                         let flags = tt.read_error_flags();
                         if flags != 0 {
@@ -114,7 +135,7 @@ pub fn main(
     } else {
         // Logic mode
         info!("logic mode");
-        let lc = new_logic_counter(tt.clone());
+        let lc = tt.logic_counter();
         lc.switch_logic_mode();
         let gw = global_window.read();
         if let None = args.window {
@@ -1,7 +1,7 @@
 use either::Either;
 use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
-use tagtools::{bit, pat, Tag};
+use tagtools::{bit, pat, Tag, COUNTER_STEP, TSTEP};
 
 pub const WIN_DEFAULT: u32 = 1;
 
@@ -24,42 +24,104 @@ pub struct LogicData {
 pub type RawData = Either<RawTags, LogicData>;
 pub type PubData = Either<TagData, LogicData>;
 
-/// Calculate the counts in a set of pattern masks, doing the calculations in parallel
+/// Calculate the counts in a set of pattern masks.
+///
+/// All patterns are counted in a single pass over the tags with
+/// `pat::pattern_counts`, at zero delay since channel delays are already
+/// applied by the tagger. A pair counts each tag of its lower channel once if
+/// the other channel has a tag in the same window, as `pat::coincidence`.
 pub fn count_patterns(tags: &[Tag], patmasks: HashSet<(u16, Option<u32>)>) -> HashMap<(u16, Option<u32>), u64> {
-    use rayon::prelude::*;
+    // The empty pattern has nothing to count
+    let keys: Vec<(u16, Option<u32>)> = patmasks.into_iter().filter(|&(pat, _)| pat != 0).collect();
+    let patterns: Vec<(u16, i64)> = keys
+        .iter()
+        .map(|&(pat, win)| (pat, win.unwrap_or(WIN_DEFAULT).into()))
+        .collect();
+    let counts = pat::pattern_counts(tags.iter().copied(), &patterns);
+    keys.into_iter().zip(counts).collect()
+}
 
-    let mut hm = HashMap::<(u16, Option<u32>), u64>::new();
-    // Preallocate the hashmap so we can perform the calculations in parallel
-    for (pat, win) in patmasks {
-        match pat.count_ones() {
-            1 => {
-                hm.insert((pat, win), 0);
-            }
-            2 => {
-                hm.insert((pat, win), 0);
+/// Two-channel coincidence counts from `count_patterns` over a tick lasting
+/// `dur` (in 5 ns steps, as `RawTags::dur`), with the accidentals expected
+/// from the singles of both channels subtracted, for every pair whose singles
+/// were counted too. Values are neither rounded nor clamped at zero, since the
+/// accidentals in a single tick are usually well under one count, and are
+/// meant to be summed over many ticks.
+pub fn subtracted_counts(
+    counts: &HashMap<(u16, Option<u32>), u64>,
+    dur: u64,
+) -> HashMap<(u16, Option<u32>), f64> {
+    // Singles don't depend on the window they were asked with
+    let singles = |ch: u8| {
+        counts.iter()
+            .find(|((p, _), _)| *p == bit::chans_to_mask(&[ch]))
+            .map(|(_, &cts)| cts)
+    };
+    let duration = (dur as f64 * COUNTER_STEP / TSTEP).round() as i64;
+    let mut subtracted = HashMap::new();
+    for (&(pat, win), &cts) in counts {
+        if let Some((ch_a, ch_b)) = bit::mask_to_pair(pat) {
+            if let (Some(sa), Some(sb)) = (singles(ch_a), singles(ch_b)) {
+                let acc = pat::accidentals_singles_counts(sa, sb, win.unwrap_or(WIN_DEFAULT).into(), duration);
+                subtracted.insert((pat, win), cts as f64 - acc.value);
             }
-            // TODO: Implement higher-order patterns
-            _ => {}
         }
     }
-    hm.par_iter_mut().for_each(|((pat, win), count)| {
-        match pat.count_ones() {
-            1 => {
-                *count += pat::singles(&tags.clone(), bit::mask_to_single(*pat).unwrap());
-            }
-            2 => {
-                let (ch_a, ch_b) = bit::mask_to_pair(*pat).unwrap();
-                *count += pat::coincidence(
-                    &tags.clone(),
-                    ch_a,
-                    ch_b,
-                    win.unwrap_or(WIN_DEFAULT).into(),
-                    0,
-                );
-            }
-            // TODO: Implement higher-order patterns
-            _ => {}
-        }
-    });
-    hm
-}
\ No newline at end of file
+    subtracted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tagtools::bit::chans_to_mask;
+
+    /// A pair counts each tag of its lower channel at most once, however many
+    /// tags of the other channel share its window
+    #[test]
+    fn pair_counts_lower_channel_tags() {
+        let tags = [
+            Tag { time: 100, channel: 1 },
+            Tag { time: 101, channel: 2 },
+            Tag { time: 102, channel: 2 },
+            Tag { time: 103, channel: 3 },
+            Tag { time: 200, channel: 2 },
+            Tag { time: 201, channel: 1 },
+            Tag { time: 202, channel: 1 },
+            Tag { time: 300, channel: 1 },
+        ];
+        let patmasks: HashSet<(u16, Option<u32>)> = [
+            (chans_to_mask(&[1]), None),
+            (chans_to_mask(&[1, 2]), None),
+            (chans_to_mask(&[1, 2]), Some(16)),
+            (chans_to_mask(&[1, 2, 3]), Some(16)),
+            (0, None),
+        ]
+        .into_iter()
+        .collect();
+        let counts = count_patterns(&tags, patmasks);
+        assert_eq!(4, counts.len());
+        assert_eq!(4, counts[&(chans_to_mask(&[1]), None)]);
+        assert_eq!(0, counts[&(chans_to_mask(&[1, 2]), None)]);
+        // Windows of 16 hold 100..=103, 192..=207 and 288..=303
+        assert_eq!(3, counts[&(chans_to_mask(&[1, 2]), Some(16))]);
+        assert_eq!(1, counts[&(chans_to_mask(&[1, 2, 3]), Some(16))]);
+
+    }
+
+    /// Accidentals are subtracted over the tick, without rounding
+    #[test]
+    fn subtracted_pair_counts() {
+        let counts: HashMap<(u16, Option<u32>), u64> = [
+            ((chans_to_mask(&[1]), None), 4),
+            ((chans_to_mask(&[2]), Some(16)), 3),
+            ((chans_to_mask(&[1, 2]), Some(16)), 3),
+            ((chans_to_mask(&[1, 3]), None), 2),
+        ]
+        .into_iter()
+        .collect();
+        // A tick of 25 * 5 ns is 800 time units, for 4 * 3 * 16 / 800 accidentals
+        let subtracted = subtracted_counts(&counts, 25);
+        assert_eq!(1, subtracted.len());
+        assert!((subtracted[&(chans_to_mask(&[1, 2]), Some(16))] - 2.76).abs() < 1e-12);
+    }
+}
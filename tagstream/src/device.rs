@@ -0,0 +1,194 @@
+//! Abstraction over the time tagger, so the controller can drive either the
+//! vendor hardware (via `timetag`) or the software simulator in `sim`.
+//!
+//! The traits mirror the subset of the `timetag::ffi::TimeTagger` and
+//! `timetag::ffi::LogicCounter` methods that the controller uses, with the
+//! same `&self` receivers: implementations handle any mutable state internally,
+//! as the vendor library does.
+
+use anyhow::Result;
+use std::collections::HashSet;
+use tagtools::Tag;
+
+#[cfg(feature = "hardware")]
+use timetag::ffi::{new_logic_counter, new_time_tagger, FfiTag};
+
+/// A time tagger in tag mode
+pub trait TagDevice {
+    /// Connect to the device
+    fn open(&self) -> Result<()>;
+    /// Disconnect from the device
+    fn close(&self);
+    /// Calibrate the timing of the inputs
+    fn calibrate(&self);
+    fn get_fpga_version(&self) -> i32;
+    /// Timing resolution in seconds
+    fn get_resolution(&self) -> f64;
+    fn read_error_flags(&self) -> u32;
+    fn set_input_threshold(&self, input: u8, voltage: f64);
+    fn set_inversion_mask(&self, mask: u16);
+    /// Delay an input by an integer number of `tagtools::TSTEP`
+    fn set_delay(&self, input: u8, delay: u32);
+    /// Function generator on output 4, in 5 ns steps
+    fn set_fg(&self, period: u32, high: u32);
+    fn start_timetags(&self);
+    fn stop_timetags(&self);
+    /// Time since the last call, in 5 ns steps
+    fn freeze_single_counter(&self) -> u64;
+    /// Tags acquired since the last call
+    fn read_tags(&self) -> Vec<Tag>;
+    /// Switch the device over to logic mode
+    fn logic_counter(&self) -> Box<dyn LogicDevice>;
+}
+
+/// A time tagger in logic mode, counting patterns on the device
+pub trait LogicDevice {
+    fn switch_logic_mode(&self);
+    fn read_error_flags(&self) -> u32;
+    fn set_window_width(&self, window: u32);
+    fn set_input_threshold(&self, input: u8, voltage: f64);
+    fn set_inversion_mask(&self, mask: u16);
+    fn set_delay(&self, input: u8, delay: u32);
+    fn set_fg(&self, period: u32, high: u32);
+    /// Latch the pattern counts since the last call
+    fn read_logic(&self) -> i64;
+    /// Count of events with at least the channels in `pos`, as of the last `read_logic`
+    fn calc_count_pos(&self, pos: u16) -> u32;
+    /// Duration of the last `read_logic` interval, in 5 ns steps
+    fn get_time_counter(&self) -> u64;
+}
+
+/// Vendor time tagger, connected over USB
+#[cfg(feature = "hardware")]
+pub struct Hardware {
+    tt: cxx::SharedPtr<timetag::ffi::TimeTagger>,
+}
+
+#[cfg(feature = "hardware")]
+impl Hardware {
+    pub fn new() -> Self {
+        Hardware { tt: new_time_tagger() }
+    }
+}
+
+#[cfg(feature = "hardware")]
+impl Default for Hardware {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "hardware")]
+impl TagDevice for Hardware {
+    fn open(&self) -> Result<()> {
+        self.tt.open()?;
+        Ok(())
+    }
+    fn close(&self) {
+        self.tt.close()
+    }
+    fn calibrate(&self) {
+        self.tt.calibrate()
+    }
+    fn get_fpga_version(&self) -> i32 {
+        self.tt.get_fpga_version()
+    }
+    fn get_resolution(&self) -> f64 {
+        self.tt.get_resolution()
+    }
+    fn read_error_flags(&self) -> u32 {
+        self.tt.read_error_flags()
+    }
+    fn set_input_threshold(&self, input: u8, voltage: f64) {
+        self.tt.set_input_threshold(input, voltage)
+    }
+    fn set_inversion_mask(&self, mask: u16) {
+        self.tt.set_inversion_mask(mask)
+    }
+    fn set_delay(&self, input: u8, delay: u32) {
+        self.tt.set_delay(input, delay)
+    }
+    fn set_fg(&self, period: u32, high: u32) {
+        self.tt.set_fg(period, high)
+    }
+    fn start_timetags(&self) {
+        self.tt.start_timetags()
+    }
+    fn stop_timetags(&self) {
+        self.tt.stop_timetags()
+    }
+    fn freeze_single_counter(&self) -> u64 {
+        self.tt.freeze_single_counter()
+    }
+    fn read_tags(&self) -> Vec<Tag> {
+        self.tt
+            .read_tags()
+            .iter()
+            // BUG: does this map cause the discrepancy in times?
+            .map(|t: &FfiTag| Tag {
+                time: t.time,
+                channel: t.channel,
+            })
+            .collect()
+    }
+    fn logic_counter(&self) -> Box<dyn LogicDevice> {
+        Box::new(HardwareLogic {
+            lc: new_logic_counter(self.tt.clone()),
+        })
+    }
+}
+
+/// Vendor time tagger in logic mode
+#[cfg(feature = "hardware")]
+pub struct HardwareLogic {
+    lc: cxx::SharedPtr<timetag::ffi::LogicCounter>,
+}
+
+#[cfg(feature = "hardware")]
+impl LogicDevice for HardwareLogic {
+    fn switch_logic_mode(&self) {
+        self.lc.switch_logic_mode()
+    }
+    fn read_error_flags(&self) -> u32 {
+        self.lc.read_error_flags()
+    }
+    fn set_window_width(&self, window: u32) {
+        self.lc.set_window_width(window)
+    }
+    fn set_input_threshold(&self, input: u8, voltage: f64) {
+        self.lc.set_input_threshold(input, voltage)
+    }
+    fn set_inversion_mask(&self, mask: u16) {
+        self.lc.set_inversion_mask(mask)
+    }
+    fn set_delay(&self, input: u8, delay: u32) {
+        self.lc.set_delay(input, delay)
+    }
+    fn set_fg(&self, period: u32, high: u32) {
+        self.lc.set_fg(period, high)
+    }
+    fn read_logic(&self) -> i64 {
+        self.lc.read_logic()
+    }
+    fn calc_count_pos(&self, pos: u16) -> u32 {
+        self.lc.calc_count_pos(pos)
+    }
+    fn get_time_counter(&self) -> u64 {
+        self.lc.get_time_counter()
+    }
+}
+
+/// Names of the error flags set by the device
+#[cfg(feature = "hardware")]
+pub fn error_text(flags: u32) -> HashSet<String> {
+    timetag::error_text(flags)
+}
+
+/// Names of the error flags set by the device. Without the vendor library,
+/// flags can only be reported by bit position.
+#[cfg(not(feature = "hardware"))]
+pub fn error_text(flags: u32) -> HashSet<String> {
+    bit_iter::BitIter::from(flags)
+        .map(|b| format!("UnknownFlag{}", b))
+        .collect()
+}
@@ -1,8 +1,11 @@
 pub mod controller;
 pub mod data;
+pub mod device;
 pub mod processor;
+pub mod replay;
 pub mod rpc;
 pub mod server;
+pub mod sim;
 pub mod timer;
 
 use argh::FromArgs;
@@ -30,6 +33,18 @@ pub struct CliArgs {
     /// server address
     #[argh(option, default = "String::from(\"127.0.0.1:6969\")")]
     pub addr: String,
+    /// use a simulated time tagger instead of the hardware
+    #[argh(switch)]
+    pub sim: bool,
+    /// json file of simulated event sources (see tagstream::sim::SimConfig)
+    #[argh(option)]
+    pub sim_config: Option<String>,
+    /// replay tags from a .tags.zst file instead of acquiring them
+    #[argh(option)]
+    pub replay: Option<String>,
+    /// replay speed relative to the recording
+    #[argh(option, default = "1.0")]
+    pub replay_speed: f64,
 }
 
 pub enum Event {
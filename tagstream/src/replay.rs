@@ -0,0 +1,307 @@
+//! Replay a recorded `.tags.zst` file as if it were a live time tagger.
+//!
+//! Each read of the device returns the next chunk of recorded time, one
+//! `timer::TICK` long (scaled by the replay speed), so the tags reach the
+//! processor and subscribers in the same sized pieces as during acquisition,
+//! and at the same pace. Chunks are cut at fixed recorded times, which makes
+//! the published data deterministic for a given file and speed. Input settings
+//! are accepted but have no effect, as the tags were already recorded with the
+//! settings in place at the time.
+
+use anyhow::{bail, Result};
+use parking_lot::Mutex;
+use std::fs::File;
+use std::path::PathBuf;
+use tagtools::{de, pat, Tag, COUNTER_STEP, TSTEP};
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, span, warn, Instrument, Level};
+
+use crate::device::{LogicDevice, TagDevice};
+use crate::timer::TICK;
+
+/// Splits time-sorted tags into consecutive chunks of equal duration
+pub struct Chunks {
+    /// Chunks of tags as read from the recording
+    source: Box<dyn Iterator<Item = Result<Vec<Tag>>> + Send>,
+    /// Tags read from the source, from `pos` on not yet returned
+    buf: Vec<Tag>,
+    pos: usize,
+    /// Start of the next chunk, from the first tag
+    start: Option<i64>,
+    /// Duration of each chunk in `tagtools::TSTEP`
+    len: i64,
+    finished: bool,
+}
+
+impl Chunks {
+    /// Chunks of `seconds` of recorded time, starting from the first tag, read
+    /// from `source` only as they are needed
+    pub fn new(source: impl Iterator<Item = Result<Vec<Tag>>> + Send + 'static, seconds: f64) -> Self {
+        Chunks {
+            source: Box::new(source),
+            buf: Vec::new(),
+            pos: 0,
+            start: None,
+            len: ((seconds / TSTEP).round() as i64).max(1),
+            finished: false,
+        }
+    }
+
+    /// Tags in the next chunk, which is empty once all tags are returned
+    pub fn next_chunk(&mut self) -> Vec<Tag> {
+        let mut chunk = Vec::new();
+        if !self.fill() {
+            return chunk;
+        }
+        let start = *self.start.get_or_insert(self.buf[self.pos].time);
+        let end = start + self.len;
+        self.start = Some(end);
+        loop {
+            let rest = &self.buf[self.pos..];
+            let n = rest.partition_point(|t| t.time < end);
+            chunk.extend_from_slice(&rest[..n]);
+            self.pos += n;
+            if self.pos < self.buf.len() || !self.fill() {
+                return chunk;
+            }
+        }
+    }
+
+    /// Read from the source until there are tags to return, if there are any
+    /// left
+    fn fill(&mut self) -> bool {
+        while self.pos == self.buf.len() {
+            match self.source.next() {
+                Some(Ok(tags)) => {
+                    self.buf = tags;
+                    self.pos = 0;
+                }
+                // The recording is replayed up to where it can't be read
+                Some(Err(e)) => {
+                    error!("replay stopped: {:#}", e);
+                    self.finish();
+                    return false;
+                }
+                None => {
+                    self.finish();
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    fn finish(&mut self) {
+        if !self.finished {
+            self.finished = true;
+            info!("replay finished");
+        }
+        self.source = Box::new(std::iter::empty());
+    }
+}
+
+struct ReplayState {
+    chunks: Option<Chunks>,
+    running: bool,
+}
+
+/// Replayed time tagger in tag mode
+pub struct Replay {
+    path: PathBuf,
+    speed: f64,
+    state: Mutex<ReplayState>,
+}
+
+impl Replay {
+    pub fn new(path: PathBuf, speed: f64) -> Self {
+        Replay {
+            path,
+            speed,
+            state: Mutex::new(ReplayState {
+                chunks: None,
+                running: false,
+            }),
+        }
+    }
+
+    /// Recorded time per tick, in seconds
+    fn chunk_seconds(&self) -> f64 {
+        TICK.as_secs_f64() * self.speed
+    }
+
+    fn load(&self) -> Result<Chunks> {
+        if !(self.speed > 0.0 && self.speed.is_finite()) {
+            bail!("replay speed {} is not a positive number", self.speed);
+        }
+        // Read as it is replayed, so recordings of any length fit in memory
+        let chunks = de::tag_chunks(File::open(&self.path)?)?;
+        info!("replaying {:?}", self.path);
+        Ok(Chunks::new(chunks, self.chunk_seconds()))
+    }
+}
+
+impl TagDevice for Replay {
+    fn open(&self) -> Result<()> {
+        let chunks = self.load()?;
+        self.state.lock().chunks = Some(chunks);
+        Ok(())
+    }
+    fn close(&self) {}
+    fn calibrate(&self) {}
+    fn get_fpga_version(&self) -> i32 {
+        0
+    }
+    fn get_resolution(&self) -> f64 {
+        TSTEP
+    }
+    fn read_error_flags(&self) -> u32 {
+        0
+    }
+    fn set_input_threshold(&self, _input: u8, _voltage: f64) {}
+    fn set_inversion_mask(&self, _mask: u16) {}
+    fn set_delay(&self, _input: u8, _delay: u32) {}
+    fn set_fg(&self, _period: u32, _high: u32) {}
+    fn start_timetags(&self) {
+        self.state.lock().running = true;
+    }
+    fn stop_timetags(&self) {
+        self.state.lock().running = false;
+    }
+    fn freeze_single_counter(&self) -> u64 {
+        (self.chunk_seconds() / COUNTER_STEP).round() as u64
+    }
+    fn read_tags(&self) -> Vec<Tag> {
+        let mut s = self.state.lock();
+        if !s.running {
+            return Vec::new();
+        }
+        match s.chunks.as_mut() {
+            Some(c) => c.next_chunk(),
+            None => Vec::new(),
+        }
+    }
+    fn logic_counter(&self) -> Box<dyn LogicDevice> {
+        let chunks = self.state.lock().chunks.take();
+        Box::new(ReplayLogic {
+            duration: (self.chunk_seconds() / COUNTER_STEP).round() as u64,
+            state: Mutex::new(ReplayLogicState {
+                chunks,
+                window: 1,
+                tags: Vec::new(),
+            }),
+        })
+    }
+}
+
+struct ReplayLogicState {
+    chunks: Option<Chunks>,
+    window: u32,
+    /// Tags of the last chunk, from which patterns are counted
+    tags: Vec<Tag>,
+}
+
+/// Replayed time tagger in logic mode. Patterns are counted from the replayed
+/// tags with `tagtools::pat::coincidence_nfold` in the global window.
+pub struct ReplayLogic {
+    /// Duration of each chunk in 5 ns steps
+    duration: u64,
+    state: Mutex<ReplayLogicState>,
+}
+
+impl LogicDevice for ReplayLogic {
+    fn switch_logic_mode(&self) {}
+    fn read_error_flags(&self) -> u32 {
+        0
+    }
+    fn set_window_width(&self, window: u32) {
+        self.state.lock().window = window.max(1);
+    }
+    fn set_input_threshold(&self, _input: u8, _voltage: f64) {}
+    fn set_inversion_mask(&self, _mask: u16) {}
+    fn set_delay(&self, _input: u8, _delay: u32) {}
+    fn set_fg(&self, _period: u32, _high: u32) {}
+    fn read_logic(&self) -> i64 {
+        let mut s = self.state.lock();
+        s.tags = match s.chunks.as_mut() {
+            Some(c) => c.next_chunk(),
+            None => Vec::new(),
+        };
+        s.tags.len() as i64
+    }
+    fn calc_count_pos(&self, pos: u16) -> u32 {
+        let s = self.state.lock();
+        let delays = vec![0; pos.count_ones() as usize];
+        pat::coincidence_nfold(&s.tags, pos, s.window.into(), &delays) as u32
+    }
+    fn get_time_counter(&self) -> u64 {
+        self.duration
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::count_patterns;
+    use std::collections::HashSet;
+    use tagtools::bit::chans_to_mask;
+
+    fn tags() -> Vec<Tag> {
+        let mut tags = Vec::new();
+        for i in 0..10_000i64 {
+            tags.push(Tag { time: 1_000_000 + 997 * i, channel: 1 });
+            if i % 3 == 0 {
+                tags.push(Tag { time: 1_000_000 + 997 * i + 5, channel: 2 });
+            }
+        }
+        tags
+    }
+
+    /// Tags as read from a file, in pieces unrelated to the replayed chunks
+    fn source(tags: &[Tag]) -> impl Iterator<Item = Result<Vec<Tag>>> + Send {
+        let pieces: Vec<Result<Vec<Tag>>> = tags.chunks(777).map(|c| Ok(c.to_vec())).collect();
+        pieces.into_iter()
+    }
+
+    #[test]
+    fn chunks_cover_all_tags() {
+        let tags = tags();
+        let mut chunks = Chunks::new(source(&tags), 1e-6);
+        let mut replayed = Vec::new();
+        loop {
+            let chunk = chunks.next_chunk();
+            if chunks.finished && chunk.is_empty() {
+                break;
+            }
+            assert!(chunk.iter().all(|t| t.time < chunks.start.unwrap()));
+            replayed.extend(chunk);
+        }
+        assert_eq!(tags, replayed);
+    }
+
+    #[test]
+    fn replay_counts_are_deterministic() {
+        let patmasks: HashSet<(u16, Option<u32>)> = [
+            (chans_to_mask(&[1]), None),
+            (chans_to_mask(&[2]), None),
+            (chans_to_mask(&[1, 2]), Some(8)),
+        ]
+        .into_iter()
+        .collect();
+        let run = || {
+            let mut chunks = Chunks::new(source(&tags()), 1e-6);
+            let mut counts = Vec::new();
+            while !chunks.finished {
+                counts.push(count_patterns(&chunks.next_chunk(), patmasks.clone()));
+            }
+            counts
+        };
+        let counts = run();
+        assert_eq!(counts, run());
+        let total = |pat| counts.iter().map(|c| c[&pat]).sum::<u64>();
+        assert_eq!(10_000, total((chans_to_mask(&[1]), None)));
+        assert_eq!(3_334, total((chans_to_mask(&[2]), None)));
+        // Pairs split across a chunk boundary are lost, as in acquisition
+        assert!(total((chans_to_mask(&[1, 2]), Some(8))) <= 3_334);
+    }
+}
@@ -0,0 +1,399 @@
+//! Software time tagger that generates tags in real time, so the server and
+//! its clients can run without the vendor library or a device attached.
+//!
+//! Each channel has uncorrelated background events, and pairs of channels can
+//! have correlated events, e.g. from a photon pair source. All events arrive as
+//! Poisson processes: inter-arrival times are exponentially distributed. The
+//! second event in a pair is offset by a fixed delay plus Gaussian jitter.
+//! Input delays set through the API are applied on top of this, as on the
+//! hardware, while thresholds and inversion are accepted but have no effect.
+
+use anyhow::{bail, Result};
+use parking_lot::Mutex;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::time::Instant;
+use tagtools::{pat, Tag, COUNTER_STEP, TSTEP};
+
+use crate::device::{LogicDevice, TagDevice};
+
+/// Gaussian jitter is truncated at this many standard deviations, which
+/// bounds how far an event can move and so keeps the tag stream sorted
+const JITTER_CUTOFF: f64 = 5.0;
+
+/// Simulated sources of events, loaded from JSON with `--sim-config`
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct SimConfig {
+    /// Seed for the random number generator, for reproducible tag streams
+    pub seed: Option<u64>,
+    /// Uncorrelated events on single channels
+    #[serde(default)]
+    pub singles: Vec<SimSingles>,
+    /// Correlated events on pairs of channels
+    #[serde(default)]
+    pub pairs: Vec<SimPair>,
+}
+
+/// Background events on one channel
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct SimSingles {
+    pub channel: u8,
+    /// Mean event rate in Hz
+    pub rate: f64,
+}
+
+/// Correlated events between two channels
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct SimPair {
+    pub ch_a: u8,
+    pub ch_b: u8,
+    /// Mean pair rate in Hz
+    pub rate: f64,
+    /// Delay of `ch_b` relative to `ch_a`, in `tagtools::TSTEP`
+    #[serde(default)]
+    pub delay: i64,
+    /// Standard deviation of the delay, in `tagtools::TSTEP`
+    #[serde(default)]
+    pub jitter: f64,
+}
+
+/// Four channels of background with two correlated pairs
+impl Default for SimConfig {
+    fn default() -> Self {
+        SimConfig {
+            seed: None,
+            singles: (1..=4).map(|channel| SimSingles { channel, rate: 20e3 }).collect(),
+            pairs: vec![
+                SimPair { ch_a: 1, ch_b: 2, rate: 2e3, delay: 20, jitter: 2.0 },
+                SimPair { ch_a: 3, ch_b: 4, rate: 1e3, delay: -10, jitter: 2.0 },
+            ],
+        }
+    }
+}
+
+impl SimConfig {
+    /// Check channels are in 1..=16 and rates are nonnegative
+    pub fn validate(&self) -> Result<()> {
+        let channels = self
+            .singles
+            .iter()
+            .map(|s| s.channel)
+            .chain(self.pairs.iter().flat_map(|p| [p.ch_a, p.ch_b]));
+        for ch in channels {
+            if !(1..=16).contains(&ch) {
+                bail!("simulated channel {} is not in 1..=16", ch);
+            }
+        }
+        let rates = self
+            .singles
+            .iter()
+            .map(|s| s.rate)
+            .chain(self.pairs.iter().map(|p| p.rate));
+        for r in rates {
+            if !(r >= 0.0 && r.is_finite()) {
+                bail!("simulated rate {} is not a nonnegative number", r);
+            }
+        }
+        for p in &self.pairs {
+            if !(p.jitter >= 0.0 && p.jitter.is_finite()) {
+                bail!("simulated jitter {} is not a nonnegative number", p.jitter);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Generates time-sorted tags for successive intervals of time
+pub struct Generator {
+    config: SimConfig,
+    rng: StdRng,
+    /// Input delays set by the user, in `tagtools::TSTEP`
+    delays: [u32; 16],
+    /// End of the last generated interval
+    now: i64,
+    /// Generated tags not yet old enough to be emitted in order
+    pending: Vec<Tag>,
+}
+
+impl Generator {
+    pub fn new(config: SimConfig) -> Self {
+        let rng = match config.seed {
+            Some(s) => StdRng::seed_from_u64(s),
+            None => StdRng::from_entropy(),
+        };
+        Generator {
+            config,
+            rng,
+            delays: [0; 16],
+            now: 0,
+            pending: Vec::new(),
+        }
+    }
+
+    pub fn set_delay(&mut self, input: u8, delay: u32) {
+        if let Some(d) = self.delays.get_mut((input as usize).wrapping_sub(1)) {
+            *d = delay;
+        }
+    }
+
+    /// Largest shift of any event from the time it was generated
+    fn horizon(&self) -> i64 {
+        let pair_shift = self
+            .config
+            .pairs
+            .iter()
+            .map(|p| p.delay.abs() + (JITTER_CUTOFF * p.jitter).ceil() as i64)
+            .max()
+            .unwrap_or(0);
+        let input_delay = *self.delays.iter().max().unwrap_or(&0) as i64;
+        pair_shift + input_delay
+    }
+
+    /// Arrival times of a Poisson process with `rate` (Hz) in `[start, end)`
+    fn arrivals(rng: &mut StdRng, rate: f64, start: i64, end: i64) -> Vec<i64> {
+        let mut times = Vec::new();
+        if rate <= 0.0 {
+            return times;
+        }
+        // Mean inter-arrival time in TSTEP
+        let mean = 1.0 / rate / TSTEP;
+        let mut t = start as f64;
+        loop {
+            // Exponential distribution by inversion; 1 - u is in (0, 1]
+            let u: f64 = rng.gen();
+            t += -mean * (1.0 - u).ln();
+            if t >= end as f64 {
+                break;
+            }
+            times.push(t as i64);
+        }
+        times
+    }
+
+    /// Standard normal variate by the Box-Muller transform
+    fn gaussian(rng: &mut StdRng) -> f64 {
+        let u1: f64 = 1.0 - rng.gen::<f64>();
+        let u2: f64 = rng.gen();
+        (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos()
+    }
+
+    /// Advance by `seconds`, returning all tags that are now complete
+    pub fn advance(&mut self, seconds: f64) -> Vec<Tag> {
+        let start = self.now;
+        let end = start + (seconds / TSTEP) as i64;
+        self.now = end;
+
+        let delays = self.delays;
+        let delayed = |t: i64, ch: u8| Tag {
+            time: t + delays[ch as usize - 1] as i64,
+            channel: ch,
+        };
+        for s in &self.config.singles {
+            for t in Self::arrivals(&mut self.rng, s.rate, start, end) {
+                self.pending.push(delayed(t, s.channel));
+            }
+        }
+        for p in &self.config.pairs {
+            for t in Self::arrivals(&mut self.rng, p.rate, start, end) {
+                let jitter = Self::gaussian(&mut self.rng).clamp(-JITTER_CUTOFF, JITTER_CUTOFF);
+                let t_b = t + p.delay + (jitter * p.jitter).round() as i64;
+                self.pending.push(delayed(t, p.ch_a));
+                self.pending.push(delayed(t_b, p.ch_b));
+            }
+        }
+        self.pending.sort_unstable();
+
+        // Events generated in the next interval can be shifted back by up to
+        // the horizon, so anything later than that must wait until then
+        let until = end - self.horizon();
+        let split = self.pending.partition_point(|t| t.time < until);
+        let rest = self.pending.split_off(split);
+        std::mem::replace(&mut self.pending, rest)
+    }
+}
+
+/// Elapsed seconds since `last`, resetting it to now
+fn lap(last: &mut Instant) -> f64 {
+    let now = Instant::now();
+    let dt = now.duration_since(*last).as_secs_f64();
+    *last = now;
+    dt
+}
+
+struct SimState {
+    generator: Generator,
+    running: bool,
+    last_read: Instant,
+    last_freeze: Instant,
+}
+
+/// Simulated time tagger in tag mode
+pub struct Simulator {
+    config: SimConfig,
+    state: Mutex<SimState>,
+}
+
+impl Simulator {
+    pub fn new(config: SimConfig) -> Self {
+        let now = Instant::now();
+        Simulator {
+            config: config.clone(),
+            state: Mutex::new(SimState {
+                generator: Generator::new(config),
+                running: false,
+                last_read: now,
+                last_freeze: now,
+            }),
+        }
+    }
+}
+
+impl TagDevice for Simulator {
+    fn open(&self) -> Result<()> {
+        self.config.validate()
+    }
+    fn close(&self) {}
+    fn calibrate(&self) {}
+    fn get_fpga_version(&self) -> i32 {
+        0
+    }
+    fn get_resolution(&self) -> f64 {
+        TSTEP
+    }
+    fn read_error_flags(&self) -> u32 {
+        0
+    }
+    fn set_input_threshold(&self, _input: u8, _voltage: f64) {}
+    fn set_inversion_mask(&self, _mask: u16) {}
+    fn set_delay(&self, input: u8, delay: u32) {
+        self.state.lock().generator.set_delay(input, delay);
+    }
+    fn set_fg(&self, _period: u32, _high: u32) {}
+    fn start_timetags(&self) {
+        let mut s = self.state.lock();
+        s.running = true;
+        s.last_read = Instant::now();
+    }
+    fn stop_timetags(&self) {
+        self.state.lock().running = false;
+    }
+    fn freeze_single_counter(&self) -> u64 {
+        let dt = lap(&mut self.state.lock().last_freeze);
+        (dt / COUNTER_STEP) as u64
+    }
+    fn read_tags(&self) -> Vec<Tag> {
+        let mut s = self.state.lock();
+        if !s.running {
+            return Vec::new();
+        }
+        let dt = lap(&mut s.last_read);
+        s.generator.advance(dt)
+    }
+    fn logic_counter(&self) -> Box<dyn LogicDevice> {
+        let mut config = self.config.clone();
+        // Don't repeat the tag mode stream if seeded
+        config.seed = config.seed.map(|s| s.wrapping_add(1));
+        Box::new(SimLogic {
+            state: Mutex::new(SimLogicState {
+                generator: Generator::new(config),
+                window: 1,
+                last_read: Instant::now(),
+                duration: 0,
+                tags: Vec::new(),
+            }),
+        })
+    }
+}
+
+struct SimLogicState {
+    generator: Generator,
+    window: u32,
+    last_read: Instant,
+    /// Duration of the last interval in 5 ns steps
+    duration: u64,
+    /// Tags of the last interval, from which patterns are counted
+    tags: Vec<Tag>,
+}
+
+/// Simulated time tagger in logic mode. Patterns are counted from simulated
+/// tags with `tagtools::pat::coincidence_nfold` in the global window.
+pub struct SimLogic {
+    state: Mutex<SimLogicState>,
+}
+
+impl LogicDevice for SimLogic {
+    fn switch_logic_mode(&self) {
+        self.state.lock().last_read = Instant::now();
+    }
+    fn read_error_flags(&self) -> u32 {
+        0
+    }
+    fn set_window_width(&self, window: u32) {
+        self.state.lock().window = window.max(1);
+    }
+    fn set_input_threshold(&self, _input: u8, _voltage: f64) {}
+    fn set_inversion_mask(&self, _mask: u16) {}
+    fn set_delay(&self, input: u8, delay: u32) {
+        self.state.lock().generator.set_delay(input, delay);
+    }
+    fn set_fg(&self, _period: u32, _high: u32) {}
+    fn read_logic(&self) -> i64 {
+        let mut s = self.state.lock();
+        let dt = lap(&mut s.last_read);
+        s.duration = (dt / COUNTER_STEP) as u64;
+        s.tags = s.generator.advance(dt);
+        s.tags.len() as i64
+    }
+    fn calc_count_pos(&self, pos: u16) -> u32 {
+        let s = self.state.lock();
+        let delays = vec![0; pos.count_ones() as usize];
+        pat::coincidence_nfold(&s.tags, pos, s.window.into(), &delays) as u32
+    }
+    fn get_time_counter(&self) -> u64 {
+        self.state.lock().duration
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> SimConfig {
+        SimConfig {
+            seed: Some(1234),
+            singles: vec![
+                SimSingles { channel: 1, rate: 1e5 },
+                SimSingles { channel: 2, rate: 1e5 },
+            ],
+            pairs: vec![SimPair { ch_a: 1, ch_b: 2, rate: 1e4, delay: 30, jitter: 1.0 }],
+        }
+    }
+
+    #[test]
+    fn sorted_across_intervals() {
+        let mut generator = Generator::new(config());
+        generator.set_delay(1, 50);
+        let mut tags = Vec::new();
+        for _ in 0..100 {
+            tags.extend(generator.advance(1e-3));
+        }
+        assert!(tags.windows(2).all(|w| w[0].time <= w[1].time));
+    }
+
+    #[test]
+    fn rates_and_correlations() {
+        let mut generator = Generator::new(config());
+        let tags = generator.advance(1.0);
+        // Singles are background plus pairs: 110k expected, sqrt(110k) ~ 330
+        let singles_1 = pat::singles(&tags, 1) as f64;
+        assert!((singles_1 - 110e3).abs() < 2e3);
+        // Pair peak should be at the configured delay, within the jitter
+        let peak: u64 = (25..=35)
+            .map(|d| pat::coincidence(&tags, 1, 2, 1, d))
+            .sum();
+        assert!((peak as f64 - 1e4).abs() < 500.0);
+        // Accidentals at a different delay: 1e5 * 1e5 * TSTEP ~ 2 per bin
+        assert!(pat::coincidence(&tags, 1, 2, 1, -200) < 20);
+    }
+}
@@ -1,11 +1,13 @@
 use std::time::Duration;
 use crate::Event;
 
+/// Interval at which the controller reads out the time tagger
+pub const TICK: Duration = Duration::from_micros(10000);
+
 pub fn main(sender: flume::Sender<Event>) -> anyhow::Result<()> {
-    let dur = Duration::from_micros(10000);
     std::thread::spawn(move || {
         while let Ok(()) = sender.send(Event::Tick) {
-            std::thread::sleep(dur);
+            std::thread::sleep(TICK);
         }
     });
     Ok(())
@@ -1,6 +1,7 @@
 #[allow(unused_imports)]
 use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
 
+use itertools::Itertools;
 use tagtools::pat;
 
 mod common;
@@ -40,9 +41,32 @@ fn coincidences(c: &mut Criterion) {
     }
 }
 
+fn histograms(c: &mut Criterion) {
+    let tags = common::load_test_data();
+    let pairs: Vec<(u8, u8)> = (1..=4).tuple_combinations().collect();
+    let specs: Vec<pat::HistogramSpec> = pairs
+        .iter()
+        .map(|&(ch_a, ch_b)| pat::HistogramSpec { ch_a, ch_b, win: 1, min_delay: -64, max_delay: 64 })
+        .collect();
+    let mut group = c.benchmark_group("Histograms");
+    group.bench_function("per pair", |b| {
+        b.iter(|| {
+            for &(ch_a, ch_b) in &pairs {
+                pat::coincidence_histogram(&tags, black_box(ch_a), ch_b, 1, -64, 64);
+            }
+        })
+    });
+    group.bench_function("single pass", |b| {
+        b.iter(|| {
+            pat::coincidence_histograms(tags.iter().copied(), black_box(&specs));
+        })
+    });
+}
+
 criterion_group!(
     benches,
     singles,
     coincidences,
+    histograms,
 );
 criterion_main!(benches);
\ No newline at end of file
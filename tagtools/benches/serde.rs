@@ -1,7 +1,8 @@
 #[allow(unused_imports)]
 use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
 
-use tagtools::{de, ser};
+use tagtools::{de, delta, ser};
+use zstd::stream;
 
 mod common;
 
@@ -33,6 +34,19 @@ fn serialize_deserialize(c: &mut Criterion) {
                 });
             }
         );
+        group.bench_with_input(
+            BenchmarkId::new("delta", level),
+            &level,
+            |b, level| {
+                b.iter(|| {
+                    buf.clear();
+                    let mut zwtr = stream::write::Encoder::new(&mut buf, *level).unwrap();
+                    delta::write_block(&mut zwtr, black_box(&tags)).unwrap();
+                    zwtr.finish().unwrap();
+                    let _ = black_box(de::tags(&*buf));
+                });
+            }
+        );
     }
 }
 
@@ -0,0 +1,73 @@
+use tagtools::{de, pat};
+
+use anyhow::{bail, Result};
+use itertools::process_results;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufReader, stdout};
+
+#[derive(Debug, argh::FromArgs, Clone)]
+/// Dead time and afterpulsing probability of each channel from its inter-arrival times
+pub struct CliArgs {
+    /// tags file path
+    #[argh(positional)]
+    pub tags: String,
+    /// bin width
+    #[argh(option, default = "64")]
+    pub bin: i64,
+    /// maximum interval
+    #[argh(option, default = "640000")]
+    pub max: i64,
+    /// start of the Poisson tail of the intervals (default: half of max)
+    #[argh(option)]
+    pub tail: Option<i64>,
+    /// write the inter-arrival histogram of each channel instead
+    #[argh(switch)]
+    pub histogram: bool,
+}
+
+fn main() -> Result<()> {
+
+    let config: CliArgs = argh::from_env();
+
+    if config.bin <= 0 {
+        bail!("bin width must be positive");
+    }
+    let tail = config.tail.unwrap_or(config.max / 2);
+
+    let file = config.tags;
+    let rdr = BufReader::new(File::open(file)?);
+
+    let mut events: BTreeMap<u8, u64> = BTreeMap::new();
+    let histograms = process_results(de::tags_iter(rdr)?, |tags| {
+        let tags = tags.inspect(|t| *events.entry(t.channel).or_default() += 1);
+        pat::interarrival_histograms(tags, config.bin, config.max)
+    })?;
+
+    let stdout = stdout();
+    let stdout = stdout.lock();
+    let mut wtr = csv::WriterBuilder::new()
+                .has_headers(false)
+                .delimiter(b'\t')
+                .from_writer(stdout);
+
+    for (ch, histogram) in &histograms {
+        if config.histogram {
+            for (d, c) in histogram {
+                wtr.write_record(&[ch.to_string(), d.to_string(), c.to_string()])?;
+            }
+        } else if let Some(a) = pat::afterpulsing(histogram, events[ch], tail) {
+            wtr.write_record(&[
+                ch.to_string(),
+                events[ch].to_string(),
+                a.dead_time.to_string(),
+                a.probability.value.to_string(),
+                a.probability.err.to_string(),
+            ])?;
+        } else {
+            eprintln!("too few intervals on channel {} for an estimate", ch);
+        }
+    }
+    wtr.flush()?;
+    Ok(())
+}
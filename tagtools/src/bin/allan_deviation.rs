@@ -0,0 +1,91 @@
+use tagtools::{de, pat, TSTEP};
+
+use anyhow::{bail, Context, Result};
+use std::fs::File;
+use std::io::{BufReader, stdout};
+
+#[derive(Debug, argh::FromArgs, Clone)]
+/// Overlapping Allan deviation of singles and coincidence rates versus averaging time
+pub struct CliArgs {
+    /// tags file path
+    #[argh(positional)]
+    pub tags: String,
+    /// shortest averaging time (rate bin width) in seconds
+    #[argh(option, default = "0.01")]
+    pub bin: f64,
+    /// coincidence window size
+    #[argh(option, default = "1")]
+    pub win: i64,
+    /// channel to analyze singles rates of, may be repeated
+    #[argh(option)]
+    pub single: Vec<u8>,
+    /// pair of channels to analyze coincidence rates of, as a,b; may be repeated
+    #[argh(option)]
+    pub pair: Vec<pat::TracePattern>,
+    /// divide by the mean rate, for the fractional Allan deviation
+    #[argh(switch)]
+    pub relative: bool,
+}
+
+fn main() -> Result<()> {
+
+    let config: CliArgs = argh::from_env();
+
+    let patterns = pat::TracePattern::from_args(&config.single, &config.pair)?;
+
+    let file = config.tags;
+    let rdr = BufReader::new(File::open(&file).with_context(|| format!("could not open {}", file))?);
+
+    let mut trace = Vec::new();
+    let bin = pat::rate_trace_seconds(de::tags_iter(rdr)?, &patterns, config.win, config.bin, |_, counts| {
+        trace.push(counts);
+        Ok(())
+    })?;
+
+    // The first and last bins are only partly covered by the run
+    let tau0 = bin as f64 * TSTEP;
+    let counts: Vec<&Vec<u64>> = trace.iter().skip(1).collect();
+    let counts = &counts[..counts.len().saturating_sub(1)];
+    if counts.len() < 2 {
+        bail!("run spans too few bins of {} s for an Allan deviation", tau0);
+    }
+
+    let adevs: Vec<Vec<pat::AllanDeviation>> = (0..patterns.len())
+        .map(|i| {
+            let rates: Vec<f64> = counts.iter().map(|c| c[i] as f64 / tau0).collect();
+            let mut adev = pat::allan_deviation(&rates, tau0);
+            if config.relative {
+                let mean = rates.iter().sum::<f64>() / rates.len() as f64;
+                for a in adev.iter_mut() {
+                    a.adev /= mean;
+                    a.lo /= mean;
+                    a.hi /= mean;
+                }
+            }
+            adev
+        })
+        .collect();
+
+    let stdout = stdout();
+    let stdout = stdout.lock();
+    let mut wtr = csv::WriterBuilder::new()
+                .has_headers(false)
+                .delimiter(b'\t')
+                .from_writer(stdout);
+
+    // Header names each group of columns by its channels
+    let mut header = vec!["tau".to_string()];
+    for name in patterns.iter().map(|p| p.to_string()) {
+        header.extend([name.clone(), format!("{} lo", name), format!("{} hi", name)]);
+    }
+    wtr.write_record(&header)?;
+    for (j, a) in adevs[0].iter().enumerate() {
+        let mut record = vec![a.tau.to_string()];
+        for adev in &adevs {
+            record.extend([adev[j].adev.to_string(), adev[j].lo.to_string(), adev[j].hi.to_string()]);
+        }
+        wtr.write_record(&record)?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
@@ -1,8 +1,9 @@
-use tagtools::{de, pat};
+use tagtools::{de, pat, ser};
 
 use anyhow::{Result};
+use itertools::process_results;
 use std::fs::File;
-use std::io::{BufReader, stdout};
+use std::io::{BufReader, BufWriter, Write, stdout};
 
 #[derive(Debug, argh::FromArgs, Clone)]
 /// cli app args
@@ -25,6 +26,13 @@ pub struct CliArgs {
     /// minimum delay
     #[argh(option, default = "10")]
     pub max: i64,
+    /// drop tags within this time of the previous one on their channel
+    #[argh(option)]
+    pub dead_time: Option<i64>,
+    /// write the histogram to this NumPy .npz file, with arrays delay and
+    /// counts, instead of standard output
+    #[argh(option)]
+    pub npz: Option<String>,
 }
 
 fn main() -> Result<()> {
@@ -32,17 +40,30 @@ fn main() -> Result<()> {
     let config: CliArgs = argh::from_env();
 
     let file = config.tags;
-    let mut rdr = BufReader::new(File::open(file)?);
-    let tags = de::tags(&mut rdr)?;
-
-    let histogram = pat::coincidence_histogram(
-        &tags,
-        config.ch_a,
-        config.ch_b,
-        config.win,
-        config.min,
-        config.max,
-    );
+    let rdr = BufReader::new(File::open(file)?);
+
+    // Stream the tags through so that memory use doesn't grow with the file
+    let histogram = process_results(de::tags_iter(rdr)?, |tags| {
+        let tags: Box<dyn Iterator<Item = _>> = match config.dead_time {
+            Some(dead_time) => Box::new(pat::dead_time_filter(tags, dead_time)),
+            None => Box::new(tags),
+        };
+        pat::coincidence_histogram_iter(
+            tags,
+            config.ch_a,
+            config.ch_b,
+            config.win,
+            config.min,
+            config.max,
+        )
+    })?;
+
+    if let Some(path) = config.npz {
+        let mut wtr = BufWriter::new(File::create(path)?);
+        ser::npz_histogram(&mut wtr, &histogram)?;
+        wtr.flush()?;
+        return Ok(());
+    }
 
     let stdout = stdout();
     let stdout = stdout.lock();
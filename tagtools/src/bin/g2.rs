@@ -0,0 +1,99 @@
+use tagtools::{cfg, de, pat, COUNTER_STEP, TSTEP};
+
+use anyhow::{bail, Context, Result};
+use itertools::process_results;
+use std::fs::File;
+use std::io::{BufReader, stdout};
+
+#[derive(Debug, argh::FromArgs, Clone)]
+/// Second-order degree of coherence g2 between two channels, with standard errors
+pub struct CliArgs {
+    /// tags file path
+    #[argh(positional)]
+    pub tags: String,
+    /// window size
+    #[argh(option, default = "1")]
+    pub win: i64,
+    /// channel a
+    #[argh(option, default = "1")]
+    pub ch_a: u8,
+    /// channel b
+    #[argh(option, default = "2")]
+    pub ch_b: u8,
+    /// minimum delay
+    #[argh(option, default = "-10")]
+    pub min: i64,
+    /// maximum delay
+    #[argh(option, default = "10")]
+    pub max: i64,
+    /// acquisition duration in 5 ns steps (default: span of the tags)
+    #[argh(option)]
+    pub duration: Option<u64>,
+    /// run record to take the acquisition duration from
+    #[argh(option)]
+    pub run: Option<String>,
+}
+
+fn main() -> Result<()> {
+
+    let config: CliArgs = argh::from_env();
+
+    if config.win <= 0 {
+        bail!("window size must be positive");
+    }
+
+    let duration = cfg::Run::duration_of(config.duration, config.run.as_deref())
+        .context("invalid --duration or --run")?;
+
+    let file = config.tags;
+    let rdr = BufReader::new(File::open(file)?);
+
+    // Count singles and the span of the tags while streaming them through
+    let (mut singles_a, mut singles_b) = (0, 0);
+    let (mut first, mut last) = (None, 0);
+    let histogram = process_results(de::tags_iter(rdr)?, |tags| {
+        let tags = tags.inspect(|t| {
+            if t.channel == config.ch_a {
+                singles_a += 1;
+            }
+            if t.channel == config.ch_b {
+                singles_b += 1;
+            }
+            first.get_or_insert(t.time);
+            last = t.time;
+        });
+        pat::coincidence_histogram_iter(
+            tags,
+            config.ch_a,
+            config.ch_b,
+            config.win,
+            config.min,
+            config.max,
+        )
+    })?;
+
+    let total_time = match duration {
+        Some(d) => d as f64 * COUNTER_STEP / TSTEP,
+        None => first.map_or(0., |f| (last - f) as f64),
+    };
+    let g2 = pat::g2_from_histogram(&histogram, singles_a, singles_b, config.win, total_time);
+    if g2.is_empty() {
+        bail!(
+            "g2 is undefined: {} tags on channel {}, {} on channel {}, duration {} s",
+            singles_a, config.ch_a, singles_b, config.ch_b, total_time * TSTEP,
+        );
+    }
+
+    let stdout = stdout();
+    let stdout = stdout.lock();
+    let mut wtr = csv::WriterBuilder::new()
+                .has_headers(false)
+                .delimiter(b'\t')
+                .from_writer(stdout);
+
+    for (d, g) in g2 {
+        wtr.write_record(&[d.to_string(), g.value.to_string(), g.err.to_string()])?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
@@ -0,0 +1,107 @@
+use tagtools::{cfg, de, pat, COUNTER_STEP, TSTEP};
+
+use anyhow::{bail, Context, Result};
+use std::fs::File;
+use std::io::{BufReader, stdout};
+
+#[derive(Debug, argh::FromArgs, Clone)]
+/// Third-order degree of coherence g3 over the delays of channels b and c from channel a
+pub struct CliArgs {
+    /// tags file path
+    #[argh(positional)]
+    pub tags: String,
+    /// window size
+    #[argh(option, default = "1")]
+    pub win: i64,
+    /// channel a
+    #[argh(option, default = "1")]
+    pub ch_a: u8,
+    /// channel b
+    #[argh(option, default = "2")]
+    pub ch_b: u8,
+    /// channel c
+    #[argh(option, default = "3")]
+    pub ch_c: u8,
+    /// maximum delay either way
+    #[argh(option, default = "10")]
+    pub max: i64,
+    /// acquisition duration in 5 ns steps (default: span of the tags)
+    #[argh(option)]
+    pub duration: Option<u64>,
+    /// run record to take the acquisition duration from
+    #[argh(option)]
+    pub run: Option<String>,
+    /// write a matrix of g3 with rows of b delays and columns of c delays,
+    /// instead of one delay pair, g3, and its standard error per line
+    #[argh(switch)]
+    pub matrix: bool,
+}
+
+fn main() -> Result<()> {
+
+    let config: CliArgs = argh::from_env();
+
+    if config.win <= 0 {
+        bail!("window size must be positive");
+    }
+
+    let duration = cfg::Run::duration_of(config.duration, config.run.as_deref())
+        .context("invalid --duration or --run")?;
+
+    let file = config.tags;
+    let rdr = BufReader::new(File::open(file)?);
+    let tags = de::tags(rdr)?;
+
+    let total_time = match (duration, tags.first(), tags.last()) {
+        (Some(d), _, _) => d as f64 * COUNTER_STEP / TSTEP,
+        (None, Some(first), Some(last)) => (last.time - first.time) as f64,
+        _ => 0.,
+    };
+    let singles = [config.ch_a, config.ch_b, config.ch_c].map(|ch| pat::singles(&tags, ch));
+    let histogram = pat::coincidence_histogram_3(
+        &tags,
+        config.ch_a,
+        config.ch_b,
+        config.ch_c,
+        config.win,
+        config.max,
+    );
+    let g3 = pat::g3_from_histogram(&histogram, singles, config.win, total_time);
+    if g3.is_empty() {
+        bail!(
+            "g3 is undefined: {:?} tags on channels {:?}, duration {} s",
+            singles, [config.ch_a, config.ch_b, config.ch_c], total_time * TSTEP,
+        );
+    }
+
+    let stdout = stdout();
+    let stdout = stdout.lock();
+    let mut wtr = csv::WriterBuilder::new()
+                .has_headers(false)
+                .delimiter(b'\t')
+                .from_writer(stdout);
+
+    if config.matrix {
+        let max = config.max / config.win * config.win;
+        let delays: Vec<i64> = (-max..=max).step_by(config.win as usize).collect();
+        let mut header = vec![String::new()];
+        header.extend(delays.iter().map(|d| d.to_string()));
+        wtr.write_record(&header)?;
+        for &d_ab in &delays {
+            let mut row = vec![d_ab.to_string()];
+            row.extend(delays.iter().map(|&d_ac| g3[&(d_ab, d_ac)].value.to_string()));
+            wtr.write_record(&row)?;
+        }
+    } else {
+        for ((d_ab, d_ac), g) in g3 {
+            wtr.write_record(&[
+                d_ab.to_string(),
+                d_ac.to_string(),
+                g.value.to_string(),
+                g.err.to_string(),
+            ])?;
+        }
+    }
+    wtr.flush()?;
+    Ok(())
+}
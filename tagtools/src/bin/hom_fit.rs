@@ -0,0 +1,107 @@
+use tagtools::{cfg, fit};
+
+use anyhow::{bail, Result};
+use std::io::{stdout, Write};
+
+#[derive(Debug, argh::FromArgs, Clone)]
+/// Fit a dip or fringe to the coincidences of run records taken over a scan
+pub struct CliArgs {
+    /// run record paths, one per step of the scan in order
+    #[argh(positional)]
+    pub runs: Vec<String>,
+    /// channel a
+    #[argh(option, default = "1")]
+    pub ch_a: u8,
+    /// channel b
+    #[argh(option, default = "2")]
+    pub ch_b: u8,
+    /// coincidence window of the counts to fit, needed when runs recorded the
+    /// pair in several windows
+    #[argh(option)]
+    pub win: Option<u32>,
+    /// model to fit: gaussian, sinc or fringe
+    #[argh(option, default = "fit::Model::Gaussian")]
+    pub model: fit::Model,
+    /// comma-separated values of the scanned parameter, one per run
+    #[argh(option, from_str_fn(parse_values))]
+    pub values: Option<Vec<f64>>,
+    /// value of the scanned parameter at the first run, without --values
+    #[argh(option, default = "0.")]
+    pub start: f64,
+    /// step of the scanned parameter between runs, without --values
+    #[argh(option, default = "1.")]
+    pub step: f64,
+    /// fit the counts with accidentals subtracted
+    #[argh(switch)]
+    pub subtracted: bool,
+    /// fit rates, dividing counts by the duration of each run
+    #[argh(switch)]
+    pub per_second: bool,
+}
+
+fn parse_values(s: &str) -> Result<Vec<f64>, String> {
+    s.split(',')
+        .map(|v| v.trim().parse::<f64>().map_err(|e| format!("invalid value {:?}: {}", v, e)))
+        .collect()
+}
+
+fn main() -> Result<()> {
+
+    let config: CliArgs = argh::from_env();
+
+    let values = match config.values {
+        Some(values) if values.len() != config.runs.len() => {
+            bail!("{} values given for {} runs", values.len(), config.runs.len())
+        }
+        Some(values) => values,
+        None => (0..config.runs.len()).map(|i| config.start + i as f64 * config.step).collect(),
+    };
+
+    let runs = config
+        .runs
+        .iter()
+        .zip(values)
+        .map(|(path, x)| Ok((x, cfg::Run::load(path)?)))
+        .collect::<Result<Vec<_>>>()?;
+
+    let points = fit::scan_points(
+        &runs,
+        config.ch_a,
+        config.ch_b,
+        config.win,
+        config.subtracted,
+        config.per_second,
+    )?;
+    let fit = fit::fit(config.model, &points)?;
+
+    let stdout = stdout();
+    let mut stdout = stdout.lock();
+    // The fit as comments, then the points with their residuals
+    writeln!(stdout, "# model\t{:?}", fit.model)?;
+    for (name, e) in [
+        ("amplitude", fit.amplitude),
+        ("visibility", fit.visibility),
+        ("center", fit.center),
+        ("width", fit.width),
+    ] {
+        writeln!(stdout, "# {}\t{}\t{}", name, e.value, e.err)?;
+    }
+    writeln!(stdout, "# chi_squared\t{}\t{}", fit.chi_squared, fit.dof)?;
+
+    let mut wtr = csv::WriterBuilder::new()
+                .has_headers(false)
+                .delimiter(b'\t')
+                .from_writer(stdout);
+    for p in points {
+        let y = fit.eval(p.x);
+        wtr.write_record(&[
+            p.x.to_string(),
+            p.y.to_string(),
+            p.err.to_string(),
+            y.to_string(),
+            ((p.y - y) / p.err).to_string(),
+        ])?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
@@ -0,0 +1,71 @@
+use tagtools::{de, pat};
+
+use anyhow::{bail, Result};
+use itertools::process_results;
+use std::fs::File;
+use std::io::{BufReader, stdout};
+
+#[derive(Debug, argh::FromArgs, Clone)]
+/// Start-stop histogram of delays from a sync channel to detector channels
+pub struct CliArgs {
+    /// tags file path
+    #[argh(positional)]
+    pub tags: String,
+    /// sync (start) channel
+    #[argh(option, default = "1")]
+    pub sync: u8,
+    /// detector (stop) channel, may be repeated
+    #[argh(option)]
+    pub stop: Vec<u8>,
+    /// bin width
+    #[argh(option, default = "1")]
+    pub bin: i64,
+    /// maximum delay
+    #[argh(option, default = "640")]
+    pub max: i64,
+    /// count every stop within range, not only the first after each sync
+    #[argh(switch)]
+    pub multi_stop: bool,
+}
+
+fn main() -> Result<()> {
+
+    let config: CliArgs = argh::from_env();
+
+    if config.stop.is_empty() {
+        bail!("at least one --stop channel is required");
+    }
+    if let Some(ch) = config.stop.iter().find(|&&ch| !(1..=16).contains(&ch)) {
+        bail!("invalid stop channel {}", ch);
+    }
+    if config.bin <= 0 {
+        bail!("bin width must be positive");
+    }
+
+    let file = config.tags;
+    let rdr = BufReader::new(File::open(file)?);
+
+    let histogram = process_results(de::tags_iter(rdr)?, |tags| {
+        pat::lifetime_histogram_iter(
+            tags,
+            config.sync,
+            &config.stop,
+            config.bin,
+            config.max,
+            config.multi_stop,
+        )
+    })?;
+
+    let stdout = stdout();
+    let stdout = stdout.lock();
+    let mut wtr = csv::WriterBuilder::new()
+                .has_headers(false)
+                .delimiter(b'\t')
+                .from_writer(stdout);
+
+    for (d, c) in histogram {
+        wtr.write_record(&[d.to_string(), c.to_string()])?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
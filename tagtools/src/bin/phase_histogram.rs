@@ -0,0 +1,67 @@
+use tagtools::{de, pat};
+
+use anyhow::{bail, Result};
+use itertools::process_results;
+use std::fs::File;
+use std::io::{BufReader, stdout};
+
+#[derive(Debug, argh::FromArgs, Clone)]
+/// Histograms of the phase of each channel's tags within the pulses of a pulsed source
+pub struct CliArgs {
+    /// tags file path
+    #[argh(positional)]
+    pub tags: String,
+    /// fixed pulse period, in time units
+    #[argh(option)]
+    pub period: Option<f64>,
+    /// time of the start of a pulse, with a fixed period
+    #[argh(option, default = "0")]
+    pub offset: i64,
+    /// clock channel marking the start of pulses, instead of a fixed period
+    #[argh(option)]
+    pub clock: Option<u8>,
+    /// number of pulses per clock tag
+    #[argh(option, default = "1")]
+    pub divider: u32,
+    /// bin width
+    #[argh(option, default = "1")]
+    pub bin: i64,
+}
+
+fn main() -> Result<()> {
+
+    let config: CliArgs = argh::from_env();
+
+    let clock = match (config.period, config.clock) {
+        (Some(period), None) if period > 0. => pat::PulseClock::Fixed { period, offset: config.offset },
+        (Some(_), None) => bail!("pulse period must be positive"),
+        (None, Some(channel)) if config.divider > 0 => pat::PulseClock::Channel { channel, divider: config.divider },
+        (None, Some(_)) => bail!("divider must be positive"),
+        _ => bail!("give either --period or --clock"),
+    };
+    if config.bin <= 0 {
+        bail!("bin width must be positive");
+    }
+
+    let file = config.tags;
+    let rdr = BufReader::new(File::open(file)?);
+
+    let histograms = process_results(de::tags_iter(rdr)?, |tags| {
+        pat::phase_histograms(tags, clock, config.bin)
+    })?;
+
+    let stdout = stdout();
+    let stdout = stdout.lock();
+    let mut wtr = csv::WriterBuilder::new()
+                .has_headers(false)
+                .delimiter(b'\t')
+                .from_writer(stdout);
+
+    for (ch, histogram) in histograms {
+        for (phase, c) in histogram {
+            wtr.write_record(&[ch.to_string(), phase.to_string(), c.to_string()])?;
+        }
+    }
+    wtr.flush()?;
+    Ok(())
+}
@@ -0,0 +1,77 @@
+use argh::FromArgs;
+use anyhow::{Context, Result};
+use std::fs::File;
+use std::io::{stdout, BufWriter, Write};
+
+use tagtools::{ptu, ser};
+
+const GIT_VERSION: &str = git_version::git_version!();
+
+#[derive(Debug, FromArgs, Clone)]
+/// Convert T2 mode records of a PicoQuant .ptu file to the .tags.zst
+/// compressed binary format, with times in units of the time tagger's
+/// resolution and detector channels counted from 1. Note: on Windows
+/// -o must be specified as the encoded data is not valid UTF-8 and thus
+/// cannot be written to stdout (a Rust stdlib limitation)
+pub struct CliArgs {
+    /// print version information
+    #[argh(switch, short = 'v')]
+    pub version: bool,
+    /// file to write output to (writes to standard output by default)
+    #[argh(option, short = 'o')]
+    pub out: Option<String>,
+    /// channel to put sync events on (dropped by default)
+    #[argh(option)]
+    pub sync_channel: Option<u8>,
+    /// channel to put marker events on (dropped by default)
+    #[argh(option)]
+    pub marker_channel: Option<u8>,
+    /// path of the .ptu file to convert
+    #[argh(positional)]
+    pub input: Option<String>,
+}
+
+fn main() -> Result<()> {
+    let args: CliArgs = argh::from_env();
+    if args.version {
+        let stdout = stdout();
+        let mut stdout = stdout.lock();
+        writeln!(
+            stdout,
+            concat!(
+                env!("CARGO_BIN_NAME"),
+                " ",
+                "{}",
+            ),
+            GIT_VERSION,
+        )?;
+        return Ok(())
+    }
+
+    let path = args.input.context("no input file given")?;
+    let f = File::open(&path).with_context(|| format!("could not open {}", path))?;
+    let mut rdr = ptu::reader(f).with_context(|| format!("could not read PTU header of {}", path))?;
+    if let Some(ch) = args.sync_channel {
+        rdr = rdr.sync_channel(ch);
+    }
+    if let Some(ch) = args.marker_channel {
+        rdr = rdr.marker_channel(ch);
+    }
+
+    let stdout = stdout();
+    let wtr: Box<dyn Write> = match args.out {
+        None => {
+            Box::new(stdout.lock())
+        },
+        Some(p) => {
+            let f = File::create(p)?;
+            Box::new(BufWriter::new(f))
+        },
+    };
+    let mut wtr = ser::TagWriter::new(wtr, ser::BATCH_DEFAULT)?;
+    for tag in rdr {
+        wtr.write(&[tag?])?;
+    }
+    wtr.finish()?.flush()?;
+    Ok(())
+}
@@ -0,0 +1,56 @@
+use tagtools::{de, pat, TSTEP};
+
+use anyhow::{Context, Result};
+use std::fs::File;
+use std::io::{BufReader, stdout};
+
+#[derive(Debug, argh::FromArgs, Clone)]
+/// Singles and coincidence counts in consecutive time bins across a run
+pub struct CliArgs {
+    /// tags file path
+    #[argh(positional)]
+    pub tags: String,
+    /// time bin width in seconds
+    #[argh(option, default = "0.01")]
+    pub bin: f64,
+    /// coincidence window size
+    #[argh(option, default = "1")]
+    pub win: i64,
+    /// channel to count singles of, may be repeated
+    #[argh(option)]
+    pub single: Vec<u8>,
+    /// pair of channels to count coincidences of, as a,b; may be repeated
+    #[argh(option)]
+    pub pair: Vec<pat::TracePattern>,
+}
+
+fn main() -> Result<()> {
+
+    let config: CliArgs = argh::from_env();
+
+    let patterns = pat::TracePattern::from_args(&config.single, &config.pair)?;
+
+    let file = config.tags;
+    let rdr = BufReader::new(File::open(&file).with_context(|| format!("could not open {}", file))?);
+
+    let stdout = stdout();
+    let stdout = stdout.lock();
+    let mut wtr = csv::WriterBuilder::new()
+                .has_headers(false)
+                .delimiter(b'\t')
+                .from_writer(stdout);
+
+    // Header names each column by its channels
+    let mut header = vec!["time".to_string()];
+    header.extend(patterns.iter().map(|p| p.to_string()));
+    wtr.write_record(&header)?;
+    // Rows are written as each bin is completed
+    pat::rate_trace_seconds(de::tags_iter(rdr)?, &patterns, config.win, config.bin, |t, counts| {
+        let mut record = vec![(t as f64 * TSTEP).to_string()];
+        record.extend(counts.iter().map(|c| c.to_string()));
+        wtr.write_record(&record)?;
+        Ok(())
+    })?;
+    wtr.flush()?;
+    Ok(())
+}
@@ -1,10 +1,14 @@
 use argh::FromArgs;
 use anyhow::{bail, Result};
-use either::{Left, Right};
+use either::{Either, Left, Right};
 use std::fs::{self, File};
-use std::io::{stdin, stdout, BufReader, Write};
+use std::io::{stdin, stdout, BufReader, BufWriter, Read, Write};
 
-use tagtools::{ser, de};
+use tagtools::clock::ClockRecovery;
+use tagtools::filter::{Filter, Window};
+#[cfg(feature = "columnar")]
+use tagtools::ser::Columnar;
+use tagtools::{ser, de, Tag};
 
 const GIT_VERSION: &str = git_version::git_version!();
 
@@ -17,11 +21,99 @@ pub struct CliArgs {
     /// print version information
     #[argh(switch, short = 'v')]
     pub version: bool,
+    /// output format: tsv (default), arrow for an Arrow IPC file, parquet, or
+    /// npy for a NumPy structured array (held in memory until the end)
+    #[argh(option, default = "Format::Tsv", from_str_fn(parse_format))]
+    pub format: Format,
+    /// keep only tags on these comma-separated channels
+    #[argh(option, from_str_fn(parse_channels))]
+    pub channels: Option<Vec<u8>>,
+    /// keep only tags at or after this time
+    #[argh(option)]
+    pub from: Option<i64>,
+    /// keep only tags before this time
+    #[argh(option)]
+    pub to: Option<i64>,
+    /// keep only tags within a window after a trigger channel, given as
+    /// trigger,delay,width; may be repeated
+    #[argh(option, from_str_fn(parse_window))]
+    pub gate: Vec<Window>,
+    /// drop tags within a window after a trigger channel, given as
+    /// trigger,delay,width; may be repeated
+    #[argh(option, from_str_fn(parse_window))]
+    pub veto: Vec<Window>,
+    /// correct times onto the timebase of a reference clock on this channel
+    #[argh(option)]
+    pub clock: Option<u8>,
+    /// number of pulses of the reference clock per tag on its channel
+    #[argh(option, default = "1", from_str_fn(parse_divider))]
+    pub clock_divider: u32,
+    /// period of the reference clock in the corrected timebase, in time units
+    /// (default: the first period measured)
+    #[argh(option)]
+    pub clock_period: Option<f64>,
+    /// decode with this many threads, or one per core if 0; this helps with
+    /// indexed files, as saved by tagsave and tagview, which are split on
+    /// their zstd frames
+    #[argh(option, default = "1")]
+    pub threads: usize,
     /// with no input or when input is '-', read from standard input
     #[argh(positional)]
     pub input: Vec<String>,
 }
 
+/// Formats of the output
+#[derive(Debug, Clone, Copy)]
+pub enum Format {
+    Tsv,
+    #[cfg(feature = "columnar")]
+    Columnar(Columnar),
+    Npy,
+}
+
+fn parse_format(s: &str) -> Result<Format, String> {
+    match s {
+        "tsv" => Ok(Format::Tsv),
+        #[cfg(feature = "columnar")]
+        "arrow" | "parquet" => Ok(Format::Columnar(s.parse().unwrap())),
+        #[cfg(not(feature = "columnar"))]
+        "arrow" | "parquet" => Err(format!("tcat was built without the columnar feature for {}", s)),
+        "npy" => Ok(Format::Npy),
+        _ => Err(format!("unknown format {:?}, expected tsv, arrow, parquet, or npy", s)),
+    }
+}
+
+fn parse_channels(s: &str) -> Result<Vec<u8>, String> {
+    s.split(',')
+        .map(|c| c.trim().parse::<u8>().map_err(|e| format!("invalid channel {:?}: {}", c, e)))
+        .collect()
+}
+
+fn parse_window(s: &str) -> Result<Window, String> {
+    let fields: Vec<&str> = s.split(',').map(str::trim).collect();
+    let (trigger, delay, width) = match fields[..] {
+        [trigger, delay, width] => (trigger, delay, width),
+        _ => return Err(format!("expected trigger,delay,width, got {:?}", s)),
+    };
+    let window = Window {
+        trigger: trigger.parse().map_err(|e| format!("invalid trigger channel {:?}: {}", trigger, e))?,
+        delay: delay.parse().map_err(|e| format!("invalid delay {:?}: {}", delay, e))?,
+        width: width.parse().map_err(|e| format!("invalid width {:?}: {}", width, e))?,
+    };
+    if window.delay < 0 {
+        return Err(format!("delay must not be negative, got {}", window.delay));
+    }
+    Ok(window)
+}
+
+fn parse_divider(s: &str) -> Result<u32, String> {
+    match s.parse::<u32>() {
+        Ok(0) => Err("clock divider must be positive".to_string()),
+        Ok(divider) => Ok(divider),
+        Err(e) => Err(format!("invalid clock divider {:?}: {}", s, e)),
+    }
+}
+
 fn main() -> Result<()> {
     let args: CliArgs = argh::from_env();
     if args.version {
@@ -68,29 +160,110 @@ fn main() -> Result<()> {
         }
     }
 
-    let stdout = stdout();
-    let stdout = stdout.lock();
-    let mut wtr = csv::WriterBuilder::new()
-        .has_headers(false)
-        .delimiter(b'\t')
-        .from_writer(stdout);
+    let mut filter = Filter::new();
+    if let Some(channels) = &args.channels {
+        filter = filter.channels(channels);
+    }
+    if let Some(from) = args.from {
+        filter = filter.from(from);
+    }
+    if let Some(to) = args.to {
+        filter = filter.to(to);
+    }
+    for w in &args.gate {
+        filter = filter.gate(w.trigger, w.delay, w.width);
+    }
+    for w in &args.veto {
+        filter = filter.veto(w.trigger, w.delay, w.width);
+    }
+
+    let recovery = args.clock.map(|channel| {
+        let recovery = ClockRecovery::new(channel).divider(args.clock_divider);
+        match args.clock_period {
+            Some(period) => recovery.period(period),
+            None => recovery,
+        }
+    });
 
+    match args.format {
+        Format::Tsv => {
+            let stdout = stdout();
+            let stdout = stdout.lock();
+            let mut wtr = csv::WriterBuilder::new()
+                .has_headers(false)
+                .delimiter(b'\t')
+                .from_writer(stdout);
+            cat_all(inputs, &mut |tags| ser::tsv(&mut wtr, tags), &filter, recovery.as_ref(), args.threads)?;
+        }
+        #[cfg(feature = "columnar")]
+        Format::Columnar(format) => {
+            // Parquet needs a `Send` writer, which a locked stdout is not
+            let mut wtr = ser::ColumnWriter::new(BufWriter::new(stdout()), format, ser::BATCH_DEFAULT)?;
+            cat_all(inputs, &mut |tags| wtr.write(tags), &filter, recovery.as_ref(), args.threads)?;
+            wtr.finish()?.flush()?;
+        }
+        Format::Npy => {
+            // The header gives the number of tags, so they can't be streamed
+            let mut tags = Vec::new();
+            cat_all(inputs, &mut |t| { tags.extend_from_slice(t); Ok(()) }, &filter, recovery.as_ref(), args.threads)?;
+            let mut wtr = BufWriter::new(stdout().lock());
+            ser::npy(&mut wtr, ser::NpyArray::Tags(&tags))?;
+            wtr.flush()?;
+        }
+    }
+    Ok(())
+}
+
+/// Write the tags from each input in turn
+fn cat_all(
+    inputs: Vec<Either<(), String>>,
+    write: &mut impl FnMut(&[Tag]) -> Result<()>,
+    filter: &Filter,
+    recovery: Option<&ClockRecovery>,
+    threads: usize,
+) -> Result<()> {
     for i in inputs {
         match i {
             Left(()) => {
                 let stdin = stdin();
                 let stdin = stdin.lock();
                 let rdr = BufReader::new(stdin);
-                let tags = de::tags(rdr).expect("Cannot deserialize tags from file");
-                ser::tsv(&mut wtr, &tags)?;
+                cat(rdr, write, filter, recovery, threads)?;
             },
             Right(path) => {
                 let f = File::open(path)?;
                 let rdr = BufReader::new(f);
-                let tags = de::tags(rdr).expect("Cannot deserialize tags from file");
-                ser::tsv(&mut wtr, &tags)?;
+                cat(rdr, write, filter, recovery, threads)?;
             },
         }
     }
     Ok(())
 }
+
+/// Write the tags from one input, with times corrected and then filtered
+fn cat<R: Read>(
+    rdr: R,
+    write: &mut impl FnMut(&[Tag]) -> Result<()>,
+    filter: &Filter,
+    recovery: Option<&ClockRecovery>,
+    threads: usize,
+) -> Result<()> {
+    let mut recovering = recovery.map(ClockRecovery::start);
+    let mut filtering = filter.start();
+    let chunks = if threads == 1 {
+        Left(de::tag_chunks(rdr)?)
+    } else {
+        Right(de::tag_chunks_par(rdr, threads)?)
+    };
+    for tags in chunks {
+        let mut tags = tags.expect("Cannot deserialize tags from file");
+        if let Some(r) = &mut recovering {
+            tags = tags.into_iter().filter_map(|t| r.correct(t)).collect();
+        }
+        if !filter.is_empty() {
+            tags.retain(|t| filtering.keep(t));
+        }
+        write(&tags)?;
+    }
+    Ok(())
+}
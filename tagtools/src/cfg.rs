@@ -1,8 +1,11 @@
 //! Configuration tools: formats for declaring and recording data
 
+use anyhow::{bail, Context, Result};
 use chrono::{DateTime, offset::Utc};
 use serde::{Serialize, Deserialize};
-use std::path::PathBuf;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 
 /// Experiment run specification for both declaring and recording runs in text files.
@@ -136,6 +139,14 @@ pub enum Coincidence {
     /// as the actual window used, regardless of what was requested or
     /// whether the server implementation chooses to honor that request.
     ChannelsCounts((u8, u8, u32, u64)),
+    /// Coincidence counts to subscribe to, with a specified window as in
+    /// `ChannelsWin`, to be recorded with the accidental coincidences
+    /// expected from the singles rates of both channels subtracted.
+    ChannelsWinSubtracted((u8, u8, u32)),
+    /// Number of coincidence events counted during the run and the actual
+    /// window used, as in `ChannelsCounts`, followed by the number with the
+    /// accidentals estimated from the singles rates subtracted.
+    ChannelsCountsSubtracted((u8, u8, u32, u64, f64)),
 }
 
 
@@ -153,6 +164,59 @@ pub struct ChannelSettings {
     pub threshold:  Option<f64>,
 }
 
+impl Run {
+    /// Read a run file
+    pub fn load(path: impl AsRef<Path>) -> Result<Run> {
+        let path = path.as_ref();
+        let file = File::open(path).with_context(|| format!("could not open {}", path.display()))?;
+        serde_json::from_reader(BufReader::new(file))
+            .with_context(|| format!("could not parse run record {}", path.display()))
+    }
+
+    /// Acquisition duration in 5 ns steps, given either directly as `duration`
+    /// or by the run record at `run`, but not both
+    pub fn duration_of(duration: Option<u64>, run: Option<&str>) -> Result<Option<u64>> {
+        match (duration, run) {
+            (Some(_), Some(_)) => bail!("a duration and a run record were both given"),
+            (Some(d), None) => Ok(Some(d)),
+            (None, Some(path)) => match Run::load(path)?.duration {
+                Some(d) => Ok(Some(d)),
+                None => bail!("run record {} has no duration", path),
+            },
+            (None, None) => Ok(None),
+        }
+    }
+}
+
+/// Metadata saved at the start of a `.tags.zst` file, so that the tags remain
+/// meaningful when separated from their `.json` record. See `ser::metadata`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Metadata {
+    /// Version of the program saving the tags
+    pub version:            String,
+    /// Time at which saving started
+    pub timestamp:          DateTime<Utc>,
+    /// Duration of one time unit of the tags, in seconds
+    pub resolution:         f64,
+    /// The run as declared (or recorded so far) when saving started
+    pub run:                Run,
+    /// Input channel settings in effect when saving started, as far as known
+    pub channel_settings:   Vec<ChannelSettings>,
+}
+
+impl Metadata {
+    /// Metadata for tags saved from now on, in units of `tagtools::TSTEP`
+    pub fn new(version: &str, run: Run, channel_settings: Vec<ChannelSettings>) -> Self {
+        Metadata {
+            version:            String::from(version),
+            timestamp:          Utc::now(),
+            resolution:         crate::TSTEP,
+            run,
+            channel_settings,
+        }
+    }
+}
+
 fn emptyvec<T>() -> Vec<T> {
     Vec::new()
 }
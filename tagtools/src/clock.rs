@@ -0,0 +1,134 @@
+//! Software clock recovery from a reference clock channel
+//!
+//! The time tagger's internal clock drifts relative to an external reference,
+//! like the repetition rate of a pulsed laser, so delays between events far
+//! apart in time smear out. A `ClockRecovery` tracks the period of the tags on a
+//! reference channel with a second-order phase-locked loop, and rewrites the
+//! time of every tag onto a timebase where the reference has a constant period.
+
+use crate::Tag;
+use std::cmp;
+
+/// Settings for tracking a reference clock, built up with its methods
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct ClockRecovery {
+    channel: u8,
+    divider: u32,
+    period: Option<f64>,
+    gain: f64,
+}
+
+impl ClockRecovery {
+    /// Track the tags on `channel`, which mark every pulse of the reference
+    pub fn new(channel: u8) -> Self {
+        ClockRecovery { channel, divider: 1, period: None, gain: 0.1 }
+    }
+
+    /// Tags on the reference channel mark every `divider`th pulse
+    ///
+    /// # Panics
+    ///
+    /// If `divider` is 0
+    pub fn divider(mut self, divider: u32) -> Self {
+        assert!(divider > 0, "clock divider must be positive");
+        self.divider = divider;
+        self
+    }
+
+    /// The period of the reference in the corrected timebase, in time units.
+    /// By default, it is the first period measured.
+    pub fn period(mut self, period: f64) -> Self {
+        self.period = Some(period);
+        self
+    }
+
+    /// Fraction of the timing error of each clock tag that the loop corrects
+    /// at once, between 0 and 1. Lower gains average over more clock tags,
+    /// suppressing their jitter but following fast drift less closely.
+    pub fn gain(mut self, gain: f64) -> Self {
+        self.gain = gain;
+        self
+    }
+
+    /// Start correcting a stream of tags, one at a time
+    pub fn start(&self) -> Recovering {
+        Recovering { settings: *self, lock: None, period: None, nominal: self.period, last: i64::MIN }
+    }
+
+    /// Lazily correct the times of any time-sorted source of tags, dropping
+    /// those before the loop has measured a first period from two clock tags
+    pub fn apply(&self, tags: impl IntoIterator<Item = Tag>) -> impl Iterator<Item = Tag> {
+        let mut recovering = self.start();
+        tags.into_iter().filter_map(move |t| recovering.correct(t))
+    }
+}
+
+/// The state of a `ClockRecovery` partway through a stream of tags
+#[derive(Clone, Debug)]
+pub struct Recovering {
+    settings: ClockRecovery,
+    /// Locked time and pulse index of the latest clock tag, and the time of the
+    /// first one, from which corrected times count
+    lock: Option<(f64, i64, i64)>,
+    /// Tracked period of the reference in the tagger's timebase
+    period: Option<f64>,
+    /// Period of the reference in the corrected timebase
+    nominal: Option<f64>,
+    /// Latest corrected time, which later ones may not precede
+    last: i64,
+}
+
+impl Recovering {
+    /// Tracked period of the reference in the tagger's timebase, once measured
+    pub fn tracked_period(&self) -> Option<f64> {
+        self.period
+    }
+
+    /// Locked time of the latest clock tag in the tagger's timebase, and the
+    /// index of its pulse counted from the first clock tag, once a period is
+    /// measured
+    pub fn locked_pulse(&self) -> Option<(f64, i64)> {
+        self.period?;
+        self.lock.map(|(locked, pulse, _)| (locked, pulse))
+    }
+
+    /// Correct the time of the next tag of the stream, or `None` if it comes
+    /// before the first period is measured
+    pub fn correct(&mut self, t: Tag) -> Option<Tag> {
+        self.track(t);
+        let (locked, pulse, first) = self.lock?;
+        let (period, nominal) = (self.period?, self.nominal?);
+        let pulses = pulse as f64 + (t.time as f64 - locked) / period;
+        let time = cmp::max(first + (pulses * nominal).round() as i64, self.last);
+        self.last = time;
+        Some(Tag { time, channel: t.channel })
+    }
+
+    /// Update the loop with the next tag of the stream, without correcting it
+    pub fn track(&mut self, t: Tag) {
+        let divider = self.settings.divider as f64;
+        if t.channel == self.settings.channel {
+            let time = t.time as f64;
+            match (self.lock, self.period) {
+                (None, _) => self.lock = Some((time, 0, t.time)),
+                (Some((locked, pulse, first)), None) => {
+                    let period = (time - locked) / divider;
+                    self.period = Some(period);
+                    self.nominal.get_or_insert(period);
+                    self.lock = Some((time, pulse + self.settings.divider as i64, first));
+                }
+                (Some((locked, pulse, first)), Some(period)) => {
+                    // Whole clock intervals elapsed, bridging missing clock tags
+                    let n = ((time - locked) / (period * divider)).round().max(1.) * divider;
+                    let predicted = locked + n * period;
+                    let error = time - predicted;
+                    // Critically damped loop: the phase takes a fraction of the
+                    // error, and the period a smaller one spread over the interval
+                    let gain = self.settings.gain;
+                    self.period = Some(period + gain * gain / 4. * error / n);
+                    self.lock = Some((predicted + gain * error, pulse + n as i64, first));
+                }
+            }
+        }
+    }
+}
@@ -1,15 +1,32 @@
-//! Deserialization of time tag objects, supporting `.tags` and `.tsv`
+//! Deserialization of time tag objects, supporting `.tags` and `.tsv`, and
+//! Arrow IPC and Parquet files
 
-use tagger_capnp::tags_capnp::tags;
+use tagger_capnp::tags_capnp::{header, tags};
+use crate::cfg::{ChannelSettings, Metadata};
+use crate::delta;
+use crate::index::Index;
+use crate::ser::METADATA_MAGIC;
 use crate::{Bin, Tag};
-use anyhow::Result;
+use anyhow::{bail, Result};
+#[cfg(feature = "columnar")]
+use arrow_array::{Array, Int64Array, RecordBatch, UInt8Array};
+use itertools::Itertools;
 use capnp::{serialize, serialize_packed};
-use capnp::message::ReaderOptions;
-use std::io::{BufReader, Read};
+use capnp::message::{self, ReaderOptions};
+use capnp::serialize::OwnedSegments;
+#[cfg(feature = "columnar")]
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+#[cfg(feature = "columnar")]
+use parquet::file::reader::ChunkReader;
+use std::collections::VecDeque;
+use std::io::{BufReader, Chain, Cursor, ErrorKind, Read, Seek, SeekFrom};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
 use std::vec::Vec;
 use zstd::stream;
 
-/// Deserialize from .tags format: zstd-compressed Cap'n Proto tags
+/// Deserialize from .tags format: zstd-compressed Cap'n Proto tags (or
+/// `delta` blocks, detected as they are read)
 ///
 /// Like many compressors, `zstd`'s API is linear under concatenation, in that
 /// `unzstd(m1.z + m2.z) == unzstd(m1.z) + unzstd(m2.z) == m1 + m2`.
@@ -21,6 +38,515 @@ pub fn tags(rdr: impl Read) -> Result<Vec<Tag>> {
     Ok(tags)
 }
 
+/// Deserialize from .tags format one chunk at a time, in constant memory
+///
+/// Unlike `tags`, which collects the whole file, this holds only the current
+/// Cap'n Proto message in memory, and yields each of its `List(Tag)` chunks in
+/// turn. Files written by `tagsave` and `tagview` hold one message per read
+/// of the time tagger, so arbitrarily long runs can be processed this way.
+pub fn tag_chunks<R: Read>(rdr: R) -> Result<TagChunks<stream::read::Decoder<'static, BufReader<R>>>> {
+    let zrdr = stream::read::Decoder::new(rdr)?;
+    Ok(TagChunks::new(zrdr))
+}
+
+/// Deserialize from .tags format one tag at a time, in constant memory
+///
+/// See `tag_chunks`. An error ends the iteration after it is returned.
+pub fn tags_iter(rdr: impl Read) -> Result<impl Iterator<Item = Result<Tag>>> {
+    let chunks = tag_chunks(rdr)?;
+    Ok(chunks.flatten_ok())
+}
+
+/// Deserialize from .tags format one chunk at a time, decoding across
+/// `threads` threads (or one per core if 0)
+///
+/// The stream is split on its zstd frames, which worker threads decompress and
+/// decode while chunks are yielded in file order. Indexed files, as saved by
+/// `tagsave` and `tagview`, hold one frame per batch of tags, so up to about
+/// `2 * threads` batches are held in memory at once. Frames of over 4 MiB, like
+/// those of files written as a single frame (by `ser::tags` or
+/// `ser::TagWriter::new`), are instead decoded as they are read, on the calling
+/// thread as with `tag_chunks`. An error ends the iteration after it is
+/// returned.
+pub fn tag_chunks_par<R: Read>(rdr: R, threads: usize) -> Result<ParTagChunks<R>> {
+    let threads = match threads {
+        0 => std::thread::available_parallelism()?.get(),
+        n => n,
+    };
+    Ok(ParTagChunks::new(rdr, threads))
+}
+
+/// Deserialize from .tags format, decoding across `threads` threads (or one
+/// per core if 0); see `tag_chunks_par`
+pub fn tags_par(rdr: impl Read, threads: usize) -> Result<Vec<Tag>> {
+    let mut tags = Vec::new();
+    for chunk in tag_chunks_par(rdr, threads)? {
+        tags.extend(chunk?);
+    }
+    Ok(tags)
+}
+
+/// Deserialize from .tags format only the tags in `[t0, t1)`
+///
+/// Files written by `ser::TagWriter::indexed` end with an `index::Index`, so
+/// only the frames overlapping the range need to be decompressed. Unindexed
+/// files are streamed from the start, stopping at the end of the range.
+pub fn tags_range<R: Read + Seek>(mut rdr: R, t0: i64, t1: i64) -> Result<Vec<Tag>> {
+    let mut tags = Vec::new();
+    let in_range = |t: &Tag| t.time >= t0 && t.time < t1;
+    match Index::read(&mut rdr)? {
+        Some(index) => {
+            for frame in index.frames_in(t0, t1) {
+                rdr.seek(SeekFrom::Start(frame.offset))?;
+                for chunk in tag_chunks((&mut rdr).take(frame.size))? {
+                    tags.extend(chunk?.into_iter().filter(in_range));
+                }
+            }
+        }
+        None => {
+            rdr.seek(SeekFrom::Start(0))?;
+            for tag in tags_iter(rdr)? {
+                let tag = tag?;
+                if tag.time >= t1 {
+                    break;
+                }
+                if in_range(&tag) {
+                    tags.push(tag);
+                }
+            }
+        }
+    }
+    Ok(tags)
+}
+
+/// Deserialize the run metadata at the start of a .tags.zst file, if it has
+/// any (see `ser::metadata`). Only the metadata frame is read.
+pub fn metadata(mut rdr: impl Read) -> Result<Option<Metadata>> {
+    let mut frame_header = [0; 8];
+    match rdr.read_exact(&mut frame_header) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+    if u32::from_le_bytes(frame_header[..4].try_into()?) != METADATA_MAGIC {
+        return Ok(None);
+    }
+    let len = u32::from_le_bytes(frame_header[4..].try_into()?) as u64;
+    let mut payload = rdr.take(len);
+    let message_reader = serialize::read_message(&mut payload, ReaderOptions::new())?;
+    let hdr = message_reader.get_root::<header::Reader>()?;
+    let timestamp = chrono::DateTime::parse_from_rfc3339(hdr.get_timestamp()?)?;
+    let mut channel_settings = Vec::new();
+    for cs in hdr.get_channel_settings()?.iter() {
+        channel_settings.push(ChannelSettings {
+            channel: cs.get_channel(),
+            invert: cs.get_has_invert().then(|| cs.get_invert()),
+            delay: cs.get_has_delay().then(|| cs.get_delay()),
+            threshold: cs.get_has_threshold().then(|| cs.get_threshold()),
+        });
+    }
+    if payload.limit() > 0 {
+        bail!("metadata frame is longer than its message");
+    }
+    Ok(Some(Metadata {
+        version: hdr.get_version()?.to_string(),
+        timestamp: timestamp.with_timezone(&chrono::Utc),
+        resolution: hdr.get_resolution(),
+        run: serde_json::from_str(hdr.get_run()?)?,
+        channel_settings,
+    }))
+}
+
+/// Iterator over the `List(Tag)` chunks of uncompressed, unpacked Cap'n Proto
+/// tags messages, reading one message at a time
+pub struct TagChunks<R: Read> {
+    rdr: BufReader<R>,
+    rdr_opts: ReaderOptions,
+    /// Message currently being read, if any
+    message: Option<message::Reader<OwnedSegments>>,
+    /// Index of the next chunk in the current message
+    next: u32,
+    /// Set at the end of the stream or after an error
+    done: bool,
+}
+
+impl<R: Read> TagChunks<R> {
+    pub fn new(rdr: R) -> Self {
+        TagChunks {
+            rdr: BufReader::new(rdr),
+            // As in `tags_uncompressed`, a single message may be arbitrarily large
+            rdr_opts: ReaderOptions {
+                traversal_limit_in_words: None,
+                ..Default::default()
+            },
+            message: None,
+            next: 0,
+            done: false,
+        }
+    }
+
+    fn next_chunk(&mut self) -> Result<Option<Vec<Tag>>> {
+        loop {
+            if let Some(message_reader) = &self.message {
+                let lists = message_reader.get_root::<tags::Reader>()?.get_tags()?;
+                if self.next < lists.len() {
+                    let chunk = lists.get(self.next)?;
+                    self.next += 1;
+                    let tags = chunk
+                        .iter()
+                        .map(|tag| Tag { time: tag.get_time(), channel: tag.get_channel() as u8 })
+                        .collect();
+                    return Ok(Some(tags));
+                }
+            }
+            // Current message exhausted (or none yet), so read the next one,
+            // which may instead be a delta block
+            self.next = 0;
+            self.message = None;
+            let mut magic = [0u8; 4];
+            let n = read_up_to(&mut self.rdr, &mut magic)?;
+            if n == 0 {
+                return Ok(None);
+            }
+            if magic == delta::MAGIC {
+                return delta::read_block(&mut self.rdr).map(Some);
+            }
+            let rdr = (&magic[..n]).chain(&mut self.rdr);
+            self.message = serialize::try_read_message(rdr, self.rdr_opts)?;
+            if self.message.is_none() {
+                return Ok(None);
+            }
+        }
+    }
+}
+
+/// Fill as much of `buf` as the reader has left, returning the bytes read
+fn read_up_to(rdr: &mut impl Read, buf: &mut [u8]) -> std::io::Result<usize> {
+    let mut n = 0;
+    while n < buf.len() {
+        match rdr.read(&mut buf[n..]) {
+            Ok(0) => break,
+            Ok(k) => n += k,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(n)
+}
+
+impl<R: Read> Iterator for TagChunks<R> {
+    type Item = Result<Vec<Tag>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let chunk = self.next_chunk().transpose();
+        if !matches!(chunk, Some(Ok(_))) {
+            self.done = true;
+        }
+        chunk
+    }
+}
+
+/// All the chunks of one frame
+type FrameChunks = Result<Vec<Vec<Tag>>>;
+/// A frame for a worker, with where to send its chunks
+type Job = (Vec<u8>, mpsc::SyncSender<FrameChunks>);
+/// Chunks of a frame too large for a worker, decoded as it is read
+type LargeFrame<R> = TagChunks<stream::read::Decoder<'static, BufReader<Chain<Cursor<Vec<u8>>, FrameReader<BufReader<R>>>>>>;
+
+/// Compressed size of the largest frame handed whole to a worker. Larger
+/// frames, like those of files written as one frame, are decoded as they are
+/// read instead, so that memory use stays bounded.
+const FRAME_LIMIT: u64 = 1 << 22;
+
+/// Chunks of tags decoded from the frames of a file by a pool of threads,
+/// built by `tag_chunks_par`
+pub struct ParTagChunks<R: Read> {
+    /// The file, while reading frames for the workers
+    rdr: Option<BufReader<R>>,
+    /// The frame being decoded as it is read, which holds the file meanwhile
+    large: Option<LargeFrame<R>>,
+    jobs: Option<mpsc::SyncSender<Job>>,
+    workers: Vec<thread::JoinHandle<()>>,
+    /// Results of the frames sent to the workers, in file order
+    in_flight: VecDeque<mpsc::Receiver<FrameChunks>>,
+    /// Chunks of the current frame not yet yielded
+    pending: std::vec::IntoIter<Vec<Tag>>,
+    /// Set at the end of the stream
+    eof: bool,
+    /// Set after an error
+    done: bool,
+}
+
+impl<R: Read> ParTagChunks<R> {
+    fn new(rdr: R, threads: usize) -> Self {
+        let (jobs, rx) = mpsc::sync_channel::<Job>(threads);
+        let rx = Arc::new(Mutex::new(rx));
+        let workers = (0..threads)
+            .map(|_| {
+                let rx = Arc::clone(&rx);
+                thread::spawn(move || loop {
+                    let job = rx.lock().unwrap().recv();
+                    let (frame, result) = match job {
+                        Ok(job) => job,
+                        Err(_) => return,
+                    };
+                    // The reader may have stopped waiting for this result
+                    let _ = result.send(decode_frame(&frame));
+                })
+            })
+            .collect();
+        ParTagChunks {
+            rdr: Some(BufReader::new(rdr)),
+            large: None,
+            jobs: Some(jobs),
+            workers,
+            in_flight: VecDeque::new(),
+            pending: Vec::new().into_iter(),
+            eof: false,
+            done: false,
+        }
+    }
+
+    fn next_chunk(&mut self) -> Result<Option<Vec<Tag>>> {
+        loop {
+            if let Some(chunk) = self.pending.next() {
+                return Ok(Some(chunk));
+            }
+            // A large frame comes after the frames before it
+            if self.in_flight.is_empty() {
+                if let Some(large) = &mut self.large {
+                    if let Some(chunk) = large.next_chunk()? {
+                        return Ok(Some(chunk));
+                    }
+                    // The whole frame has been read, so the file is next
+                    let zrdr = self.large.take().unwrap().rdr.into_inner();
+                    let (_, frame) = zrdr.finish().into_inner().into_inner();
+                    self.rdr = Some(frame.rdr);
+                }
+            }
+            // Keep every worker busy, with one more frame each queued
+            let jobs = self.jobs.as_ref().unwrap();
+            while !self.eof && self.large.is_none() && self.in_flight.len() < 2 * self.workers.len() {
+                let rdr = self.rdr.as_mut().unwrap();
+                let (tx, rx) = mpsc::sync_channel(1);
+                match read_frame(rdr) {
+                    Ok(Some(Frame::Whole(frame))) => jobs.send((frame, tx))?,
+                    Ok(Some(Frame::Large(head, state))) => {
+                        let frame = FrameReader { rdr: self.rdr.take().unwrap(), state };
+                        let zrdr = stream::read::Decoder::new(Cursor::new(head).chain(frame))?;
+                        self.large = Some(TagChunks::new(zrdr));
+                        break;
+                    }
+                    Ok(None) => {
+                        self.eof = true;
+                        break;
+                    }
+                    // Return the error after the frames before it
+                    Err(e) => {
+                        tx.send(Err(e))?;
+                        self.eof = true;
+                    }
+                }
+                self.in_flight.push_back(rx);
+            }
+            match self.in_flight.pop_front() {
+                Some(rx) => self.pending = rx.recv()??.into_iter(),
+                None if self.large.is_some() => {}
+                None => return Ok(None),
+            }
+        }
+    }
+}
+
+impl<R: Read> Iterator for ParTagChunks<R> {
+    type Item = Result<Vec<Tag>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let chunk = self.next_chunk().transpose();
+        if !matches!(chunk, Some(Ok(_))) {
+            self.done = true;
+        }
+        chunk
+    }
+}
+
+impl<R: Read> Drop for ParTagChunks<R> {
+    fn drop(&mut self) {
+        // Workers finish their current frame and see that there are no more
+        self.jobs = None;
+        self.in_flight.clear();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// Decompress one zstd frame and decode all of its chunks
+fn decode_frame(frame: &[u8]) -> FrameChunks {
+    TagChunks::new(stream::read::Decoder::with_buffer(frame)?).collect()
+}
+
+/// Magic number starting a zstd frame
+const ZSTD_MAGIC: u32 = 0xFD2FB528;
+
+/// The next zstd frame of a file, as read by `read_frame`
+enum Frame {
+    Whole(Vec<u8>),
+    /// The first `FRAME_LIMIT` bytes of a frame, and where the rest starts
+    Large(Vec<u8>, FrameState),
+}
+
+/// Read the next zstd frame, without decompressing it, skipping any
+/// skippable frames (like those of `ser::metadata` and `index::Index`)
+fn read_frame(rdr: &mut impl Read) -> Result<Option<Frame>> {
+    let mut frame = match FrameReader::next(rdr)? {
+        Some(frame) => frame,
+        None => return Ok(None),
+    };
+    let mut buf = Vec::new();
+    (&mut frame).take(FRAME_LIMIT).read_to_end(&mut buf)?;
+    if frame.state.done {
+        Ok(Some(Frame::Whole(buf)))
+    } else {
+        Ok(Some(Frame::Large(buf, frame.state)))
+    }
+}
+
+/// Reader of the bytes of one zstd frame, ending with the frame
+///
+/// The compressed size of a frame isn't stored, so this walks the block
+/// headers of the frame, as laid out in RFC 8878.
+struct FrameReader<R: Read> {
+    rdr: R,
+    state: FrameState,
+}
+
+/// Where a `FrameReader` is in its frame
+struct FrameState {
+    /// Header bytes read from the file but not yet returned
+    head: Cursor<Vec<u8>>,
+    /// Bytes left of the current block
+    block: u64,
+    /// Set at the last block
+    last: bool,
+    checksum: bool,
+    /// Set once the whole frame has been read
+    done: bool,
+}
+
+impl<R: Read> FrameReader<R> {
+    /// Start reading the next frame, after any skippable frames
+    fn next(mut rdr: R) -> Result<Option<Self>> {
+        loop {
+            let mut magic = [0u8; 4];
+            match read_up_to(&mut rdr, &mut magic)? {
+                0 => return Ok(None),
+                4 => {}
+                _ => bail!("zstd frame ended early"),
+            }
+            let magic = u32::from_le_bytes(magic);
+            if magic & 0xFFFFFFF0 == 0x184D2A50 {
+                let mut size = [0u8; 4];
+                rdr.read_exact(&mut size)?;
+                let size = u32::from_le_bytes(size) as u64;
+                if std::io::copy(&mut (&mut rdr).take(size), &mut std::io::sink())? < size {
+                    bail!("zstd skippable frame ended early");
+                }
+                continue;
+            }
+            if magic != ZSTD_MAGIC {
+                bail!("not a zstd frame: magic {:#010x}", magic);
+            }
+
+            let mut head = magic.to_le_bytes().to_vec();
+            let mut descriptor = [0u8; 1];
+            rdr.read_exact(&mut descriptor)?;
+            let descriptor = descriptor[0];
+            head.push(descriptor);
+            let single_segment = descriptor & 0x20 != 0;
+            let checksum = descriptor & 0x04 != 0;
+            let dictionary_id = [0, 1, 2, 4][(descriptor & 0x03) as usize];
+            let content_size = match descriptor >> 6 {
+                0 => single_segment as usize,
+                1 => 2,
+                2 => 4,
+                _ => 8,
+            };
+            let window = !single_segment as usize;
+            read_onto(&mut rdr, &mut head, (window + dictionary_id + content_size) as u64)?;
+            let state = FrameState {
+                head: Cursor::new(head),
+                block: 0,
+                last: false,
+                checksum,
+                done: false,
+            };
+            return Ok(Some(FrameReader { rdr, state }));
+        }
+    }
+
+    /// Read the header of the next block, or the checksum after the last
+    fn next_block(&mut self) -> Result<()> {
+        let mut head = Vec::new();
+        if self.state.last {
+            if self.state.checksum {
+                read_onto(&mut self.rdr, &mut head, 4)?;
+            }
+            self.state.done = true;
+        } else {
+            read_onto(&mut self.rdr, &mut head, 3)?;
+            let header = u32::from_le_bytes([head[0], head[1], head[2], 0]);
+            self.state.last = header & 1 != 0;
+            self.state.block = match (header >> 1) & 0x03 {
+                // Run-length blocks hold one byte to repeat
+                1 => 1,
+                3 => bail!("reserved zstd block type"),
+                _ => (header >> 3) as u64,
+            };
+        }
+        self.state.head = Cursor::new(head);
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for FrameReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while !buf.is_empty() {
+            let n = self.state.head.read(buf)?;
+            if n > 0 {
+                return Ok(n);
+            }
+            if self.state.block > 0 {
+                let n = (&mut self.rdr).take(self.state.block).read(buf)?;
+                if n == 0 {
+                    return Err(std::io::Error::new(ErrorKind::UnexpectedEof, "zstd frame ended early"));
+                }
+                self.state.block -= n as u64;
+                return Ok(n);
+            }
+            if self.state.done {
+                break;
+            }
+            self.next_block().map_err(|e| std::io::Error::new(ErrorKind::InvalidData, e))?;
+        }
+        Ok(0)
+    }
+}
+
+/// Append exactly `n` more bytes of a zstd frame to `buf`
+fn read_onto(rdr: &mut impl Read, buf: &mut Vec<u8>, n: u64) -> Result<()> {
+    if rdr.take(n).read_to_end(buf)? < n as usize {
+        bail!("zstd frame ended early");
+    }
+    Ok(())
+}
+
 pub fn tags_bench(rdr: impl Read, pack: bool) -> Result<Vec<Tag>> {
     let mut zrdr = stream::read::Decoder::new(rdr)?;
     let tags;
@@ -32,30 +558,12 @@ pub fn tags_bench(rdr: impl Read, pack: bool) -> Result<Vec<Tag>> {
     Ok(tags)
 }
 
-/// Deserialize to uncompressed, unpacked Cap'n Proto tags
+/// Deserialize to uncompressed, unpacked Cap'n Proto tags, or `delta` blocks
 pub fn tags_uncompressed(rdr: &mut impl Read) -> Result<Vec<Tag>> {
-    let mut brdr = BufReader::new(rdr);
     let mut tags: Vec<Tag> = Vec::new();
-
-    // Traversal limit is 64 MiB by default as a simple DoS mitigation.
-    // To read in arbitrarily-large datasets, we need to disable this.
-    let rdr_opts = ReaderOptions{
-        traversal_limit_in_words: None,
-        ..Default::default()
-    };
-
-    while let Some(message_reader) =
-        serialize::try_read_message(&mut brdr, rdr_opts)?
-    {
-        let tags_reader = message_reader.get_root::<tags::Reader>()?;
-
-        for chunk in tags_reader.get_tags()?.iter() {
-            for tag in chunk?.iter() {
-                tags.push(Tag { time: tag.get_time(), channel: tag.get_channel() as u8 })
-            }
-        }
+    for chunk in TagChunks::new(rdr) {
+        tags.extend(chunk?);
     }
-
     Ok(tags)
 }
 
@@ -100,6 +608,69 @@ pub fn tsv(rdr: &mut csv::Reader<impl Read>) -> Result<Vec<Tag>> {
     Ok(tags)
 }
 
+/// Deserialize from an Arrow IPC file one record batch at a time
+///
+/// Files must have the non-null `time` (Int64) and `channel` (UInt8) columns
+/// of `ser::tag_schema`, as written by `ser::ColumnWriter`; other columns are
+/// ignored. An error ends the iteration after it is returned.
+#[cfg(feature = "columnar")]
+pub fn arrow_chunks<R: Read + Seek>(rdr: R) -> Result<impl Iterator<Item = Result<Vec<Tag>>>> {
+    let batches = arrow_ipc::reader::FileReader::try_new(rdr, None)?;
+    Ok(batch_chunks(batches))
+}
+
+/// Deserialize from a Parquet file one record batch at a time
+///
+/// See `arrow_chunks`. Batches hold up to `ser::BATCH_DEFAULT` tags, whatever
+/// the row groups of the file.
+#[cfg(feature = "columnar")]
+pub fn parquet_chunks<R: ChunkReader + 'static>(rdr: R) -> Result<impl Iterator<Item = Result<Vec<Tag>>>> {
+    let batches = ParquetRecordBatchReaderBuilder::try_new(rdr)?
+        .with_batch_size(crate::ser::BATCH_DEFAULT)
+        .build()?;
+    Ok(batch_chunks(batches))
+}
+
+#[cfg(feature = "columnar")]
+fn batch_chunks<E>(batches: impl Iterator<Item = Result<RecordBatch, E>>) -> impl Iterator<Item = Result<Vec<Tag>>>
+where E: std::error::Error + Send + Sync + 'static {
+    let mut done = false;
+    batches.map_while(move |batch| {
+        if done {
+            return None;
+        }
+        let tags = batch.map_err(anyhow::Error::from).and_then(|b| batch_tags(&b));
+        done = tags.is_err();
+        Some(tags)
+    })
+}
+
+/// Tags from the `time` and `channel` columns of a record batch
+#[cfg(feature = "columnar")]
+fn batch_tags(batch: &RecordBatch) -> Result<Vec<Tag>> {
+    fn column<'a, A: Array + 'static>(batch: &'a RecordBatch, name: &str, ty: &str) -> Result<&'a A> {
+        let column = match batch.column_by_name(name) {
+            Some(c) => c,
+            None => bail!("record batch has no {} column", name),
+        };
+        if column.null_count() > 0 {
+            bail!("{} column has nulls", name);
+        }
+        match column.as_any().downcast_ref::<A>() {
+            Some(a) => Ok(a),
+            None => bail!("{} column is {}, not {}", name, column.data_type(), ty),
+        }
+    }
+    let time = column::<Int64Array>(batch, "time", "Int64")?;
+    let channel = column::<UInt8Array>(batch, "channel", "UInt8")?;
+    Ok(time
+        .values()
+        .iter()
+        .zip(channel.values().iter())
+        .map(|(&time, &channel)| Tag { time, channel })
+        .collect())
+}
+
 /// Deserialize a tab-separated histogram file of (x,y) records.
 pub fn histogram_tsv<R, T, U>(rdr: &mut csv::Reader<R>,) -> anyhow::Result<Vec<Bin<T, U>>>
 where
@@ -0,0 +1,121 @@
+//! Delta-encoded blocks of tags, an alternative to Cap'n Proto `List(Tag)`
+//!
+//! Each `Tag` takes 16 bytes in a Cap'n Proto message before compression, most
+//! of them the high bytes of times that barely change from tag to tag. A delta
+//! block instead stores the difference of each time from the previous one as
+//! a varint, and the channels as a separate column of bytes, which both
+//! shrinks the data and compresses better. Blocks take the place of messages
+//! in the zstd stream of a `.tags.zst` file, and start with a magic number
+//! that can't begin a Cap'n Proto message (it would be a segment count of over
+//! a billion), so `de` tells them apart as it reads. All integers are
+//! little-endian:
+//!
+//! ```text
+//! header:   magic u32 = "TDLT", version u8 = 1, count u32, first time i64,
+//!           size of the times u32
+//! times:    per tag, the zigzag-encoded difference from the previous time
+//!           (the first time for the first tag) as an unsigned LEB128 varint
+//! channels: per tag, u8
+//! ```
+//!
+//! Each block is independent of the others, so blocks can be written one per
+//! zstd frame for `index`.
+
+use crate::Tag;
+use anyhow::{bail, Context, Result};
+use std::io::{Read, Write};
+
+/// Magic number starting a block, "TDLT" in little-endian order
+pub const MAGIC: [u8; 4] = *b"TDLT";
+/// Version of the block layout
+pub const VERSION: u8 = 1;
+
+/// Write one block of tags
+pub fn write_block(wtr: &mut impl Write, tags: &[Tag]) -> Result<()> {
+    if tags.len() > u32::MAX as usize {
+        bail!("too many tags for one delta block: {}", tags.len());
+    }
+    let first = tags.first().map_or(0, |t| t.time);
+    // Most differences fit in one or two bytes
+    let mut times = Vec::with_capacity(2 * tags.len());
+    let mut prev = first;
+    for t in tags {
+        let delta = t.time.wrapping_sub(prev);
+        prev = t.time;
+        let mut z = ((delta << 1) ^ (delta >> 63)) as u64;
+        while z >= 0x80 {
+            times.push(z as u8 | 0x80);
+            z >>= 7;
+        }
+        times.push(z as u8);
+    }
+    if times.len() > u32::MAX as usize {
+        bail!("delta block times too large: {} bytes", times.len());
+    }
+    let channels: Vec<u8> = tags.iter().map(|t| t.channel).collect();
+
+    let mut header = Vec::with_capacity(21);
+    header.extend_from_slice(&MAGIC);
+    header.push(VERSION);
+    header.extend_from_slice(&(tags.len() as u32).to_le_bytes());
+    header.extend_from_slice(&first.to_le_bytes());
+    header.extend_from_slice(&(times.len() as u32).to_le_bytes());
+    wtr.write_all(&header)?;
+    wtr.write_all(&times)?;
+    wtr.write_all(&channels)?;
+    Ok(())
+}
+
+/// Read one block of tags, whose magic number has already been read
+pub fn read_block(rdr: &mut impl Read) -> Result<Vec<Tag>> {
+    let mut header = [0u8; 17];
+    rdr.read_exact(&mut header).context("delta block header ended early")?;
+    if header[0] != VERSION {
+        bail!("unsupported delta block version {}", header[0]);
+    }
+    let count = u32::from_le_bytes(header[1..5].try_into()?) as usize;
+    let first = i64::from_le_bytes(header[5..13].try_into()?);
+    let size = u32::from_le_bytes(header[13..17].try_into()?) as u64;
+
+    // Read what is there rather than trust the sizes before allocating
+    let mut times = Vec::new();
+    rdr.take(size).read_to_end(&mut times)?;
+    if (times.len() as u64) < size {
+        bail!("delta block times ended early");
+    }
+    // Every varint takes at least one byte
+    if count > times.len() {
+        bail!("delta block of {} tags has only {} bytes of times", count, times.len());
+    }
+    let mut channels = vec![0u8; count];
+    rdr.read_exact(&mut channels).context("delta block channels ended early")?;
+
+    let mut tags = Vec::with_capacity(count);
+    let mut bytes = times.iter();
+    let mut time = first;
+    for channel in channels {
+        let mut z: u64 = 0;
+        let mut shift = 0;
+        loop {
+            let b = match bytes.next() {
+                Some(&b) => b,
+                None => bail!("delta block times ended early"),
+            };
+            if shift > 63 {
+                bail!("delta block time difference overflows");
+            }
+            z |= ((b & 0x7F) as u64) << shift;
+            shift += 7;
+            if b < 0x80 {
+                break;
+            }
+        }
+        let delta = (z >> 1) as i64 ^ -((z & 1) as i64);
+        time = time.wrapping_add(delta);
+        tags.push(Tag { time, channel });
+    }
+    if bytes.next().is_some() {
+        bail!("delta block has more times than tags");
+    }
+    Ok(tags)
+}
@@ -0,0 +1,176 @@
+//! Composable filters over time-sorted streams of tags
+//!
+//! A `Filter` is built up from conditions that a tag must all meet to be kept,
+//! and is applied lazily to any source of tags, so it can sit between a
+//! streaming reader like `de::tags_iter` and the `pat` functions that take
+//! iterators, or collect the kept tags for those that take slices:
+//!
+//! ```
+//! use tagtools::{filter::Filter, pat, Tag};
+//!
+//! let tags = [(0, 1), (10, 2), (500, 2), (1000, 1), (1004, 2)]
+//!     .map(|(time, channel)| Tag { time, channel });
+//! // Channel 2 within 100 time units after channel 1
+//! let filter = Filter::new().gate(1, 0, 100);
+//! let kept: Vec<Tag> = filter.apply(tags).collect();
+//! assert_eq!(pat::singles(&kept, 2), 2);
+//! ```
+//!
+//! Conditions are checked in a fixed order, whatever the order they were added
+//! in: the dead time first, as a detector would impose it, then gates and vetoes,
+//! and finally channels and the time range. Tags dropped by the dead time
+//! trigger no gate or veto, while any others can, even if they are dropped.
+
+use crate::Tag;
+use std::collections::{HashMap, VecDeque};
+
+/// A window of `width` starting `delay` after each tag on a `trigger` channel
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub struct Window {
+    pub trigger: u8,
+    pub delay: i64,
+    pub width: i64,
+}
+
+/// Conditions for keeping tags, built up with its methods
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub struct Filter {
+    channels: Option<Vec<u8>>,
+    from: Option<i64>,
+    to: Option<i64>,
+    gates: Vec<Window>,
+    vetoes: Vec<Window>,
+    dead_time: Option<i64>,
+}
+
+impl Filter {
+    /// A filter that keeps every tag
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Keep only tags on `channels`
+    pub fn channels(mut self, channels: &[u8]) -> Self {
+        self.channels = Some(channels.to_vec());
+        self
+    }
+
+    /// Keep only tags at or after `time`
+    pub fn from(mut self, time: i64) -> Self {
+        self.from = Some(time);
+        self
+    }
+
+    /// Keep only tags before `time`
+    pub fn to(mut self, time: i64) -> Self {
+        self.to = Some(time);
+        self
+    }
+
+    /// Keep only tags within `[delay, delay + width)` after a tag on `trigger`.
+    /// Tags on `trigger` itself are not gated. With several gates, a tag must
+    /// be within all of them.
+    ///
+    /// # Panics
+    ///
+    /// If `delay` is negative, since the filter only looks back in time
+    pub fn gate(mut self, trigger: u8, delay: i64, width: i64) -> Self {
+        assert!(delay >= 0, "gate delay must not be negative");
+        self.gates.push(Window { trigger, delay, width });
+        self
+    }
+
+    /// Drop tags within `[delay, delay + width)` after a tag on `trigger`. Tags
+    /// on `trigger` itself are not vetoed.
+    ///
+    /// # Panics
+    ///
+    /// If `delay` is negative, since the filter only looks back in time
+    pub fn veto(mut self, trigger: u8, delay: i64, width: i64) -> Self {
+        assert!(delay >= 0, "veto delay must not be negative");
+        self.vetoes.push(Window { trigger, delay, width });
+        self
+    }
+
+    /// Drop tags that follow the previous kept tag on their channel by less
+    /// than `dead_time`, as in `pat::dead_time_filter`
+    pub fn dead_time(mut self, dead_time: i64) -> Self {
+        self.dead_time = Some(dead_time);
+        self
+    }
+
+    /// Whether this filter keeps every tag
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Start filtering a stream of tags, checking them one at a time
+    pub fn start(&self) -> Filtering {
+        Filtering {
+            filter: self.clone(),
+            previous: HashMap::new(),
+            gate_triggers: vec![VecDeque::new(); self.gates.len()],
+            veto_triggers: vec![VecDeque::new(); self.vetoes.len()],
+        }
+    }
+
+    /// Lazily keep the tags of any time-sorted source that meet the conditions
+    pub fn apply(&self, tags: impl IntoIterator<Item = Tag>) -> impl Iterator<Item = Tag> {
+        let mut filtering = self.start();
+        tags.into_iter().filter(move |t| filtering.keep(t))
+    }
+}
+
+/// The state of a `Filter` partway through a stream of tags
+#[derive(Clone, Debug)]
+pub struct Filtering {
+    filter: Filter,
+    /// Time of the previous kept tag on each channel, for the dead time
+    previous: HashMap<u8, i64>,
+    /// Recent trigger times whose windows may still be open, for each window
+    gate_triggers: Vec<VecDeque<i64>>,
+    veto_triggers: Vec<VecDeque<i64>>,
+}
+
+impl Filtering {
+    /// Whether to keep the next tag of the stream
+    pub fn keep(&mut self, t: &Tag) -> bool {
+        let f = &self.filter;
+        if let Some(dead_time) = f.dead_time {
+            match self.previous.get(&t.channel) {
+                Some(&p) if t.time - p < dead_time => return false,
+                _ => {
+                    self.previous.insert(t.channel, t.time);
+                }
+            }
+        }
+
+        // Every window must be checked to record its triggers
+        let mut keep = true;
+        for (w, triggers) in f.gates.iter().zip(&mut self.gate_triggers) {
+            keep &= in_window(w, triggers, t) != Some(false);
+        }
+        for (w, triggers) in f.vetoes.iter().zip(&mut self.veto_triggers) {
+            keep &= in_window(w, triggers, t) != Some(true);
+        }
+
+        keep && f.channels.as_ref().is_none_or(|chs| chs.contains(&t.channel))
+            && f.from.is_none_or(|from| t.time >= from)
+            && f.to.is_none_or(|to| t.time < to)
+    }
+}
+
+/// Whether `t` is in the window after any trigger so far, or `None` if it is a
+/// trigger itself, in which case it is recorded
+fn in_window(w: &Window, triggers: &mut VecDeque<i64>, t: &Tag) -> Option<bool> {
+    // Triggers whose windows have closed will not open again
+    while triggers.front().is_some_and(|&tr| t.time >= tr + w.delay + w.width) {
+        triggers.pop_front();
+    }
+    if t.channel == w.trigger {
+        triggers.push_back(t.time);
+        return None;
+    }
+    // The earliest remaining trigger has the earliest opening window
+    Some(triggers.front().is_some_and(|&tr| t.time >= tr + w.delay))
+}
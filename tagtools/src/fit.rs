@@ -0,0 +1,333 @@
+//! Fits of two-photon interference dips and fringes to coincidences scanned
+//! over a parameter, like the delay of a Hong-Ou-Mandel interferometer
+//!
+//! The coincidences at each step of a scan are typically recorded by `tagsave`
+//! as one `cfg::Run` per step. `scan_points` collects the counts of one pair of
+//! channels from these records, and `fit` fits one of the `Model`s to them by
+//! weighted least squares, reporting the visibility and width of the dip or
+//! fringe with their uncertainties.
+
+use crate::cfg::{Coincidence, Run};
+use crate::pat::Estimate;
+use crate::COUNTER_STEP;
+use anyhow::{bail, Result};
+use itertools::Itertools;
+use std::f64::consts::PI;
+use std::str::FromStr;
+
+/// Shape of the coincidences over the scanned parameter `x`, with amplitude
+/// `a`, visibility `v`, center `x0` and width `w`
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum Model {
+    /// `a * (1 - v * exp(-(x - x0)^2 / (2 w^2)))`, a dip from photons with
+    /// Gaussian spectra
+    Gaussian,
+    /// `a * (1 - v * sinc((x - x0) / w))`, with `sinc(u) = sin(pi u) / (pi u)`,
+    /// a dip from photons with rectangular spectra
+    Sinc,
+    /// `a * (1 + v * cos(2 pi (x - x0) / w))`, a fringe of period `w`, with the
+    /// center fit as the maximum nearest the middle of the scan
+    Fringe,
+}
+
+impl Model {
+    /// Value of the model at `x` with parameters `[a, v, x0, w]`
+    pub fn eval(&self, p: &[f64; 4], x: f64) -> f64 {
+        let [a, v, x0, w] = *p;
+        let u = (x - x0) / w;
+        match self {
+            Model::Gaussian => a * (1. - v * (-u * u / 2.).exp()),
+            Model::Sinc => {
+                let sinc = if u == 0. { 1. } else { (PI * u).sin() / (PI * u) };
+                a * (1. - v * sinc)
+            }
+            Model::Fringe => a * (1. + v * (2. * PI * u).cos()),
+        }
+    }
+}
+
+impl FromStr for Model {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "gaussian" => Ok(Model::Gaussian),
+            "sinc" => Ok(Model::Sinc),
+            "fringe" => Ok(Model::Fringe),
+            _ => bail!("unknown model {:?}, expected gaussian, sinc or fringe", s),
+        }
+    }
+}
+
+/// Counts, or a rate, at one value of the scanned parameter
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Point {
+    pub x: f64,
+    pub y: f64,
+    /// Standard error of `y`
+    pub err: f64,
+}
+
+/// Collect the coincidences of `ch_a` and `ch_b` (in either order) from run
+/// records, each with the value of the scanned parameter it was taken at.
+///
+/// With `subtracted`, the counts with accidentals subtracted are taken from
+/// `Coincidence::ChannelsCountsSubtracted`, and otherwise the raw counts from
+/// either that or `Coincidence::ChannelsCounts`. Only counts in window `win`
+/// are taken, if given; otherwise a run must have counts of the pair in only
+/// one window. With `per_second`, counts are divided by the duration of each
+/// run, so runs of different lengths compare. The standard errors follow from
+/// Poisson statistics of the raw counts.
+pub fn scan_points(
+    runs: &[(f64, Run)],
+    ch_a: u8,
+    ch_b: u8,
+    win: Option<u32>,
+    subtracted: bool,
+    per_second: bool,
+) -> Result<Vec<Point>> {
+    let pair = |a: u8, b: u8, w: u32| {
+        ((a, b) == (ch_a, ch_b) || (b, a) == (ch_a, ch_b)) && (win.is_none() || win == Some(w))
+    };
+    runs.iter()
+        .enumerate()
+        .map(|(i, (x, run))| {
+            // Counts of the pair in each window, raw counts alongside
+            let counts: Vec<(u32, f64, f64)> = run.coincidences.iter().filter_map(|c| match *c {
+                Coincidence::ChannelsCounts((a, b, w, n)) if pair(a, b, w) && !subtracted => {
+                    Some((w, n as f64, n as f64))
+                }
+                Coincidence::ChannelsCountsSubtracted((a, b, w, n, s)) if pair(a, b, w) => {
+                    Some((w, if subtracted { s } else { n as f64 }, n as f64))
+                }
+                _ => None,
+            }).collect();
+            let (y, raw) = match counts.first() {
+                Some(&(w, y, raw)) => {
+                    if counts.iter().any(|c| c.0 != w) {
+                        let wins = counts.iter().map(|c| c.0.to_string()).unique().join(", ");
+                        bail!("run {} has counts of channels {} and {} in windows {}, so one must be chosen",
+                            i, ch_a, ch_b, wins);
+                    }
+                    (y, raw)
+                }
+                None => bail!("run {} has no recorded counts of channels {} and {}", i, ch_a, ch_b),
+            };
+            // Zero counts are as likely from a mean of one
+            let err = raw.max(1.).sqrt();
+            let scale = if per_second {
+                match run.duration {
+                    Some(d) if d > 0 => 1. / (d as f64 * COUNTER_STEP),
+                    _ => bail!("run {} has no duration", i),
+                }
+            } else {
+                1.
+            };
+            Ok(Point { x: *x, y: y * scale, err: err * scale })
+        })
+        .collect()
+}
+
+/// Result of fitting a `Model`, with standard errors scaled by the reduced
+/// chi-squared when it is above one
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Fit {
+    pub model: Model,
+    pub amplitude: Estimate,
+    pub visibility: Estimate,
+    pub center: Estimate,
+    pub width: Estimate,
+    pub chi_squared: f64,
+    /// Degrees of freedom: the number of points less the four parameters
+    pub dof: usize,
+}
+
+impl Fit {
+    /// Value of the fit at `x`
+    pub fn eval(&self, x: f64) -> f64 {
+        self.model.eval(&self.params(), x)
+    }
+
+    fn params(&self) -> [f64; 4] {
+        [self.amplitude.value, self.visibility.value, self.center.value, self.width.value]
+    }
+}
+
+/// Fit `model` to `points` by weighted least squares, with the
+/// Levenberg-Marquardt algorithm from starting values estimated from the points
+pub fn fit(model: Model, points: &[Point]) -> Result<Fit> {
+    if points.len() <= 4 {
+        bail!("need more than 4 points to fit, got {}", points.len());
+    }
+    if points.iter().any(|p| !p.err.is_finite() || p.err <= 0.) {
+        bail!("every point needs a positive error");
+    }
+
+    let chi_squared = |p: &[f64; 4]| -> f64 {
+        points.iter().map(|pt| ((pt.y - model.eval(p, pt.x)) / pt.err).powi(2)).sum()
+    };
+    let mut p = initial_params(model, points);
+    let mut chi2 = chi_squared(&p);
+    let mut lambda = 1e-3;
+    for _ in 0..1000 {
+        let (jtj, jtr) = normal_equations(model, points, &p);
+        let mut damped = jtj;
+        for (j, row) in damped.iter_mut().enumerate() {
+            row[j] += lambda * jtj[j][j].max(1e-12);
+        }
+        let step = match solve(damped, jtr) {
+            Some(step) => step,
+            None => {
+                lambda *= 10.;
+                continue;
+            }
+        };
+        let mut trial = p;
+        for (t, s) in trial.iter_mut().zip(step) {
+            *t += s;
+        }
+        let trial_chi2 = chi_squared(&trial);
+        if trial_chi2.is_finite() && trial_chi2 <= chi2 {
+            let converged = chi2 - trial_chi2 <= 1e-12 * chi2.max(1e-300);
+            p = trial;
+            chi2 = trial_chi2;
+            lambda = (lambda / 10.).max(1e-12);
+            if converged {
+                break;
+            }
+        } else {
+            lambda *= 10.;
+            if lambda > 1e12 {
+                break;
+            }
+        }
+    }
+    let dof = points.len() - 4;
+    let scale = (chi2 / dof as f64).max(1.);
+    let covariance = match invert(normal_equations(model, points, &p).0) {
+        Some(c) => c,
+        None => bail!("fit is degenerate, the parameters are not all determined by the points"),
+    };
+    let estimate = |j: usize| Estimate { value: p[j], err: (covariance[j][j] * scale).sqrt() };
+    let mut width = estimate(3);
+    width.value = width.value.abs();
+    let mut center = estimate(2);
+    if model == Model::Fringe {
+        // Any maximum of a fringe is a center, so take the one nearest the
+        // middle of the scan
+        let middle = points.iter().map(|pt| pt.x).sum::<f64>() / points.len() as f64;
+        center.value -= ((center.value - middle) / width.value).round() * width.value;
+    }
+    Ok(Fit {
+        model,
+        amplitude: estimate(0),
+        visibility: estimate(1),
+        center,
+        width,
+        chi_squared: chi2,
+        dof,
+    })
+}
+
+/// Starting values of `[a, v, x0, w]` from the shape of the points
+fn initial_params(model: Model, points: &[Point]) -> [f64; 4] {
+    let mut sorted = points.to_vec();
+    sorted.sort_by(|a, b| a.x.total_cmp(&b.x));
+    let span = sorted.last().unwrap().x - sorted[0].x;
+    let mean = points.iter().map(|p| p.y).sum::<f64>() / points.len() as f64;
+    let max = points.iter().max_by(|a, b| a.y.total_cmp(&b.y)).unwrap();
+    let min = points.iter().min_by(|a, b| a.y.total_cmp(&b.y)).unwrap();
+    match model {
+        Model::Gaussian | Model::Sinc => {
+            // The baseline from the outer fifth of the scan on each side
+            let n = (sorted.len() / 5).max(1);
+            let outer: Vec<f64> = sorted[..n].iter().chain(&sorted[sorted.len() - n..]).map(|p| p.y).collect();
+            let a = outer.iter().sum::<f64>() / outer.len() as f64;
+            let v = if a > 0. { 1. - min.y / a } else { 0.5 };
+            // Full width at half depth, around the deepest point
+            let half = a * (1. - v / 2.);
+            let below: Vec<f64> = sorted.iter().filter(|p| p.y < half).map(|p| p.x).collect();
+            let fwhm = match (below.first(), below.last()) {
+                (Some(first), Some(last)) if last > first => last - first,
+                _ => span / 10.,
+            };
+            let w = match model {
+                Model::Gaussian => fwhm / 2.355,
+                _ => fwhm / 1.207,
+            };
+            [a, v, min.x, w]
+        }
+        Model::Fringe => {
+            let v = if mean > 0. { (max.y - min.y) / (max.y + min.y) } else { 0.5 };
+            // Each period crosses the mean twice
+            let crossings = sorted.windows(2).filter(|w| (w[0].y - mean) * (w[1].y - mean) < 0.).count();
+            let w = if crossings > 0 { 2. * span / crossings as f64 } else { span };
+            [mean, v, max.x, w]
+        }
+    }
+}
+
+/// `J^T J` and `J^T r` of the weighted residuals, with the Jacobian from
+/// central differences
+fn normal_equations(model: Model, points: &[Point], p: &[f64; 4]) -> ([[f64; 4]; 4], [f64; 4]) {
+    let mut jtj = [[0.; 4]; 4];
+    let mut jtr = [0.; 4];
+    let steps: Vec<f64> = p.iter().map(|&v| 1e-6 * v.abs().max(1e-6)).collect();
+    for pt in points {
+        let mut row = [0.; 4];
+        for (j, &h) in steps.iter().enumerate() {
+            let (mut up, mut down) = (*p, *p);
+            up[j] += h;
+            down[j] -= h;
+            row[j] = (model.eval(&up, pt.x) - model.eval(&down, pt.x)) / (2. * h) / pt.err;
+        }
+        let r = (pt.y - model.eval(p, pt.x)) / pt.err;
+        for j in 0..4 {
+            jtr[j] += row[j] * r;
+            for k in 0..4 {
+                jtj[j][k] += row[j] * row[k];
+            }
+        }
+    }
+    (jtj, jtr)
+}
+
+/// Solve `m x = b` by Gaussian elimination with partial pivoting
+fn solve(mut m: [[f64; 4]; 4], mut b: [f64; 4]) -> Option<[f64; 4]> {
+    for col in 0..4 {
+        let pivot = (col..4).max_by(|&i, &j| m[i][col].abs().total_cmp(&m[j][col].abs()))?;
+        if !m[pivot][col].is_normal() {
+            return None;
+        }
+        m.swap(col, pivot);
+        b.swap(col, pivot);
+        let pivot_row = m[col];
+        for row in col + 1..4 {
+            let f = m[row][col] / pivot_row[col];
+            for (v, p) in m[row].iter_mut().zip(pivot_row).skip(col) {
+                *v -= f * p;
+            }
+            b[row] -= f * b[col];
+        }
+    }
+    let mut x = [0.; 4];
+    for row in (0..4).rev() {
+        let s: f64 = (row + 1..4).map(|k| m[row][k] * x[k]).sum();
+        x[row] = (b[row] - s) / m[row][row];
+    }
+    Some(x)
+}
+
+/// Invert a matrix one column at a time
+fn invert(m: [[f64; 4]; 4]) -> Option<[[f64; 4]; 4]> {
+    let mut inv = [[0.; 4]; 4];
+    for col in 0..4 {
+        let mut e = [0.; 4];
+        e[col] = 1.;
+        let x = solve(m, e)?;
+        for row in 0..4 {
+            inv[row][col] = x[row];
+        }
+    }
+    Some(inv)
+}
@@ -0,0 +1,164 @@
+//! Time index for seekable `.tags.zst` files
+//!
+//! An indexed file stores its tags in independent zstd frames, followed by one
+//! zstd skippable frame holding an `Index` of the data frames. Decoders skip
+//! skippable frames, so indexed files remain readable as plain `.tags.zst`,
+//! while readers that look for the index can decompress only the frames that
+//! hold a given time range. All integers are little-endian:
+//!
+//! ```text
+//! skippable frame header:  magic u32 = 0x184D2A5A, payload size u32
+//! per data frame:          offset u64, size u64, first time i64, last time i64,
+//!                          counts [u64; 16] (channels 1 to 16)
+//! footer:                  number of data frames u64, index magic u32 = "TIDX"
+//! ```
+//!
+//! The footer ends the file, so the index can be found by reading backwards.
+
+use crate::Tag;
+use anyhow::{bail, Result};
+use std::io::{Read, Seek, SeekFrom, Write};
+
+/// Magic number of the skippable frame holding the index
+const SKIPPABLE_MAGIC: u32 = 0x184D2A5A;
+/// Magic number ending an index, "TIDX" in little-endian order
+const INDEX_MAGIC: u32 = u32::from_le_bytes(*b"TIDX");
+/// Bytes in each frame entry
+const ENTRY_SIZE: usize = 4 * 8 + 16 * 8;
+/// Bytes in the footer
+const FOOTER_SIZE: usize = 8 + 4;
+/// Bytes in the skippable frame header
+const HEADER_SIZE: usize = 4 + 4;
+
+/// Location, time range and singles counts of one data frame
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub struct Frame {
+    /// Byte offset of the frame from the start of the file
+    pub offset: u64,
+    /// Compressed size of the frame in bytes
+    pub size: u64,
+    /// Time of the first tag in the frame
+    pub first: i64,
+    /// Time of the last tag in the frame
+    pub last: i64,
+    /// Number of tags on each of channels 1 to 16
+    pub counts: [u64; 16],
+}
+
+impl Frame {
+    /// Describe a frame of the given time-sorted tags
+    pub fn new(offset: u64, size: u64, tags: &[Tag]) -> Self {
+        let mut counts = [0; 16];
+        for t in tags {
+            if (1..=16).contains(&t.channel) {
+                counts[t.channel as usize - 1] += 1;
+            }
+        }
+        Frame {
+            offset,
+            size,
+            first: tags.first().map_or(0, |t| t.time),
+            last: tags.last().map_or(0, |t| t.time),
+            counts,
+        }
+    }
+}
+
+/// Index of the data frames in a file, in file (and time) order
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub struct Index {
+    pub frames: Vec<Frame>,
+}
+
+impl Index {
+    /// Frames that may hold tags in `[t0, t1)`
+    pub fn frames_in(&self, t0: i64, t1: i64) -> impl Iterator<Item = &Frame> {
+        let start = self.frames.partition_point(|f| f.last < t0);
+        self.frames[start..].iter().take_while(move |f| f.first < t1)
+    }
+
+    /// Total number of tags on each of channels 1 to 16
+    pub fn counts(&self) -> [u64; 16] {
+        let mut counts = [0; 16];
+        for f in &self.frames {
+            for (c, n) in counts.iter_mut().zip(f.counts) {
+                *c += n;
+            }
+        }
+        counts
+    }
+
+    /// Write the index as a skippable frame
+    pub fn write(&self, wtr: &mut impl Write) -> Result<()> {
+        let payload = self.frames.len() * ENTRY_SIZE + FOOTER_SIZE;
+        if payload > u32::MAX as usize {
+            bail!("too many frames to index: {}", self.frames.len());
+        }
+        let mut buf = Vec::with_capacity(HEADER_SIZE + payload);
+        buf.extend(SKIPPABLE_MAGIC.to_le_bytes());
+        buf.extend((payload as u32).to_le_bytes());
+        for f in &self.frames {
+            buf.extend(f.offset.to_le_bytes());
+            buf.extend(f.size.to_le_bytes());
+            buf.extend(f.first.to_le_bytes());
+            buf.extend(f.last.to_le_bytes());
+            for n in f.counts {
+                buf.extend(n.to_le_bytes());
+            }
+        }
+        buf.extend((self.frames.len() as u64).to_le_bytes());
+        buf.extend(INDEX_MAGIC.to_le_bytes());
+        wtr.write_all(&buf)?;
+        Ok(())
+    }
+
+    /// Read the index from the end of a file, if it has one. This leaves the
+    /// reader at an arbitrary position.
+    pub fn read(rdr: &mut (impl Read + Seek)) -> Result<Option<Self>> {
+        let len = rdr.seek(SeekFrom::End(0))?;
+        if len < (HEADER_SIZE + FOOTER_SIZE) as u64 {
+            return Ok(None);
+        }
+        rdr.seek(SeekFrom::End(-(FOOTER_SIZE as i64)))?;
+        let mut footer = [0; FOOTER_SIZE];
+        rdr.read_exact(&mut footer)?;
+        if u32::from_le_bytes(footer[8..].try_into()?) != INDEX_MAGIC {
+            return Ok(None);
+        }
+        let n = u64::from_le_bytes(footer[..8].try_into()?);
+        let payload = n
+            .checked_mul(ENTRY_SIZE as u64)
+            .and_then(|p| p.checked_add(FOOTER_SIZE as u64))
+            .filter(|&p| p + HEADER_SIZE as u64 <= len);
+        let payload = match payload {
+            Some(p) => p,
+            None => bail!("corrupt index: {} frames do not fit in the file", n),
+        };
+        rdr.seek(SeekFrom::Start(len - payload - HEADER_SIZE as u64))?;
+        let mut buf = vec![0; HEADER_SIZE + payload as usize - FOOTER_SIZE];
+        rdr.read_exact(&mut buf)?;
+        let word = |i: usize| -> [u8; 8] { buf[i..i + 8].try_into().unwrap() };
+        if u32::from_le_bytes(buf[..4].try_into()?) != SKIPPABLE_MAGIC
+            || u32::from_le_bytes(buf[4..8].try_into()?) as u64 != payload
+        {
+            bail!("corrupt index: bad frame header");
+        }
+        let frames = (0..n as usize)
+            .map(|i| {
+                let e = HEADER_SIZE + i * ENTRY_SIZE;
+                let mut counts = [0; 16];
+                for (c, count) in counts.iter_mut().enumerate() {
+                    *count = u64::from_le_bytes(word(e + 32 + 8 * c));
+                }
+                Frame {
+                    offset: u64::from_le_bytes(word(e)),
+                    size: u64::from_le_bytes(word(e + 8)),
+                    first: i64::from_le_bytes(word(e + 16)),
+                    last: i64::from_le_bytes(word(e + 24)),
+                    counts,
+                }
+            })
+            .collect();
+        Ok(Some(Index { frames }))
+    }
+}
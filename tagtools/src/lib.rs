@@ -1,7 +1,13 @@
 pub mod bit;
 pub mod cfg;
+pub mod clock;
 pub mod de;
+pub mod delta;
+pub mod filter;
+pub mod fit;
+pub mod index;
 pub mod pat;
+pub mod ptu;
 pub mod ser;
 
 /// The basic representation of a tagged event
@@ -25,6 +31,9 @@ where
 }
 
 pub const TSTEP: f64 = 156.25e-12;
+/// Duration of one step of the time tagger's internal counters, in seconds, the
+/// unit of acquisition durations such as `cfg::Run::duration`
+pub const COUNTER_STEP: f64 = 5e-9;
 pub const CHAN16: [u8; 16] = [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16];
 pub const THRESHOLD_MIN: f64 = -4.0;
 pub const THRESHOLD_MAX: f64 = 4.0;
\ No newline at end of file
@@ -1,10 +1,13 @@
 //! Tools for analyzing patterns in time tag datasets
 
-use crate::Tag;
+use crate::{bit, bit::BitOps, clock, Tag, COUNTER_STEP, TSTEP};
+use anyhow::Context;
 use itertools::Itertools;
 use std::cmp;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap};
 use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
 
 /// Count number of events in a given channel.
 pub fn singles(tags: &[Tag], ch: u8) -> u64 {
@@ -57,12 +60,26 @@ pub fn coincidence_histogram(
     win: i64,
     min_delay: i64,
     max_delay: i64,
+) -> BTreeMap<i64, u64> {
+    coincidence_histogram_iter(tags.iter().copied(), ch_a, ch_b, win, min_delay, max_delay)
+}
+
+/// Calculate the raw coincidence histogram as in `coincidence_histogram`, from
+/// any time-sorted source of tags. Only the tags within the delay horizon are
+/// held in memory, so this can consume a streaming reader like `de::tags_iter`.
+pub fn coincidence_histogram_iter(
+    tags: impl IntoIterator<Item = Tag>,
+    ch_a: u8,
+    ch_b: u8,
+    win: i64,
+    min_delay: i64,
+    max_delay: i64,
 ) -> BTreeMap<i64, u64> {
     // Peekable iterator over the tags, binned by win via integer division
     // We don't multiply again by win to restore the original scale of the
     // time units; this is done later when required.
     let mut tag_iter = tags
-        .iter()
+        .into_iter()
         .map(|t| Tag { time: t.time / win, channel: t.channel })
         .peekable();
 
@@ -160,11 +177,108 @@ pub fn coincidence_histogram(
     return histogram;
 }
 
+/// Channels, window and delay range of one histogram for `coincidence_histograms`
+#[derive(Clone, Copy, Eq, PartialEq, Hash, Debug)]
+pub struct HistogramSpec {
+    pub ch_a: u8,
+    pub ch_b: u8,
+    pub win: i64,
+    pub min_delay: i64,
+    pub max_delay: i64,
+}
+
+/// Calculate several raw coincidence histograms, each as in
+/// `coincidence_histogram`, in one pass over any time-sorted source of tags.
+/// Histograms are returned in the order of `specs`.
+///
+/// A deque holds the tags within the longest delay horizon of any histogram.
+/// Each new tag is paired with every earlier tag in the deque, and the pair is
+/// counted in each histogram it belongs to, found by a lookup on its two
+/// channels: at a positive delay if the earlier tag is from `ch_a`, otherwise
+/// at a negative delay if it is from `ch_b`. The time performance is therefore
+/// `O(n * m)` for `n` tags and `m` tags within the horizon, plus the number of
+/// coincidences counted, independent of the number of histograms.
+pub fn coincidence_histograms(
+    tags: impl IntoIterator<Item = Tag>,
+    specs: &[HistogramSpec],
+) -> Vec<BTreeMap<i64, u64>> {
+    // Delay ranges in units of each window, as in coincidence_histogram
+    let ranges: Vec<(i64, i64)> = specs
+        .iter()
+        .map(|s| (s.min_delay / s.win, s.max_delay / s.win))
+        .collect();
+    let mut counts: Vec<Vec<u64>> = ranges
+        .iter()
+        .map(|&(min_win, max_win)| vec![0; (max_win - min_win + 1).max(0) as usize])
+        .collect();
+
+    // Histograms to count a pair in, by (earlier, later) channel, and whether
+    // the earlier tag is ch_a (positive delay) or ch_b (negative delay)
+    let mut by_channels: HashMap<(u8, u8), Vec<(usize, bool)>> = HashMap::new();
+    for (i, (s, &(min_win, _))) in specs.iter().zip(&ranges).enumerate() {
+        by_channels.entry((s.ch_a, s.ch_b)).or_default().push((i, true));
+        if s.ch_a != s.ch_b && !min_win.is_positive() {
+            by_channels.entry((s.ch_b, s.ch_a)).or_default().push((i, false));
+        }
+    }
+
+    // Longest horizon in time units, beyond which no tag can pair with a
+    // later one in any histogram
+    let horizon = specs
+        .iter()
+        .zip(&ranges)
+        .map(|(s, &(min_win, max_win))| (cmp::max(min_win.abs(), max_win) + 1) * s.win)
+        .max()
+        .unwrap_or(0);
+
+    let mut buffer: VecDeque<Tag> = VecDeque::new();
+    for t in tags {
+        while let Some(e) = buffer.front() {
+            if t.time - e.time < horizon {
+                break;
+            }
+            buffer.pop_front();
+        }
+        for e in &buffer {
+            if let Some(hists) = by_channels.get(&(e.channel, t.channel)) {
+                for &(i, positive) in hists {
+                    let win = specs[i].win;
+                    let (min_win, max_win) = ranges[i];
+                    let delay = if positive {
+                        t.time / win - e.time / win
+                    } else {
+                        e.time / win - t.time / win
+                    };
+                    if min_win <= delay && delay <= max_win {
+                        counts[i][(delay - min_win) as usize] += 1;
+                    }
+                }
+            }
+        }
+        buffer.push_back(t);
+    }
+
+    counts
+        .into_iter()
+        .zip(specs.iter().zip(&ranges))
+        .map(|(c, (s, &(min_win, _)))| {
+            c.into_iter()
+                .enumerate()
+                .map(|(j, n)| ((min_win + j as i64) * s.win, n))
+                .collect()
+        })
+        .collect()
+}
+
 /// Calculate the second-order degree of coherence, or g^(2) function, of light
 /// from photon correlations, as in an intensity interferometer or
 /// Hanbury Brown-Twiss experiment. Window, channels, and delay range specified
 /// as in coincidence_histogram, as this is essentially a normalization of that
 /// histogram to the singles rates and window size.
+///
+/// The total time is taken as the span from the first to the last tag; use
+/// `g2_duration` to normalize to the acquisition duration instead, with
+/// uncertainties. The result is empty if either channel has no tags.
 pub fn g2(
     tags: &[Tag],
     ch_a: u8,
@@ -173,20 +287,275 @@ pub fn g2(
     min_delay: i64,
     max_delay: i64,
 ) -> BTreeMap<i64, f64> {
-    let total_time = (tags.last().unwrap().time - tags.first().unwrap().time) as f64;
-    let singles_a = singles(tags, ch_a) as f64;
-    let singles_b = singles(tags, ch_b) as f64;
-    let g2_histogram = coincidence_histogram(tags, ch_a, ch_b, win, min_delay, max_delay)
+    let total_time = match (tags.first(), tags.last()) {
+        (Some(first), Some(last)) => (last.time - first.time) as f64,
+        _ => 0.,
+    };
+    g2_from_histogram(
+        &coincidence_histogram(tags, ch_a, ch_b, win, min_delay, max_delay),
+        singles(tags, ch_a),
+        singles(tags, ch_b),
+        win,
+        total_time,
+    )
+    .into_iter()
+    .map(|(d, g)| (d, g.value))
+    .collect()
+}
+
+/// Calculate g^(2) as in `g2`, normalized to the acquisition `duration` in
+/// steps of `COUNTER_STEP` (as recorded in `cfg::Run::duration`), with the
+/// Poisson standard error of each bin. The result is empty if either channel
+/// has no tags or the duration is zero.
+pub fn g2_duration(
+    tags: &[Tag],
+    ch_a: u8,
+    ch_b: u8,
+    win: i64,
+    min_delay: i64,
+    max_delay: i64,
+    duration: u64,
+) -> BTreeMap<i64, Estimate> {
+    g2_from_histogram(
+        &coincidence_histogram(tags, ch_a, ch_b, win, min_delay, max_delay),
+        singles(tags, ch_a),
+        singles(tags, ch_b),
+        win,
+        duration as f64 * COUNTER_STEP / TSTEP,
+    )
+}
+
+/// Normalize a coincidence histogram with bins of `win` to g^(2), given the
+/// singles counts of both channels over `total_time` in time units.
+///
+/// The standard error of each bin follows from Poisson statistics of its
+/// coincidence counts; the singles counts are typically much larger and their
+/// uncertainty is neglected. The result is empty if either channel has no
+/// tags or the total time isn't positive, since g^(2) is undefined.
+pub fn g2_from_histogram(
+    histogram: &BTreeMap<i64, u64>,
+    singles_a: u64,
+    singles_b: u64,
+    win: i64,
+    total_time: f64,
+) -> BTreeMap<i64, Estimate> {
+    if singles_a == 0 || singles_b == 0 || total_time <= 0. {
+        return BTreeMap::new();
+    }
+    let scale = total_time / win as f64 / singles_a as f64 / singles_b as f64;
+    histogram
         .iter()
         .map(|(&d, &c)| {
             (
                 d,
-                (c as f64) * total_time / win as f64 / singles_a / singles_b,
+                Estimate {
+                    value: c as f64 * scale,
+                    err: (c as f64).sqrt() * scale,
+                },
             )
         })
-        .collect();
+        .collect()
+}
+
+/// Calculate the three-channel coincidence histogram of triples of tags from
+/// `ch_a`, `ch_b` and `ch_c`, over the delays `(t_b - t_a, t_c - t_a)` that are
+/// both within `max_delay` either way. Tags are binned by `win` as in
+/// `coincidence_histogram`, and every combination of a `ch_b` and a `ch_c` tag
+/// around each `ch_a` tag is counted.
+///
+/// Each channel's tags are scanned with a window of the `ch_b` and `ch_c` tags
+/// around the current `ch_a` tag, so the time performance is `O(n * m^2)` for
+/// `n` tags and `m` tags per channel within the delay range.
+pub fn coincidence_histogram_3(
+    tags: &[Tag],
+    ch_a: u8,
+    ch_b: u8,
+    ch_c: u8,
+    win: i64,
+    max_delay: i64,
+) -> BTreeMap<(i64, i64), u64> {
+    let max_win = max_delay / win;
+    let binned = |ch: u8| -> Vec<i64> {
+        tags.iter().filter(|t| t.channel == ch).map(|t| t.time / win).collect()
+    };
+    let (a, b, c) = (binned(ch_a), binned(ch_b), binned(ch_c));
 
-    return g2_histogram;
+    let mut histogram: BTreeMap<(i64, i64), u64> = BTreeMap::new();
+    for d_ab in -max_win..=max_win {
+        for d_ac in -max_win..=max_win {
+            histogram.insert((d_ab * win, d_ac * win), 0);
+        }
+    }
+
+    // Indices bounding the ch_b and ch_c tags within range of the ch_a tag,
+    // which only move forward since the ch_a tags are time-sorted
+    let (mut b_lo, mut b_hi, mut c_lo, mut c_hi) = (0, 0, 0, 0);
+    for &ta in &a {
+        while b_lo < b.len() && b[b_lo] < ta - max_win {
+            b_lo += 1;
+        }
+        while b_hi < b.len() && b[b_hi] <= ta + max_win {
+            b_hi += 1;
+        }
+        while c_lo < c.len() && c[c_lo] < ta - max_win {
+            c_lo += 1;
+        }
+        while c_hi < c.len() && c[c_hi] <= ta + max_win {
+            c_hi += 1;
+        }
+        for &tb in &b[b_lo..b_hi] {
+            for &tc in &c[c_lo..c_hi] {
+                *(histogram.get_mut(&((tb - ta) * win, (tc - ta) * win)).unwrap()) += 1;
+            }
+        }
+    }
+    histogram
+}
+
+/// Calculate the third-order degree of coherence, or g^(3) function, over the
+/// delays `(t_b - t_a, t_c - t_a)`, as for heralded single photons. Window,
+/// channels and delay range are as in `coincidence_histogram_3`, and the total
+/// time is the span from the first to the last tag, as in `g2`. Use
+/// `g3_from_histogram` to normalize to another duration. The result is empty
+/// if any channel has no tags.
+pub fn g3(
+    tags: &[Tag],
+    ch_a: u8,
+    ch_b: u8,
+    ch_c: u8,
+    win: i64,
+    max_delay: i64,
+) -> BTreeMap<(i64, i64), Estimate> {
+    let total_time = match (tags.first(), tags.last()) {
+        (Some(first), Some(last)) => (last.time - first.time) as f64,
+        _ => 0.,
+    };
+    g3_from_histogram(
+        &coincidence_histogram_3(tags, ch_a, ch_b, ch_c, win, max_delay),
+        [singles(tags, ch_a), singles(tags, ch_b), singles(tags, ch_c)],
+        win,
+        total_time,
+    )
+}
+
+/// Normalize a three-channel coincidence histogram with bins of `win` to
+/// g^(3), given the singles counts of the three channels over `total_time` in
+/// time units. Uncorrelated channels give g^(3) = 1. Standard errors follow
+/// from Poisson statistics of the triple counts, as in `g2_from_histogram`,
+/// and the result is likewise empty if g^(3) is undefined.
+pub fn g3_from_histogram(
+    histogram: &BTreeMap<(i64, i64), u64>,
+    singles_abc: [u64; 3],
+    win: i64,
+    total_time: f64,
+) -> BTreeMap<(i64, i64), Estimate> {
+    if singles_abc.contains(&0) || total_time <= 0. {
+        return BTreeMap::new();
+    }
+    let scale = (total_time / win as f64).powi(2)
+        / singles_abc.iter().map(|&n| n as f64).product::<f64>();
+    histogram
+        .iter()
+        .map(|(&d, &c)| {
+            (
+                d,
+                Estimate {
+                    value: c as f64 * scale,
+                    err: (c as f64).sqrt() * scale,
+                },
+            )
+        })
+        .collect()
+}
+
+/// Calculate the start-stop (TCSPC) histogram of delays from each `sync` tag
+/// to later tags on any of the `stops` channels, as for fluorescence lifetime
+/// or heralding measurements with a pulsed source.
+///
+/// Delays are histogrammed in bins of width `bin`, from 0 up to `max_delay`
+/// inclusive, and each bin is keyed by the delay at its start. If `multi_stop`
+/// is false, only the first stop tag after each sync tag is counted, as in
+/// classic start-stop electronics; otherwise every stop tag within range is.
+/// Stop tags at the same time as a sync tag count at zero delay, whichever
+/// order they have in the data.
+pub fn lifetime_histogram(
+    tags: &[Tag],
+    sync: u8,
+    stops: &[u8],
+    bin: i64,
+    max_delay: i64,
+    multi_stop: bool,
+) -> BTreeMap<i64, u64> {
+    lifetime_histogram_iter(tags.iter().copied(), sync, stops, bin, max_delay, multi_stop)
+}
+
+/// Calculate the start-stop histogram as in `lifetime_histogram`, from any
+/// time-sorted source of tags. Only the sync tags within `max_delay` of the
+/// latest tag are held in memory, so this can consume a streaming reader like
+/// `de::tags_iter`.
+pub fn lifetime_histogram_iter(
+    tags: impl IntoIterator<Item = Tag>,
+    sync: u8,
+    stops: &[u8],
+    bin: i64,
+    max_delay: i64,
+    multi_stop: bool,
+) -> BTreeMap<i64, u64> {
+    let stop_mask = bit::chans_to_mask(stops);
+    let max_bin = max_delay / bin;
+
+    let mut histogram: BTreeMap<i64, u64> = BTreeMap::new();
+    (0..=max_bin).for_each(|i| {
+        histogram.insert(i * bin, 0);
+    });
+
+    // Sync tags still waiting for (more) stop tags, in time order
+    let mut pending: VecDeque<i64> = VecDeque::new();
+    // Time and number of the latest stop tags, to catch stops that come just
+    // before a sync tag at the same time
+    let mut stop_time = i64::MIN;
+    let mut stop_count = 0;
+
+    for t in tags {
+        // Drop the sync tags out of range of any later stop, whether or not
+        // stops come at all
+        while let Some(&s) = pending.front() {
+            if (t.time - s) / bin > max_bin {
+                pending.pop_front();
+            } else {
+                break;
+            }
+        }
+        let is_stop = (1..=16).contains(&t.channel) && stop_mask.check(t.channel as usize - 1);
+        // Handle the tag as a stop first, so that a sync channel which is also
+        // a stop channel doesn't stop itself
+        if is_stop {
+            for &s in &pending {
+                *(histogram.get_mut(&((t.time - s) / bin * bin)).unwrap()) += 1;
+            }
+            if !multi_stop {
+                pending.clear();
+            }
+        }
+        if t.channel == sync {
+            let coincident = if t.time == stop_time { stop_count } else { 0 };
+            if coincident > 0 {
+                *(histogram.get_mut(&0).unwrap()) += if multi_stop { coincident } else { 1 };
+            }
+            if multi_stop || coincident == 0 {
+                pending.push_back(t.time);
+            }
+        }
+        if is_stop {
+            if t.time == stop_time {
+                stop_count += 1;
+            } else {
+                stop_time = t.time;
+                stop_count = 1;
+            }
+        }
+    }
+    histogram
 }
 
 /// Count coincidences using set intersection algorithm.
@@ -233,3 +602,884 @@ pub fn coincidence_intersection(tags: &[Tag], ch_a: u8, ch_b: u8, win: i64, dela
     }
     count
 }
+
+/// Count n-fold coincidences between all channels in `patmask`.
+///
+/// This generalizes the set intersection algorithm of `coincidence_intersection`
+/// to an arbitrary number of channels. `delays` holds one delay per channel in
+/// the mask, in ascending channel order (as returned by `bit::mask_to_chans`),
+/// and is subtracted from that channel's tags before they are binned by `win`.
+/// Each tag of the lowest channel is counted once if every other channel has a
+/// tag in the same bin. Since delaying a channel's tags keeps them time-sorted,
+/// each channel is scanned only once, and the complexity is linear in the
+/// number of tags for each channel in the mask.
+///
+/// For a two-channel mask with delays `[0, delay]`, this is identical to
+/// `coincidence(tags, ch_a, ch_b, win, delay)` for non-negative `delay`.
+pub fn coincidence_nfold(tags: &[Tag], patmask: u16, win: i64, delays: &[i64]) -> u64 {
+    let chans = bit::mask_to_chans(patmask);
+    assert_eq!(chans.len(), delays.len(), "need one delay per channel in patmask");
+    let (ch_first, chs_rest) = match chans.split_first() {
+        Some((&ch, rest)) => (ch, rest),
+        None => return 0,
+    };
+    let del_first = delays[0];
+
+    // Sorted, delayed and windowed time bins for each of the remaining channels
+    let mut bin_iters: Vec<_> = chs_rest
+        .iter()
+        .zip(&delays[1..])
+        .map(|(&ch, &del)| {
+            tags.iter()
+                .filter(move |&&t| t.channel == ch)
+                .map(move |t| (t.time - del) / win)
+                .peekable()
+        })
+        .collect();
+
+    let mut count = 0;
+    for bin_first in tags
+        .iter()
+        .filter(|&&t| t.channel == ch_first)
+        .map(|t| (t.time - del_first) / win)
+    {
+        let mut all_present = true;
+        for bins in bin_iters.iter_mut() {
+            // Skip past earlier bins; once any channel is exhausted, no
+            // further coincidences are possible
+            while bins.next_if(|&b| b < bin_first).is_some() {}
+            match bins.peek() {
+                Some(&b) => {
+                    if b != bin_first {
+                        all_present = false;
+                    }
+                }
+                None => return count,
+            }
+        }
+        if all_present {
+            count += 1;
+        }
+    }
+    count
+}
+
+/// Count many patterns at zero delay in a single pass over time-sorted tags.
+///
+/// Each of `patterns` is a channel mask with its own window. A pattern counts
+/// as `coincidence_nfold` with zero delays: each tag of the lowest channel in
+/// the mask is counted once if every other channel in the mask has a tag in
+/// the same bin of the window. A single channel therefore counts as `singles`,
+/// and a pair as `coincidence` at zero delay. For every distinct window, the
+/// channels present in the current bin and their numbers of tags are kept, and
+/// added to the counts of that window's patterns once the bin is over, so the
+/// cost per tag grows with the number of distinct windows only.
+pub fn pattern_counts(tags: impl IntoIterator<Item = Tag>, patterns: &[(u16, i64)]) -> Vec<u64> {
+    struct WindowBin {
+        win: i64,
+        bin: Option<i64>,
+        /// Channels with tags in the current bin
+        mask: u16,
+        /// Tags of each channel in the current bin
+        tags: [u64; 16],
+        /// Indices of the patterns in this window
+        patterns: Vec<usize>,
+    }
+
+    let mut bins: Vec<WindowBin> = Vec::new();
+    for (i, &(mask, win)) in patterns.iter().enumerate() {
+        // The empty pattern has nothing to count
+        if mask == 0 {
+            continue;
+        }
+        match bins.iter_mut().find(|b| b.win == win) {
+            Some(b) => b.patterns.push(i),
+            None => bins.push(WindowBin { win, bin: None, mask: 0, tags: [0; 16], patterns: vec![i] }),
+        }
+    }
+
+    let mut counts = vec![0; patterns.len()];
+    let flush = |b: &WindowBin, counts: &mut [u64]| {
+        for &i in &b.patterns {
+            let mask = patterns[i].0;
+            if b.mask & mask == mask {
+                counts[i] += b.tags[mask.trailing_zeros() as usize];
+            }
+        }
+    };
+    for t in tags {
+        // Channels are 1-indexed, bits are 0-indexed
+        let bit = match t.channel {
+            1..=16 => (t.channel - 1) as usize,
+            _ => continue,
+        };
+        for b in bins.iter_mut() {
+            let bin = t.time / b.win;
+            if b.bin != Some(bin) {
+                flush(b, &mut counts);
+                b.bin = Some(bin);
+                b.mask = 0;
+                b.tags = [0; 16];
+            }
+            b.mask |= 1 << bit;
+            b.tags[bit] += 1;
+        }
+    }
+    for b in &bins {
+        flush(b, &mut counts);
+    }
+    counts
+}
+
+/// An estimated quantity with its one standard deviation uncertainty
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct Estimate {
+    pub value: f64,
+    pub err: f64,
+}
+
+/// Coincidence counts at one delay, with the accidental coincidences expected
+/// at that delay from uncorrelated events
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct Car {
+    /// Raw coincidence counts
+    pub coincidences: u64,
+    /// Estimated accidental coincidences
+    pub accidentals: Estimate,
+}
+
+impl Car {
+    /// Accidental-subtracted coincidences
+    pub fn true_coincidences(&self) -> Estimate {
+        let n = self.coincidences as f64;
+        Estimate {
+            value: n - self.accidentals.value,
+            err: (n + self.accidentals.err.powi(2)).sqrt(),
+        }
+    }
+
+    /// Coincidence-to-accidental ratio, raw coincidences over accidentals
+    pub fn ratio(&self) -> Estimate {
+        let n = self.coincidences as f64;
+        let a = self.accidentals;
+        let value = n / a.value;
+        Estimate {
+            value,
+            err: value * (1. / n + (a.err / a.value).powi(2)).sqrt(),
+        }
+    }
+}
+
+/// Estimate accidental coincidences in a `win` from the singles counts of two
+/// uncorrelated channels over `duration`, both in time units. The uncertainty
+/// follows from Poisson statistics of the singles counts.
+pub fn accidentals_singles_counts(singles_a: u64, singles_b: u64, win: i64, duration: i64) -> Estimate {
+    if singles_a == 0 || singles_b == 0 || duration <= 0 {
+        return Estimate::default();
+    }
+    let (a, b) = (singles_a as f64, singles_b as f64);
+    let value = a * b * win as f64 / duration as f64;
+    Estimate {
+        value,
+        err: value * (1. / a + 1. / b).sqrt(),
+    }
+}
+
+/// Estimate accidental coincidences between `ch_a` and `ch_b` in a `win` from
+/// their singles rates over the span of `tags`
+pub fn accidentals_singles(tags: &[Tag], ch_a: u8, ch_b: u8, win: i64) -> Estimate {
+    let duration = match (tags.first(), tags.last()) {
+        (Some(first), Some(last)) => last.time - first.time,
+        _ => 0,
+    };
+    accidentals_singles_counts(singles(tags, ch_a), singles(tags, ch_b), win, duration)
+}
+
+/// Estimate accidental coincidences at `delay` as the mean of the bins of a
+/// coincidence histogram that are at least `min_offset` away from it, where
+/// only uncorrelated events contribute. The uncertainty follows from Poisson
+/// statistics of the off-peak counts.
+pub fn accidentals_offpeak(histogram: &BTreeMap<i64, u64>, delay: i64, min_offset: i64) -> Estimate {
+    let (bins, total) = histogram
+        .iter()
+        .filter(|(&d, _)| (d - delay).abs() >= min_offset)
+        .fold((0u64, 0u64), |(bins, total), (_, &c)| (bins + 1, total + c));
+    if bins == 0 {
+        return Estimate::default();
+    }
+    Estimate {
+        value: total as f64 / bins as f64,
+        err: (total as f64).sqrt() / bins as f64,
+    }
+}
+
+/// Count coincidences at `delay` and estimate their accidentals from the
+/// singles rates, as in `accidentals_singles`
+pub fn car_singles(tags: &[Tag], ch_a: u8, ch_b: u8, win: i64, delay: i64) -> Car {
+    Car {
+        coincidences: coincidence(tags, ch_a, ch_b, win, delay),
+        accidentals: accidentals_singles(tags, ch_a, ch_b, win),
+    }
+}
+
+/// Count coincidences at `delay` and estimate their accidentals from the bins
+/// of the coincidence histogram between `min_offset` and `max_offset` away
+/// from it on both sides, as in `accidentals_offpeak`
+pub fn car_offpeak(
+    tags: &[Tag],
+    ch_a: u8,
+    ch_b: u8,
+    win: i64,
+    delay: i64,
+    min_offset: i64,
+    max_offset: i64,
+) -> Car {
+    let histogram = coincidence_histogram(tags, ch_a, ch_b, win, delay - max_offset, delay + max_offset);
+    Car {
+        coincidences: coincidence(tags, ch_a, ch_b, win, delay),
+        accidentals: accidentals_offpeak(&histogram, delay, min_offset),
+    }
+}
+
+/// A peak in a coincidence histogram
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Peak {
+    /// Delay of the bin with the most counts
+    pub delay: i64,
+    /// Background-subtracted centroid of the peak, resolving fractions of a bin
+    pub centroid: f64,
+    /// Counts in the bin at `delay`
+    pub counts: u64,
+    /// Background counts per bin, taken as the median of all bins
+    pub background: f64,
+}
+
+/// Locate the peak of a coincidence histogram, as from `coincidence_histogram`.
+///
+/// The centroid is the mean delay of the bins around the highest bin that
+/// stand above the background, weighted by their counts above it. Returns
+/// `None` if the highest bin isn't at least five standard deviations (of
+/// Poisson noise on the background, but at least one count) above it.
+pub fn find_peak(histogram: &BTreeMap<i64, u64>) -> Option<Peak> {
+    let (&delay, &counts) = histogram.iter().rev().max_by_key(|(_, &c)| c)?;
+    let mut sorted: Vec<u64> = histogram.values().copied().collect();
+    sorted.sort_unstable();
+    let n = sorted.len();
+    let background = if n % 2 == 1 {
+        sorted[n / 2] as f64
+    } else {
+        (sorted[n / 2 - 1] + sorted[n / 2]) as f64 / 2.
+    };
+    if (counts as f64) < background + 5. * background.max(1.).sqrt() {
+        return None;
+    }
+
+    let above = |(&d, &c): (&i64, &u64)| (c as f64 > background).then_some((d, c as f64 - background));
+    let (mut sum, mut weights) = (0., 0.);
+    for (d, w) in histogram
+        .range(..=delay)
+        .rev()
+        .map_while(above)
+        .chain(histogram.range(delay + 1..).map_while(above))
+    {
+        sum += d as f64 * w;
+        weights += w;
+    }
+    Some(Peak {
+        delay,
+        centroid: sum / weights,
+        counts,
+        background,
+    })
+}
+
+/// Delays searched either way by `tagsave` and `tagview` when calibrating with
+/// `calibrate_delays`, in `TSTEP` (100 ns)
+pub const CALIBRATION_RANGE: i64 = 640;
+
+/// Find the coincidence peak between `reference` and each of `channels`, with
+/// delays in `win` bins up to `max_delay` either way. Channels without a clear
+/// peak (see `find_peak`) are left out.
+pub fn calibrate_delays(
+    tags: &[Tag],
+    reference: u8,
+    channels: &[u8],
+    win: i64,
+    max_delay: i64,
+) -> BTreeMap<u8, Peak> {
+    channels
+        .iter()
+        .filter(|&&ch| ch != reference)
+        .filter_map(|&ch| {
+            let histogram = coincidence_histogram(tags, reference, ch, win, -max_delay, max_delay);
+            find_peak(&histogram).map(|peak| (ch, peak))
+        })
+        .collect()
+}
+
+/// Input delays, in time units, that move each of the `peaks` found against
+/// `reference` by `calibrate_delays` to zero delay, given the `current` input
+/// delays the tags were acquired with (missing channels are taken as zero).
+///
+/// Input delays can't be negative, so if a channel is ahead of the reference
+/// by more than its current delay, the reference and all calibrated channels
+/// are delayed further together. Channels without a peak are not included, so
+/// in that case they will need to be delayed by the same amount by hand.
+pub fn aligned_delays(
+    reference: u8,
+    peaks: &BTreeMap<u8, Peak>,
+    current: &BTreeMap<u8, u32>,
+) -> BTreeMap<u8, u32> {
+    let current_of = |ch: u8| current.get(&ch).copied().unwrap_or(0) as i64;
+    let mut delays: BTreeMap<u8, i64> = peaks
+        .iter()
+        .map(|(&ch, peak)| (ch, current_of(ch) - peak.centroid.round() as i64))
+        .collect();
+    delays.insert(reference, current_of(reference));
+    let shift = cmp::max(0, -delays.values().copied().min().unwrap_or(0));
+    delays
+        .into_iter()
+        .map(|(ch, d)| (ch, (d + shift) as u32))
+        .collect()
+}
+
+/// A pattern counted in a rate trace
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum TracePattern {
+    Single(u8),
+    /// Coincidences of two channels in the same window, counted as in
+    /// `pattern_counts`: each tag of the lower channel counts once if the other
+    /// channel has a tag in its window
+    Pair(u8, u8),
+}
+
+impl TracePattern {
+    /// Singles of `singles` and coincidences of `pairs`, as given to the
+    /// `--single` and `--pair` options of `rate_trace` and `allan_deviation`
+    pub fn from_args(singles: &[u8], pairs: &[TracePattern]) -> anyhow::Result<Vec<TracePattern>> {
+        let mut patterns: Vec<TracePattern> = singles.iter().map(|&ch| TracePattern::Single(ch)).collect();
+        for &p in pairs {
+            match p {
+                TracePattern::Pair(..) => patterns.push(p),
+                TracePattern::Single(ch) => anyhow::bail!("expected a pair of channels as a,b, got {}", ch),
+            }
+        }
+        if patterns.is_empty() {
+            anyhow::bail!("at least one --single or --pair is required");
+        }
+        Ok(patterns)
+    }
+}
+
+/// Parse a channel, or a pair of channels as `a,b`
+impl FromStr for TracePattern {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let parse = |c: &str| c.trim().parse::<u8>().with_context(|| format!("invalid channel {:?}", c));
+        match s.split_once(',') {
+            Some((a, b)) => Ok(TracePattern::Pair(parse(a)?, parse(b)?)),
+            None => Ok(TracePattern::Single(parse(s)?)),
+        }
+    }
+}
+
+/// Channels as parsed, e.g. to name columns
+impl fmt::Display for TracePattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TracePattern::Single(ch) => write!(f, "{}", ch),
+            TracePattern::Pair(a, b) => write!(f, "{},{}", a, b),
+        }
+    }
+}
+
+/// Count singles and coincidences of `patterns` in consecutive time bins of
+/// width `bin`, from any time-sorted source of tags, to follow the rates over
+/// the course of a run. Coincidences are counted in windows of `win`, in the
+/// time bin of the first tag of each window.
+///
+/// Yields the start time of each bin with the counts of each pattern in it, in
+/// the order of `patterns`, as soon as the bin is complete. Every bin from the
+/// first to the last tag is yielded, including those without counts, but only
+/// the bins of the current window are held in memory.
+pub fn rate_trace<I: IntoIterator<Item = Tag>>(
+    tags: I,
+    patterns: &[TracePattern],
+    win: i64,
+    bin: i64,
+) -> RateTrace<I::IntoIter> {
+    let mut singles: HashMap<u8, Vec<usize>> = HashMap::new();
+    let mut pairs = Vec::new();
+    for (i, &p) in patterns.iter().enumerate() {
+        match p {
+            TracePattern::Single(ch) => singles.entry(ch).or_default().push(i),
+            TracePattern::Pair(ch_a, ch_b) => pairs.push((i, cmp::min(ch_a, ch_b), cmp::max(ch_a, ch_b))),
+        }
+    }
+    RateTrace {
+        tags: tags.into_iter(),
+        singles,
+        pairs,
+        patterns: patterns.len(),
+        win,
+        bin,
+        window: None,
+        in_window: HashMap::new(),
+        next: 0,
+        ready: 0,
+        open: VecDeque::new(),
+        pending: None,
+        done: false,
+    }
+}
+
+/// Iterator over the bins of a `rate_trace`
+pub struct RateTrace<I> {
+    tags: I,
+    /// Patterns counting singles of each channel
+    singles: HashMap<u8, Vec<usize>>,
+    /// Patterns counting pairs, with their lower and higher channels
+    pairs: Vec<(usize, u8, u8)>,
+    patterns: usize,
+    win: i64,
+    bin: i64,
+    /// Current window, and the bin of its first tag
+    window: Option<(i64, i64)>,
+    /// Tags per channel so far in the current window
+    in_window: HashMap<u8, u64>,
+    /// Index of the next bin to yield, whose counts start `open`
+    next: i64,
+    /// Bins before this index have all their counts
+    ready: i64,
+    open: VecDeque<Vec<u64>>,
+    /// Tag read but not yet counted, until the bins before it are yielded
+    pending: Option<Tag>,
+    done: bool,
+}
+
+impl<I> RateTrace<I> {
+    /// Counts of the bin with index `b`, which must not have been yielded
+    fn counts(&mut self, b: i64) -> &mut Vec<u64> {
+        let i = (b - self.next) as usize;
+        while self.open.len() <= i {
+            self.open.push_back(vec![0; self.patterns]);
+        }
+        &mut self.open[i]
+    }
+
+    /// Add the coincidences of the current window to the bin of its first tag
+    fn close_window(&mut self) {
+        if let Some((_, b)) = self.window.take() {
+            let count = |ch| self.in_window.get(&ch).copied().unwrap_or(0);
+            let found: Vec<(usize, u64)> = self
+                .pairs
+                .iter()
+                .filter(|&&(_, _, high)| count(high) > 0)
+                .map(|&(i, low, _)| (i, count(low)))
+                .collect();
+            let counts = self.counts(b);
+            for (i, n) in found {
+                counts[i] += n;
+            }
+            self.in_window.clear();
+        }
+    }
+}
+
+impl<I: Iterator<Item = Tag>> Iterator for RateTrace<I> {
+    type Item = (i64, Vec<u64>);
+
+    fn next(&mut self) -> Option<(i64, Vec<u64>)> {
+        loop {
+            if self.next < self.ready {
+                let b = self.next;
+                let counts = self.open.pop_front().unwrap_or_else(|| vec![0; self.patterns]);
+                self.next += 1;
+                return Some((b * self.bin, counts));
+            }
+            if let Some(t) = self.pending.take() {
+                // Time-sorted tags never fall in a bin already yielded
+                let b = cmp::max(t.time.div_euclid(self.bin), self.next);
+                if let Some(counted) = self.singles.get(&t.channel).cloned() {
+                    let counts = self.counts(b);
+                    for i in counted {
+                        counts[i] += 1;
+                    }
+                }
+                *self.in_window.entry(t.channel).or_default() += 1;
+                continue;
+            }
+            if self.done {
+                return None;
+            }
+            match self.tags.next() {
+                Some(t) => {
+                    let b = t.time.div_euclid(self.bin);
+                    let w = t.time.div_euclid(self.win);
+                    if self.window.is_none() {
+                        // The first tag starts the trace
+                        self.next = b;
+                        self.ready = b;
+                    }
+                    match self.window {
+                        Some((current, _)) if current == w => {}
+                        _ => {
+                            self.close_window();
+                            self.window = Some((w, cmp::max(b, self.next)));
+                        }
+                    }
+                    // Bins before the current window are complete
+                    self.ready = self.window.unwrap().1;
+                    self.pending = Some(t);
+                }
+                None => {
+                    self.close_window();
+                    self.ready = self.next + self.open.len() as i64;
+                    self.done = true;
+                }
+            }
+        }
+    }
+}
+
+/// Calculate `rate_trace` from a source of tags that may fail to read, like
+/// `de::tags_iter`, with the time bin width given in seconds, passing each bin
+/// to `each` as it is completed. Returns the bin width in `TSTEP`.
+pub fn rate_trace_seconds(
+    tags: impl IntoIterator<Item = anyhow::Result<Tag>>,
+    patterns: &[TracePattern],
+    win: i64,
+    bin: f64,
+    mut each: impl FnMut(i64, Vec<u64>) -> anyhow::Result<()>,
+) -> anyhow::Result<i64> {
+    if win <= 0 {
+        anyhow::bail!("window size must be positive");
+    }
+    let bin = (bin / TSTEP).round() as i64;
+    if bin <= 0 {
+        anyhow::bail!("time bin width must be positive");
+    }
+    itertools::process_results(tags, |tags| {
+        rate_trace(tags, patterns, win, bin).try_for_each(|(t, counts)| each(t, counts))
+    })??;
+    Ok(bin)
+}
+
+/// Overlapping Allan deviation at one averaging time, with a 68% confidence
+/// interval
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct AllanDeviation {
+    /// Averaging time, in the units of the bin width passed to `allan_deviation`
+    pub tau: f64,
+    pub adev: f64,
+    /// Lower bound of the confidence interval
+    pub lo: f64,
+    /// Upper bound of the confidence interval
+    pub hi: f64,
+}
+
+/// Calculate the overlapping Allan deviation of a series of rates measured in
+/// consecutive bins of width `tau0`, e.g. the counts of a `rate_trace` per bin
+/// width, at averaging times of octaves of `tau0` up to half of the series.
+///
+/// At an averaging time `m * tau0`, this is the root of half the mean squared
+/// difference between the averages of all pairs of adjacent runs of `m` bins.
+/// The confidence interval is from the chi-squared distribution with the
+/// equivalent degrees of freedom for white frequency noise, which is the
+/// Poisson noise of count rates.
+pub fn allan_deviation(rates: &[f64], tau0: f64) -> Vec<AllanDeviation> {
+    let n = rates.len();
+    // Cumulative sums so that the average of any run of bins is one difference
+    let mut sums = Vec::with_capacity(n + 1);
+    sums.push(0.);
+    for r in rates {
+        sums.push(sums.last().unwrap() + r);
+    }
+
+    let mut adevs = Vec::new();
+    let mut m = 1;
+    while 2 * m <= n {
+        let pairs = n - 2 * m + 1;
+        let sum_sq: f64 = (0..pairs)
+            .map(|j| {
+                let first = sums[j + m] - sums[j];
+                let second = sums[j + 2 * m] - sums[j + m];
+                ((second - first) / m as f64).powi(2)
+            })
+            .sum();
+        let adev = (sum_sq / (2 * pairs) as f64).sqrt();
+
+        let (nf, mf) = (n as f64, m as f64);
+        let edf = ((3. * (nf - 1.) / (2. * mf) - 2. * (nf - 2.) / nf) * 4. * mf * mf
+            / (4. * mf * mf + 5.))
+            .max(1.);
+        adevs.push(AllanDeviation {
+            tau: mf * tau0,
+            adev,
+            lo: adev * (edf / chi_squared_quantile(edf, 1.)).sqrt(),
+            hi: adev * (edf / chi_squared_quantile(edf, -1.)).sqrt(),
+        });
+        m *= 2;
+    }
+    adevs
+}
+
+/// Quantile of the chi-squared distribution with `k` degrees of freedom at `z`
+/// standard deviations of the normal distribution, by the Wilson-Hilferty
+/// approximation
+fn chi_squared_quantile(k: f64, z: f64) -> f64 {
+    let s = 2. / (9. * k);
+    k * (1. - s + z * s.sqrt()).powi(3)
+}
+
+/// Histogram the intervals between consecutive tags on each channel, in bins of
+/// width `bin` keyed by their start from 0 to `max_delay`, from any time-sorted
+/// source of tags. Longer intervals are not counted.
+///
+/// At short intervals this shows the dead time of a detector, and any excess
+/// over the exponential distribution of a Poisson process shows afterpulsing.
+pub fn interarrival_histograms(
+    tags: impl IntoIterator<Item = Tag>,
+    bin: i64,
+    max_delay: i64,
+) -> BTreeMap<u8, BTreeMap<i64, u64>> {
+    let max_bin = max_delay / bin;
+    let mut counts: BTreeMap<u8, Vec<u64>> = BTreeMap::new();
+    let mut previous: HashMap<u8, i64> = HashMap::new();
+    for t in tags {
+        let bins = counts
+            .entry(t.channel)
+            .or_insert_with(|| vec![0; max_bin as usize + 1]);
+        if let Some(p) = previous.insert(t.channel, t.time) {
+            let i = (t.time - p) / bin;
+            if i <= max_bin {
+                bins[i as usize] += 1;
+            }
+        }
+    }
+    counts
+        .into_iter()
+        .map(|(ch, bins)| {
+            let histogram = bins
+                .into_iter()
+                .enumerate()
+                .map(|(i, c)| (i as i64 * bin, c))
+                .collect();
+            (ch, histogram)
+        })
+        .collect()
+}
+
+/// Dead time and afterpulsing of a detector, from `afterpulsing`
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct Afterpulsing {
+    /// Start of the first bin with any intervals, in time units
+    pub dead_time: i64,
+    /// Probability of an afterpulse following a detection
+    pub probability: Estimate,
+}
+
+/// Estimate the dead time and afterpulsing probability of a detector from the
+/// `interarrival_histograms` of its channel with `events` tags in total.
+///
+/// The bins from `tail_start` on are fit by maximum likelihood with the
+/// exponential distribution of intervals of a Poisson process. Afterpulses are
+/// the excess of the bins from the dead time up to `tail_start` over this fit.
+/// The uncertainty follows from Poisson statistics of these bins and the tail.
+/// Returns `None` if the histogram or its tail is empty.
+pub fn afterpulsing(histogram: &BTreeMap<i64, u64>, events: u64, tail_start: i64) -> Option<Afterpulsing> {
+    let (&dead_time, _) = histogram.iter().find(|(_, &c)| c > 0)?;
+
+    // Poisson maximum likelihood fit of counts = amplitude * exp(-rate * x)
+    // on the tail, with x from tail_start, matches the total and mean x of the
+    // fit to those of the counts. The mean falls with the rate, so bisect.
+    let tail: Vec<(f64, f64)> = histogram
+        .range(tail_start..)
+        .map(|(&d, &c)| ((d - tail_start) as f64, c as f64))
+        .collect();
+    let total: f64 = tail.iter().map(|&(_, c)| c).sum();
+    let span = tail.last().map_or(0., |&(x, _)| x);
+    if total == 0. || span == 0. {
+        return None;
+    }
+    let mean = tail.iter().map(|&(x, c)| x * c).sum::<f64>() / total;
+    let fit_mean = |rate: f64| {
+        let (sum, sum_x) = tail
+            .iter()
+            .fold((0., 0.), |(s, sx), &(x, _)| (s + (-rate * x).exp(), sx + x * (-rate * x).exp()));
+        sum_x / sum
+    };
+    let (mut lo, mut hi) = (-50. / span, 50. / span);
+    for _ in 0..100 {
+        let rate = (lo + hi) / 2.;
+        if fit_mean(rate) > mean {
+            lo = rate;
+        } else {
+            hi = rate;
+        }
+    }
+    let rate = (lo + hi) / 2.;
+    let amplitude = total / tail.iter().map(|&(x, _)| (-rate * x).exp()).sum::<f64>();
+
+    let (counts, expected) = histogram
+        .range(dead_time..tail_start)
+        .fold((0., 0.), |(counts, expected), (&d, &c)| {
+            (counts + c as f64, expected + amplitude * (-rate * (d - tail_start) as f64).exp())
+        });
+    // The expected counts are as uncertain as the total of the tail they scale
+    let (excess, variance) = (counts - expected, counts + expected * expected / total);
+    Some(Afterpulsing {
+        dead_time,
+        probability: Estimate {
+            value: excess / events as f64,
+            err: variance.sqrt() / events as f64,
+        },
+    })
+}
+
+/// Drop tags that follow the previous kept tag on their channel by less than
+/// `dead_time`, emulating a non-paralyzable detector dead time in software.
+/// This is lazy over any time-sorted source of tags.
+pub fn dead_time_filter(
+    tags: impl IntoIterator<Item = Tag>,
+    dead_time: i64,
+) -> impl Iterator<Item = Tag> {
+    let mut previous: HashMap<u8, i64> = HashMap::new();
+    tags.into_iter().filter(move |t| match previous.get(&t.channel) {
+        Some(&p) if t.time - p < dead_time => false,
+        _ => {
+            previous.insert(t.channel, t.time);
+            true
+        }
+    })
+}
+
+/// Reference for folding tags into the periods of a pulsed source
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum PulseClock {
+    /// Pulses every `period` time units, with pulse 0 starting at `offset`
+    Fixed { period: f64, offset: i64 },
+    /// Tags on `channel` mark the start of every `divider`th pulse. The start
+    /// and period of the pulses are tracked with the loop of a
+    /// `clock::ClockRecovery` at its default gain, which averages out the
+    /// jitter of the clock tags while following slow drift. Missing clock tags
+    /// are bridged by counting whole periods in an interval.
+    Channel { channel: u8, divider: u32 },
+}
+
+/// A tag with the pulse it falls in and its phase from the start of the pulse,
+/// from `fold_pulses`
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub struct PulseTag {
+    pub tag: Tag,
+    pub pulse: i64,
+    /// Time from the start of the pulse, rounded down to time units
+    pub phase: i64,
+}
+
+/// The state of folding a stream of tags, as in `fold_pulses`
+#[derive(Clone, Debug)]
+pub struct Folding {
+    clock: PulseClock,
+    /// Loop tracking the clock channel of `PulseClock::Channel`
+    recovering: Option<clock::Recovering>,
+}
+
+impl Folding {
+    pub fn new(clock: PulseClock) -> Self {
+        let recovering = match clock {
+            PulseClock::Fixed { .. } => None,
+            PulseClock::Channel { channel, divider } => {
+                Some(clock::ClockRecovery::new(channel).divider(divider).start())
+            }
+        };
+        Folding { clock, recovering }
+    }
+
+    /// Fold the next tag of the stream, or `None` if it comes before the period
+    /// is known from the first two clock tags
+    pub fn fold(&mut self, t: Tag) -> Option<PulseTag> {
+        let (start, first_pulse, period) = match (self.clock, self.recovering.as_mut()) {
+            (PulseClock::Fixed { period, offset }, _) => (offset as f64, 0, period),
+            (PulseClock::Channel { .. }, Some(recovering)) => {
+                recovering.track(t);
+                let (locked, pulse) = recovering.locked_pulse()?;
+                (locked, pulse, recovering.tracked_period()?)
+            }
+            (PulseClock::Channel { .. }, None) => unreachable!("clock channel is tracked from the start"),
+        };
+        let elapsed = t.time as f64 - start;
+        let pulses = (elapsed / period).floor();
+        Some(PulseTag {
+            tag: t,
+            pulse: first_pulse + pulses as i64,
+            // Rounding may leave the phase just short of the start of the pulse
+            phase: (elapsed - pulses * period).floor().max(0.) as i64,
+        })
+    }
+}
+
+/// Fold any time-sorted source of tags into the pulses of `clock`, dropping
+/// those before the period is known
+pub fn fold_pulses(
+    tags: impl IntoIterator<Item = Tag>,
+    clock: PulseClock,
+) -> impl Iterator<Item = PulseTag> {
+    let mut folding = Folding::new(clock);
+    tags.into_iter().filter_map(move |t| folding.fold(t))
+}
+
+/// Histogram the phases of the tags on each channel within the pulses of
+/// `clock`, in bins of width `bin` keyed by their start. Every channel has the
+/// same bins, from 0 to the latest bin with counts on any channel.
+pub fn phase_histograms(
+    tags: impl IntoIterator<Item = Tag>,
+    clock: PulseClock,
+    bin: i64,
+) -> BTreeMap<u8, BTreeMap<i64, u64>> {
+    let mut histograms: BTreeMap<u8, BTreeMap<i64, u64>> = BTreeMap::new();
+    let mut max_phase = 0;
+    for p in fold_pulses(tags, clock) {
+        let b = p.phase / bin * bin;
+        max_phase = cmp::max(max_phase, b);
+        *histograms.entry(p.tag.channel).or_default().entry(b).or_default() += 1;
+    }
+    for histogram in histograms.values_mut() {
+        for b in (0..=max_phase).step_by(bin as usize) {
+            histogram.entry(b).or_default();
+        }
+    }
+    histograms
+}
+
+/// A tag with the pulse and time bin within it that it falls in, from
+/// `assign_time_bins`
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub struct TimeBinTag {
+    pub tag: Tag,
+    pub pulse: i64,
+    /// Index of the time bin
+    pub bin: usize,
+}
+
+/// Assign the tags of any time-sorted source to a pulse of `clock`, and a time
+/// bin within it, for time-bin encoding. Time bin `i` holds phases from
+/// `edges[i]` up to `edges[i + 1]`, so `n + 1` increasing edges give `n` bins,
+/// e.g. `[0, 40, 80]` for an early and a late bin. Tags outside every time bin
+/// are dropped.
+pub fn assign_time_bins<'a>(
+    tags: impl IntoIterator<Item = Tag> + 'a,
+    clock: PulseClock,
+    edges: &'a [i64],
+) -> impl Iterator<Item = TimeBinTag> + 'a {
+    fold_pulses(tags, clock).filter_map(move |p| {
+        // Edges are sorted, so this counts the edges at or before the phase
+        let i = edges.partition_point(|&e| e <= p.phase);
+        if i > 0 && i < edges.len() {
+            Some(TimeBinTag { tag: p.tag, pulse: p.pulse, bin: i - 1 })
+        } else {
+            None
+        }
+    })
+}
@@ -0,0 +1,313 @@
+//! Reader for PicoQuant `.ptu` files of T2 mode records
+//!
+//! A PTU file starts with the magic `PQTTTR\0\0` and an 8-byte version, then a
+//! header of tagged values, each with a 32-byte name, a 4-byte index, a 4-byte
+//! type and an 8-byte value, where strings and arrays follow with the value as
+//! their length in bytes. The header ends with the tag `Header_End`, followed by
+//! the records, little-endian 32-bit words. In T2 mode, each record holds the
+//! time of an event on a detector channel, the sync input or a marker, or an
+//! overflow of the time field that is carried into the times of later records.
+//!
+//! Times are converted from the file's resolution, `MeasDesc_Resolution`, to
+//! `TSTEP` exactly (rounding down), as long as the resolution is a whole number
+//! of femtoseconds, which it is for every PicoQuant instrument. T3 mode records
+//! and the older `.ht2`/`.ht3` file formats are not supported.
+
+use crate::Tag;
+use anyhow::{bail, Context, Result};
+use std::collections::HashMap;
+use std::io::{BufReader, Read};
+
+/// Magic string starting a PTU file
+const MAGIC: &[u8; 8] = b"PQTTTR\0\0";
+
+// Types of header tags
+const TY_EMPTY8: u32 = 0xFFFF0008;
+const TY_BOOL8: u32 = 0x00000008;
+const TY_INT8: u32 = 0x10000008;
+const TY_BITSET64: u32 = 0x11000008;
+const TY_COLOR8: u32 = 0x12000008;
+const TY_FLOAT8: u32 = 0x20000008;
+const TY_TDATETIME: u32 = 0x21000008;
+const TY_FLOAT8_ARRAY: u32 = 0x2001FFFF;
+const TY_ANSI_STRING: u32 = 0x4001FFFF;
+const TY_WIDE_STRING: u32 = 0x4002FFFF;
+const TY_BINARY_BLOB: u32 = 0xFFFFFFFF;
+
+// Record types of T2 mode
+const RT_PICOHARP_T2: u32 = 0x00010203;
+const RT_HYDRAHARP_T2: u32 = 0x00010204;
+const RT_HYDRAHARP2_T2: u32 = 0x01010204;
+const RT_TIMEHARP260N_T2: u32 = 0x00010205;
+const RT_TIMEHARP260P_T2: u32 = 0x00010206;
+const RT_MULTIHARP_T2: u32 = 0x00010207;
+
+/// Time of one overflow of PicoHarp T2 records
+const PICOHARP_WRAPAROUND: i64 = 210698240;
+/// Time of one overflow of version 1 HydraHarp T2 records
+const HYDRAHARP_V1_WRAPAROUND: i64 = 33552000;
+/// Time of one overflow of later HydraHarp-style T2 records
+const HYDRAHARP_V2_WRAPAROUND: i64 = 1 << 25;
+
+/// Femtoseconds per `crate::TSTEP`
+const TSTEP_FS: i128 = 156250;
+
+/// A value of the PTU header
+#[derive(Clone, PartialEq, Debug)]
+pub enum Value {
+    Empty,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    /// Days since 1899-12-30, as in the file
+    DateTime(f64),
+    String(String),
+    /// Arrays and blobs, as raw bytes
+    Bytes(Vec<u8>),
+}
+
+/// Layouts of T2 records
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+enum Format {
+    PicoHarp,
+    HydraHarpV1,
+    HydraHarpV2,
+}
+
+/// Decoded T2 record
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+enum Record {
+    /// Detector channel, from 0
+    Photon(u8, i64),
+    Sync(i64),
+    /// Bits of the set markers
+    Marker(u8, i64),
+    Overflow,
+}
+
+/// Streaming reader of the tags in a PTU file, built with `reader`
+pub struct PtuReader<R: Read> {
+    rdr: BufReader<R>,
+    header: HashMap<String, Value>,
+    format: Format,
+    resolution_fs: i128,
+    /// Time carried from overflow records, in the file's resolution
+    overflow: i64,
+    sync_channel: Option<u8>,
+    marker_channel: Option<u8>,
+    /// Records left to read, if the header says
+    remaining: Option<u64>,
+}
+
+/// Read the header of a PTU file, returning a reader of its tags. Detector
+/// channels 0, 1, ... of the file are tags on channels 1, 2, ..., while sync
+/// and marker events are dropped unless given channels with the reader's
+/// `sync_channel` and `marker_channel`.
+pub fn reader<R: Read>(rdr: R) -> Result<PtuReader<R>> {
+    let mut rdr = BufReader::new(rdr);
+    let mut magic = [0u8; 8];
+    rdr.read_exact(&mut magic).context("file too short for a PTU header")?;
+    if &magic != MAGIC {
+        bail!("not a PTU file");
+    }
+    let mut version = [0u8; 8];
+    rdr.read_exact(&mut version)?;
+
+    let header = read_header(&mut rdr)?;
+    let record_type = match header.get("TTResultFormat_TTTRRecType") {
+        Some(Value::Int(t)) => *t as u32,
+        _ => bail!("PTU header has no record type"),
+    };
+    let format = match record_type {
+        RT_PICOHARP_T2 => Format::PicoHarp,
+        RT_HYDRAHARP_T2 => Format::HydraHarpV1,
+        RT_HYDRAHARP2_T2 | RT_TIMEHARP260N_T2 | RT_TIMEHARP260P_T2 | RT_MULTIHARP_T2 => Format::HydraHarpV2,
+        t => bail!("unsupported PTU record type {:#010x}, only T2 mode is supported", t),
+    };
+    let resolution = match header.get("MeasDesc_Resolution") {
+        Some(Value::Float(r)) if *r > 0. => *r,
+        _ => bail!("PTU header has no resolution"),
+    };
+    let resolution_fs = (resolution * 1e15).round() as i128;
+    if resolution_fs == 0 || ((resolution_fs as f64 * 1e-15) / resolution - 1.).abs() > 1e-9 {
+        bail!("PTU resolution {} s is not a whole number of femtoseconds", resolution);
+    }
+    let remaining = match header.get("TTResult_NumberOfRecords") {
+        Some(Value::Int(n)) if *n >= 0 => Some(*n as u64),
+        _ => None,
+    };
+    Ok(PtuReader {
+        rdr,
+        header,
+        format,
+        resolution_fs,
+        overflow: 0,
+        sync_channel: None,
+        marker_channel: None,
+        remaining,
+    })
+}
+
+/// Read header tags up to `Header_End`
+fn read_header(rdr: &mut impl Read) -> Result<HashMap<String, Value>> {
+    let mut header = HashMap::new();
+    loop {
+        let mut entry = [0u8; 48];
+        rdr.read_exact(&mut entry).context("PTU header ended early")?;
+        let name_end = entry[..32].iter().position(|&b| b == 0).unwrap_or(32);
+        let mut name = String::from_utf8_lossy(&entry[..name_end]).into_owned();
+        let idx = i32::from_le_bytes(entry[32..36].try_into().unwrap());
+        let typ = u32::from_le_bytes(entry[36..40].try_into().unwrap());
+        let raw: [u8; 8] = entry[40..48].try_into().unwrap();
+        let int = i64::from_le_bytes(raw);
+
+        let mut bytes = || -> Result<Vec<u8>> {
+            if int < 0 {
+                bail!("negative length of PTU header tag {}", name);
+            }
+            // The length may be corrupt, so only read as much as is there
+            let mut buf = Vec::new();
+            rdr.by_ref().take(int as u64).read_to_end(&mut buf)?;
+            if buf.len() as u64 != int as u64 {
+                bail!("PTU header ended early in tag {}", name);
+            }
+            Ok(buf)
+        };
+        let value = match typ {
+            TY_EMPTY8 => Value::Empty,
+            TY_BOOL8 => Value::Bool(int != 0),
+            TY_INT8 | TY_BITSET64 | TY_COLOR8 => Value::Int(int),
+            TY_FLOAT8 => Value::Float(f64::from_le_bytes(raw)),
+            TY_TDATETIME => Value::DateTime(f64::from_le_bytes(raw)),
+            TY_ANSI_STRING => {
+                let buf = bytes()?;
+                let end = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+                Value::String(String::from_utf8_lossy(&buf[..end]).into_owned())
+            }
+            TY_WIDE_STRING => {
+                let buf = bytes()?;
+                let units: Vec<u16> = buf
+                    .chunks_exact(2)
+                    .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                    .take_while(|&u| u != 0)
+                    .collect();
+                Value::String(String::from_utf16_lossy(&units))
+            }
+            TY_FLOAT8_ARRAY | TY_BINARY_BLOB => Value::Bytes(bytes()?),
+            t => bail!("unknown PTU header tag type {:#010x} of {}", t, name),
+        };
+        if name == "Header_End" {
+            return Ok(header);
+        }
+        // Indexed tags are distinguished by their index
+        if idx >= 0 {
+            name = format!("{}({})", name, idx);
+        }
+        header.insert(name, value);
+    }
+}
+
+impl<R: Read> PtuReader<R> {
+    /// Put sync events on `channel`
+    pub fn sync_channel(mut self, channel: u8) -> Self {
+        self.sync_channel = Some(channel);
+        self
+    }
+
+    /// Put marker events on `channel`
+    pub fn marker_channel(mut self, channel: u8) -> Self {
+        self.marker_channel = Some(channel);
+        self
+    }
+
+    /// Header values by name, with the index in parentheses for indexed values,
+    /// e.g. `"HW_InpChan_Offset(0)"`
+    pub fn header(&self) -> &HashMap<String, Value> {
+        &self.header
+    }
+
+    /// Duration of one time unit of the file, in seconds
+    pub fn resolution(&self) -> f64 {
+        self.resolution_fs as f64 / 1e15
+    }
+
+    /// Time in the file's resolution to `TSTEP`, rounding down
+    fn to_tstep(&self, time: i64) -> i64 {
+        (time as i128 * self.resolution_fs).div_euclid(TSTEP_FS) as i64
+    }
+
+    fn decode(&mut self, word: u32) -> Record {
+        match self.format {
+            Format::PicoHarp => {
+                let channel = (word >> 28) as u8;
+                let time = (word & 0x0FFF_FFFF) as i64;
+                if channel == 0xF {
+                    let markers = (time & 0xF) as u8;
+                    if markers == 0 {
+                        self.overflow += PICOHARP_WRAPAROUND;
+                        Record::Overflow
+                    } else {
+                        Record::Marker(markers, self.overflow + time)
+                    }
+                } else {
+                    Record::Photon(channel, self.overflow + time)
+                }
+            }
+            Format::HydraHarpV1 | Format::HydraHarpV2 => {
+                let special = word >> 31 == 1;
+                let channel = ((word >> 25) & 0x3F) as u8;
+                let time = (word & 0x01FF_FFFF) as i64;
+                if !special {
+                    Record::Photon(channel, self.overflow + time)
+                } else if channel == 0x3F {
+                    self.overflow += match self.format {
+                        Format::HydraHarpV1 => HYDRAHARP_V1_WRAPAROUND,
+                        // Later formats count several overflows in one record
+                        _ => HYDRAHARP_V2_WRAPAROUND * time.max(1),
+                    };
+                    Record::Overflow
+                } else if channel == 0 {
+                    Record::Sync(self.overflow + time)
+                } else {
+                    Record::Marker(channel, self.overflow + time)
+                }
+            }
+        }
+    }
+}
+
+impl<R: Read> Iterator for PtuReader<R> {
+    type Item = Result<Tag>;
+
+    fn next(&mut self) -> Option<Result<Tag>> {
+        loop {
+            if self.remaining == Some(0) {
+                return None;
+            }
+            let mut word = [0u8; 4];
+            match self.rdr.read_exact(&mut word) {
+                Ok(()) => {}
+                // Without a record count, the records end with the file
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof && self.remaining.is_none() => {
+                    return None;
+                }
+                Err(e) => {
+                    self.remaining = Some(0);
+                    return Some(Err(e).context("PTU records ended early"));
+                }
+            }
+            if let Some(n) = self.remaining.as_mut() {
+                *n -= 1;
+            }
+            let (channel, time) = match self.decode(u32::from_le_bytes(word)) {
+                Record::Photon(ch, time) => (Some(ch + 1), time),
+                Record::Sync(time) => (self.sync_channel, time),
+                Record::Marker(_, time) => (self.marker_channel, time),
+                Record::Overflow => (None, 0),
+            };
+            if let Some(channel) = channel {
+                return Some(Ok(Tag { time: self.to_tstep(time), channel }));
+            }
+        }
+    }
+}
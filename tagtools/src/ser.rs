@@ -1,10 +1,28 @@
-//! Serialization of time tag objects, supporting `.tags.zst` and `.tsv`
+//! Serialization of time tag objects, supporting `.tags.zst` and `.tsv`,
+//! Arrow IPC and Parquet files for dataframe tools, and NumPy `.npy` and `.npz`
 
+use crate::cfg::Metadata;
+use crate::delta;
+use crate::index::{Frame, Index};
+use crate::pat::Estimate;
 use crate::Tag;
-use anyhow::Result;
-use capnp::{message, serialize, serialize_packed};
+use anyhow::{bail, Result};
+#[cfg(feature = "columnar")]
+use arrow_array::{Int64Array, RecordBatch, UInt8Array};
+#[cfg(feature = "columnar")]
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+use capnp::{message, serialize, serialize_packed, Word};
+#[cfg(feature = "columnar")]
+use parquet::basic::Compression;
+#[cfg(feature = "columnar")]
+use parquet::file::properties::WriterProperties;
+use std::collections::BTreeMap;
 use std::io::Write;
-use tagger_capnp::tags_capnp::tags;
+#[cfg(feature = "columnar")]
+use std::str::FromStr;
+#[cfg(feature = "columnar")]
+use std::sync::Arc;
+use tagger_capnp::tags_capnp::{header, tags};
 use zstd::stream;
 
 /// Serialize to .tags.zst format: zstd-compressed Cap'n Proto tags
@@ -21,6 +39,283 @@ pub fn tags(wtr: &mut impl Write, tags: &[Tag]) -> Result<()> {
     Ok(())
 }
 
+/// Magic number of the skippable frame holding the metadata header
+pub const METADATA_MAGIC: u32 = 0x184D2A5B;
+
+/// Serialize run metadata as a Cap'n Proto `Header` message in a zstd
+/// skippable frame, which zstd decoders (and so `de::tags`) pass over. This
+/// must be the first thing written to a file for `de::metadata` to find it.
+pub fn metadata(wtr: &mut impl Write, meta: &Metadata) -> Result<()> {
+    let mut message = message::Builder::new_default();
+    let mut hdr = message.init_root::<header::Builder>();
+    hdr.set_version(&meta.version);
+    hdr.set_timestamp(&meta.timestamp.to_rfc3339());
+    hdr.set_resolution(meta.resolution);
+    hdr.set_run(&serde_json::to_string(&meta.run)?);
+    let mut css = hdr.init_channel_settings(meta.channel_settings.len() as u32);
+    for (i, cs) in meta.channel_settings.iter().enumerate() {
+        let mut csb = css.reborrow().get(i as u32);
+        csb.set_channel(cs.channel);
+        if let Some(inv) = cs.invert {
+            csb.set_has_invert(true);
+            csb.set_invert(inv);
+        }
+        if let Some(del) = cs.delay {
+            csb.set_has_delay(true);
+            csb.set_delay(del);
+        }
+        if let Some(th) = cs.threshold {
+            csb.set_has_threshold(true);
+            csb.set_threshold(th);
+        }
+    }
+    let mut payload = Vec::new();
+    serialize::write_message(&mut payload, &message)?;
+    wtr.write_all(&METADATA_MAGIC.to_le_bytes())?;
+    wtr.write_all(&(payload.len() as u32).to_le_bytes())?;
+    wtr.write_all(&payload)?;
+    Ok(())
+}
+
+/// Number of tags per message written by `TagWriter`
+pub const BATCH_DEFAULT: usize = 1 << 16;
+
+/// Long-lived serializer to .tags.zst format for saving data as it arrives
+///
+/// Where `tags` starts a new zstd frame and heap-allocates a new message on
+/// every call, a `TagWriter` keeps one zstd encoder open for the whole file
+/// (or, with `indexed`, one compression context) and builds every message in
+/// the same scratch space. Tags are buffered until
+/// `batch` of them can be written as one message. `flush` writes out whatever
+/// is buffered, so that everything written so far can be read back, and
+/// `finish` does the same and ends the zstd frame. Dropping a writer without
+/// calling `finish` loses any buffered tags.
+pub struct TagWriter<W: Write> {
+    out: Output<W>,
+    encoding: Encoding,
+    /// Allocator building every message in the same scratch space
+    scratch: Scratch,
+    buf: Vec<Tag>,
+    batch: usize,
+    /// Set once any tags have been written
+    started: bool,
+}
+
+/// How a `TagWriter` encodes each batch of tags
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub enum Encoding {
+    /// Cap'n Proto `Tags` messages, readable by every version of `de`
+    #[default]
+    Capnp,
+    /// `delta` blocks, smaller and faster to decode, readable by `de` since
+    /// they were introduced
+    Delta,
+}
+
+/// Where a `TagWriter` sends its messages
+enum Output<W: Write> {
+    /// All messages in one zstd frame
+    Stream(stream::write::Encoder<'static, W>),
+    /// One zstd frame per message, plus an index of the frames
+    Indexed {
+        wtr: W,
+        compressor: zstd::block::Compressor,
+        /// Uncompressed message, reused for every frame
+        raw: Vec<u8>,
+        /// Bytes written so far
+        offset: u64,
+        index: Index,
+    },
+}
+
+impl<W: Write> TagWriter<W> {
+    /// Write messages of `batch` tags to `wtr`
+    pub fn new(wtr: W, batch: usize) -> Result<Self> {
+        let out = Output::Stream(stream::write::Encoder::new(wtr, 0)?);
+        Ok(Self::with_output(out, batch))
+    }
+
+    /// Write messages of `batch` tags to `wtr`, each in its own zstd frame,
+    /// and end the file with an `index::Index` of the frames on `finish`. This
+    /// allows `de::tags_range` to read a time range without decompressing the
+    /// whole file, and stays readable by anything that reads unindexed files.
+    pub fn indexed(wtr: W, batch: usize) -> Result<Self> {
+        let out = Output::Indexed {
+            wtr,
+            compressor: zstd::block::Compressor::new(),
+            raw: Vec::new(),
+            offset: 0,
+            index: Index::default(),
+        };
+        Ok(Self::with_output(out, batch))
+    }
+
+    fn with_output(out: Output<W>, batch: usize) -> Self {
+        let batch = batch.max(1);
+        // Root pointer and struct, the outer list pointer, then per inner list
+        // a list pointer and tag word, and two words per tag (see `fillmsg`)
+        let lists = batch / ((1 << 28) - 1) + 1;
+        let words = 4 + 2 * lists + 2 * batch;
+        TagWriter {
+            out,
+            encoding: Encoding::Capnp,
+            scratch: Scratch::new(words),
+            buf: Vec::with_capacity(batch),
+            batch,
+            started: false,
+        }
+    }
+
+    /// Encode batches with `encoding` instead of as Cap'n Proto messages
+    pub fn encoding(mut self, encoding: Encoding) -> Self {
+        if encoding != Encoding::Capnp {
+            self.scratch = Scratch::new(0);
+        }
+        self.encoding = encoding;
+        self
+    }
+
+    /// Write run metadata at the start of the file (see `metadata`), which
+    /// must be done before writing any tags
+    pub fn write_metadata(&mut self, meta: &Metadata) -> Result<()> {
+        if self.started {
+            bail!("metadata must be written before any tags");
+        }
+        match &mut self.out {
+            // The encoder has no output until it is given data
+            Output::Stream(zwtr) => metadata(zwtr.get_mut(), meta)?,
+            Output::Indexed { wtr, offset, .. } => {
+                let mut buf = Vec::new();
+                metadata(&mut buf, meta)?;
+                wtr.write_all(&buf)?;
+                *offset += buf.len() as u64;
+            }
+        }
+        Ok(())
+    }
+
+    /// Buffer tags, writing a message whenever a batch is complete
+    pub fn write(&mut self, tags: &[Tag]) -> Result<()> {
+        self.started = true;
+        self.buf.extend_from_slice(tags);
+        if self.buf.len() < self.batch {
+            return Ok(());
+        }
+        let full = self.buf.len() / self.batch * self.batch;
+        for chunk in self.buf[..full].chunks(self.batch) {
+            self.out.write_batch(self.encoding, &mut self.scratch, chunk)?;
+        }
+        self.buf.drain(..full);
+        Ok(())
+    }
+
+    /// Write any buffered tags as a (short) message and flush the output
+    pub fn flush(&mut self) -> Result<()> {
+        self.started = true;
+        self.write_buffered()?;
+        match &mut self.out {
+            Output::Stream(zwtr) => zwtr.flush()?,
+            Output::Indexed { wtr, .. } => wtr.flush()?,
+        }
+        Ok(())
+    }
+
+    /// Write any buffered tags, end the zstd frame (or write the index) and
+    /// return the inner writer
+    pub fn finish(mut self) -> Result<W> {
+        self.write_buffered()?;
+        let wtr = match self.out {
+            Output::Stream(zwtr) => zwtr.finish()?,
+            Output::Indexed { mut wtr, index, .. } => {
+                index.write(&mut wtr)?;
+                wtr
+            }
+        };
+        Ok(wtr)
+    }
+
+    fn write_buffered(&mut self) -> Result<()> {
+        if !self.buf.is_empty() {
+            self.out.write_batch(self.encoding, &mut self.scratch, &self.buf)?;
+            self.buf.clear();
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Output<W> {
+    fn write_batch(&mut self, encoding: Encoding, scratch: &mut Scratch, tags: &[Tag]) -> Result<()> {
+        match self {
+            Output::Stream(zwtr) => write_batch(zwtr, encoding, scratch, tags)?,
+            Output::Indexed { wtr, compressor, raw, offset, index } => {
+                raw.clear();
+                write_batch(raw, encoding, scratch, tags)?;
+                let frame = compressor.compress(raw, 0)?;
+                wtr.write_all(&frame)?;
+                index.frames.push(Frame::new(*offset, frame.len() as u64, tags));
+                *offset += frame.len() as u64;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Word-aligned space for one message, owned by a `TagWriter` and reused for
+/// every message it builds. Like a `message::ScratchSpaceHeapAllocator` kept
+/// across messages, the space is zeroed in full only once, and afterwards just
+/// the words a message used are zeroed again. Messages that do not fit go to
+/// the heap.
+struct Scratch {
+    space: Vec<Word>,
+    /// Set while a message is using the space
+    allocated: bool,
+    heap: message::HeapAllocator,
+}
+
+impl Scratch {
+    fn new(words: usize) -> Self {
+        Scratch { space: Word::allocate_zeroed_vec(words), allocated: false, heap: message::HeapAllocator::new() }
+    }
+}
+
+unsafe impl message::Allocator for Scratch {
+    fn allocate_segment(&mut self, minimum_size: u32) -> (*mut u8, u32) {
+        if (minimum_size as usize) < self.space.len() && !self.allocated {
+            self.allocated = true;
+            (Word::words_to_bytes_mut(&mut self.space).as_mut_ptr(), self.space.len() as u32)
+        } else {
+            self.heap.allocate_segment(minimum_size)
+        }
+    }
+
+    fn deallocate_segment(&mut self, ptr: *mut u8, word_size: u32, words_used: u32) {
+        if ptr == Word::words_to_bytes_mut(&mut self.space).as_mut_ptr() {
+            Word::words_to_bytes_mut(&mut self.space[..words_used as usize]).fill(0);
+            self.allocated = false;
+        } else {
+            self.heap.deallocate_segment(ptr, word_size, words_used);
+        }
+    }
+}
+
+/// Encode one batch of tags and write it out, building Cap'n Proto messages
+/// in `scratch`
+fn write_batch(wtr: &mut impl Write, encoding: Encoding, scratch: &mut Scratch, tags: &[Tag]) -> Result<()> {
+    match encoding {
+        Encoding::Capnp => write_message(wtr, scratch, tags),
+        Encoding::Delta => delta::write_block(wtr, tags),
+    }
+}
+
+/// Build one tags message with the given allocator and write it out
+fn write_message<A>(wtr: &mut impl Write, allocator: &mut A, tags: &[Tag]) -> Result<()>
+where A: message::Allocator {
+    let mut message = message::Builder::new(allocator);
+    fillmsg(&mut message, tags);
+    serialize::write_message(wtr, &message)?;
+    Ok(())
+}
+
 /// Serialize to .tags: uncompressed, unpacked Cap'n Proto tags
 pub fn tags_uncompressed(wtr: &mut impl Write, tags: &[Tag]) -> Result<()> {
     let message = newmsg(&tags);
@@ -50,6 +345,263 @@ pub fn tsv(wtr: &mut csv::Writer<impl Write>, tags: &[Tag]) -> Result<()> {
     Ok(())
 }
 
+/// Columnar file formats for dataframe tools
+#[cfg(feature = "columnar")]
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum Columnar {
+    /// Arrow IPC file, as read by e.g. `polars.read_ipc`
+    Arrow,
+    /// Parquet file with Snappy-compressed columns
+    Parquet,
+}
+
+#[cfg(feature = "columnar")]
+impl FromStr for Columnar {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "arrow" => Ok(Columnar::Arrow),
+            "parquet" => Ok(Columnar::Parquet),
+            _ => bail!("unknown columnar format {:?}, expected arrow or parquet", s),
+        }
+    }
+}
+
+/// Arrow schema of tags: non-null columns `time` (Int64) and `channel` (UInt8)
+#[cfg(feature = "columnar")]
+pub fn tag_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("time", DataType::Int64, false),
+        Field::new("channel", DataType::UInt8, false),
+    ]))
+}
+
+/// Arrow record batch of tags, with `tag_schema`
+#[cfg(feature = "columnar")]
+pub fn record_batch(tags: &[Tag]) -> Result<RecordBatch> {
+    let time = Int64Array::from_iter_values(tags.iter().map(|t| t.time));
+    let channel = UInt8Array::from_iter_values(tags.iter().map(|t| t.channel));
+    Ok(RecordBatch::try_new(tag_schema(), vec![Arc::new(time), Arc::new(channel)])?)
+}
+
+/// Serializer to Arrow IPC or Parquet files, with tags in record batches
+///
+/// As with `TagWriter`, tags are buffered until `batch` of them can be written
+/// as one record batch (or, for Parquet, added to the current row group), and
+/// `finish` writes whatever is buffered and the file footer. Both formats end
+/// with a footer, so a file is unreadable until `finish` is called.
+#[cfg(feature = "columnar")]
+pub struct ColumnWriter<W: Write + Send> {
+    out: ColumnOutput<W>,
+    buf: Vec<Tag>,
+    batch: usize,
+}
+
+/// Where a `ColumnWriter` sends its batches
+#[cfg(feature = "columnar")]
+enum ColumnOutput<W: Write + Send> {
+    Arrow(arrow_ipc::writer::FileWriter<W>),
+    Parquet(parquet::arrow::ArrowWriter<W>),
+}
+
+#[cfg(feature = "columnar")]
+impl<W: Write + Send> ColumnWriter<W> {
+    /// Write record batches of `batch` tags to `wtr` in `format`
+    pub fn new(wtr: W, format: Columnar, batch: usize) -> Result<Self> {
+        let schema = tag_schema();
+        let out = match format {
+            Columnar::Arrow => ColumnOutput::Arrow(arrow_ipc::writer::FileWriter::try_new(wtr, &schema)?),
+            Columnar::Parquet => {
+                let props = WriterProperties::builder()
+                    .set_compression(Compression::SNAPPY)
+                    .build();
+                ColumnOutput::Parquet(parquet::arrow::ArrowWriter::try_new(wtr, schema, Some(props))?)
+            }
+        };
+        let batch = batch.max(1);
+        Ok(ColumnWriter { out, buf: Vec::with_capacity(batch), batch })
+    }
+
+    /// Buffer tags, writing a record batch whenever one is complete
+    pub fn write(&mut self, tags: &[Tag]) -> Result<()> {
+        self.buf.extend_from_slice(tags);
+        if self.buf.len() < self.batch {
+            return Ok(());
+        }
+        let full = self.buf.len() / self.batch * self.batch;
+        for chunk in self.buf[..full].chunks(self.batch) {
+            self.out.write(chunk)?;
+        }
+        self.buf.drain(..full);
+        Ok(())
+    }
+
+    /// Write any buffered tags and the footer, and return the inner writer
+    pub fn finish(mut self) -> Result<W> {
+        if !self.buf.is_empty() {
+            self.out.write(&self.buf)?;
+        }
+        let wtr = match self.out {
+            ColumnOutput::Arrow(mut wtr) => {
+                wtr.finish()?;
+                wtr.into_inner()?
+            }
+            ColumnOutput::Parquet(wtr) => wtr.into_inner()?,
+        };
+        Ok(wtr)
+    }
+}
+
+#[cfg(feature = "columnar")]
+impl<W: Write + Send> ColumnOutput<W> {
+    fn write(&mut self, tags: &[Tag]) -> Result<()> {
+        let batch = record_batch(tags)?;
+        match self {
+            ColumnOutput::Arrow(wtr) => wtr.write(&batch)?,
+            ColumnOutput::Parquet(wtr) => wtr.write(&batch)?,
+        }
+        Ok(())
+    }
+}
+
+/// One-dimensional array to write in NumPy's `.npy` format
+#[derive(Clone, Copy, Debug)]
+pub enum NpyArray<'a> {
+    I64(&'a [i64]),
+    U64(&'a [u64]),
+    F64(&'a [f64]),
+    /// Structured array with fields `time` (`<i8`) and `channel` (`u1`)
+    Tags(&'a [Tag]),
+}
+
+impl NpyArray<'_> {
+    fn descr(&self) -> &'static str {
+        match self {
+            NpyArray::I64(_) => "'<i8'",
+            NpyArray::U64(_) => "'<u8'",
+            NpyArray::F64(_) => "'<f8'",
+            NpyArray::Tags(_) => "[('time', '<i8'), ('channel', '|u1')]",
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            NpyArray::I64(a) => a.len(),
+            NpyArray::U64(a) => a.len(),
+            NpyArray::F64(a) => a.len(),
+            NpyArray::Tags(a) => a.len(),
+        }
+    }
+}
+
+/// Serialize an array to NumPy's `.npy` format (version 1.0), as read by
+/// `numpy.load`
+///
+/// The header is a Python dict literal giving the dtype and shape, padded so
+/// that the data starts on a 64-byte boundary; the data follows in C order.
+pub fn npy(wtr: &mut impl Write, array: NpyArray) -> Result<()> {
+    let mut header = format!(
+        "{{'descr': {}, 'fortran_order': False, 'shape': ({},), }}",
+        array.descr(),
+        array.len(),
+    );
+    // Magic, version, and header length take 10 bytes, and the header ends
+    // with a newline
+    let pad = (64 - (10 + header.len() + 1) % 64) % 64;
+    header.extend(std::iter::repeat_n(' ', pad));
+    header.push('\n');
+    wtr.write_all(b"\x93NUMPY\x01\x00")?;
+    wtr.write_all(&(header.len() as u16).to_le_bytes())?;
+    wtr.write_all(header.as_bytes())?;
+
+    let mut buf = Vec::with_capacity(array.len() * 9);
+    match array {
+        NpyArray::I64(a) => a.iter().for_each(|x| buf.extend_from_slice(&x.to_le_bytes())),
+        NpyArray::U64(a) => a.iter().for_each(|x| buf.extend_from_slice(&x.to_le_bytes())),
+        NpyArray::F64(a) => a.iter().for_each(|x| buf.extend_from_slice(&x.to_le_bytes())),
+        // Structured arrays are packed, with no padding between fields
+        NpyArray::Tags(a) => a.iter().for_each(|t| {
+            buf.extend_from_slice(&t.time.to_le_bytes());
+            buf.push(t.channel);
+        }),
+    }
+    wtr.write_all(&buf)?;
+    Ok(())
+}
+
+/// Serialize named arrays to NumPy's `.npz` format, an uncompressed zip
+/// archive of `.npy` files, as read by `numpy.load`
+pub fn npz(wtr: &mut impl Write, arrays: &[(&str, NpyArray)]) -> Result<()> {
+    // Zip fields for a stored (uncompressed) entry, dated 1980-01-01
+    const VERSION: u16 = 20;
+    const DATE: u16 = 0x21;
+    let mut central = Vec::new();
+    let mut offset: u64 = 0;
+    for (name, array) in arrays {
+        let name = format!("{}.npy", name);
+        let mut data = Vec::new();
+        npy(&mut data, *array)?;
+        if offset + data.len() as u64 > u32::MAX as u64 {
+            bail!("npz files over 4 GiB are not supported");
+        }
+        let crc = crc32fast::hash(&data);
+        let mut fields = Vec::new();
+        for x in [VERSION, 0, 0, 0, DATE] {
+            fields.extend_from_slice(&x.to_le_bytes());
+        }
+        for x in [crc, data.len() as u32, data.len() as u32] {
+            fields.extend_from_slice(&x.to_le_bytes());
+        }
+        fields.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        fields.extend_from_slice(&0u16.to_le_bytes());
+
+        wtr.write_all(&0x04034b50u32.to_le_bytes())?;
+        wtr.write_all(&fields)?;
+        wtr.write_all(name.as_bytes())?;
+        wtr.write_all(&data)?;
+
+        central.extend_from_slice(&0x02014b50u32.to_le_bytes());
+        central.extend_from_slice(&VERSION.to_le_bytes());
+        central.extend_from_slice(&fields);
+        // Comment length, disk, and internal and external attributes
+        central.extend_from_slice(&[0; 10]);
+        central.extend_from_slice(&(offset as u32).to_le_bytes());
+        central.extend_from_slice(name.as_bytes());
+        offset += (30 + name.len() + data.len()) as u64;
+    }
+    wtr.write_all(&central)?;
+    wtr.write_all(&0x06054b50u32.to_le_bytes())?;
+    wtr.write_all(&[0; 4])?;
+    wtr.write_all(&(arrays.len() as u16).to_le_bytes())?;
+    wtr.write_all(&(arrays.len() as u16).to_le_bytes())?;
+    wtr.write_all(&(central.len() as u32).to_le_bytes())?;
+    wtr.write_all(&(offset as u32).to_le_bytes())?;
+    wtr.write_all(&0u16.to_le_bytes())?;
+    Ok(())
+}
+
+/// Serialize a coincidence histogram (see `pat::coincidence_histogram`) to
+/// `.npz`, with arrays `delay` and `counts`
+pub fn npz_histogram(wtr: &mut impl Write, histogram: &BTreeMap<i64, u64>) -> Result<()> {
+    let delay: Vec<i64> = histogram.keys().copied().collect();
+    let counts: Vec<u64> = histogram.values().copied().collect();
+    npz(wtr, &[("delay", NpyArray::I64(&delay)), ("counts", NpyArray::U64(&counts))])
+}
+
+/// Serialize g2 with its standard errors (see `pat::g2`) to `.npz`, with
+/// arrays `delay`, `g2`, and `err`
+pub fn npz_g2(wtr: &mut impl Write, g2: &BTreeMap<i64, Estimate>) -> Result<()> {
+    let delay: Vec<i64> = g2.keys().copied().collect();
+    let value: Vec<f64> = g2.values().map(|g| g.value).collect();
+    let err: Vec<f64> = g2.values().map(|g| g.err).collect();
+    npz(wtr, &[
+        ("delay", NpyArray::I64(&delay)),
+        ("g2", NpyArray::F64(&value)),
+        ("err", NpyArray::F64(&err)),
+    ])
+}
+
 /// Allocate and build a new message; return a pointer to it
 #[inline(always)]
 pub fn newmsg(tags: &[Tag]) -> message::Builder<message::HeapAllocator> {
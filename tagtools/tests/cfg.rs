@@ -139,4 +139,43 @@ fn de_complex() {
     };
 
     assert_eq!(r, de);
-}
\ No newline at end of file
+}
+
+#[test]
+fn deserialize_subtracted_coincidences() {
+    let x = r#"{
+        "coincidences": [
+            {"channels_win_subtracted": [1, 2, 8]},
+            {"channels_counts_subtracted": [1, 2, 8, 100, 96.5]}
+        ]
+    }"#;
+
+    let de: Run = serde_json::from_str(x).unwrap();
+
+    let r = Run {
+        coincidences: vec![
+            Coincidence::ChannelsWinSubtracted((1, 2, 8)),
+            Coincidence::ChannelsCountsSubtracted((1, 2, 8, 100, 96.5)),
+        ],
+        ..Default::default()
+    };
+
+    assert_eq!(r, de);
+    assert_eq!(r, deserialize_config(&serialize_config(&r)));
+}
+
+#[test]
+fn duration_from_run_record() {
+    let path = std::env::temp_dir().join(format!("tagtools-run-{}.json", std::process::id()));
+    std::fs::write(&path, r#"{"duration": 200000000}"#).unwrap();
+    let run_path = path.to_str().unwrap();
+    assert_eq!(Run::load(&path).unwrap().duration, Some(200000000));
+    assert_eq!(Run::duration_of(None, Some(run_path)).unwrap(), Some(200000000));
+    assert_eq!(Run::duration_of(Some(5), None).unwrap(), Some(5));
+    assert_eq!(Run::duration_of(None, None).unwrap(), None);
+    assert!(Run::duration_of(Some(5), Some(run_path)).is_err());
+
+    std::fs::write(&path, "{}").unwrap();
+    assert!(Run::duration_of(None, Some(run_path)).is_err());
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,86 @@
+use tagtools::clock::ClockRecovery;
+use tagtools::{pat, Tag};
+
+#[allow(dead_code)]
+mod common;
+
+const PERIOD: f64 = 80.;
+const PHASE: f64 = 30.;
+
+/// Tags of a reference clock on channel 1 every `divider` pulses, and a
+/// detector on channel 2 at a fixed phase of every 7th pulse, in a tagger
+/// timebase that drifts relative to the pulses
+fn drifting_tags(pulses: i64, divider: i64) -> Vec<Tag> {
+    let tagger_time = |t: f64| t * (1. + 1e-4 * (t * 2. * std::f64::consts::PI / 2e6).sin());
+    let mut rng = common::Lcg::new(3);
+    let mut jitter = || (rng.next_u64() >> 62) as f64 - 1.5;
+    let mut tags = Vec::new();
+    for k in 0..pulses {
+        let start = 1000. + k as f64 * PERIOD;
+        if k % divider == 0 {
+            tags.push(Tag { time: (tagger_time(start) + jitter()).round() as i64, channel: 1 });
+        }
+        if k % 7 == 0 {
+            tags.push(Tag { time: tagger_time(start + PHASE).round() as i64, channel: 2 });
+        }
+    }
+    tags.sort();
+    tags
+}
+
+/// Fraction of the detector tags within `tolerance` of their phase in pulses
+/// of the nominal period
+fn in_phase(tags: impl IntoIterator<Item = Tag>, offset: i64, tolerance: i64) -> f64 {
+    let clock = pat::PulseClock::Fixed { period: PERIOD, offset };
+    let phases: Vec<i64> = pat::fold_pulses(tags, clock)
+        .filter(|p| p.tag.channel == 2)
+        .map(|p| p.phase)
+        .collect();
+    let close = phases.iter().filter(|&&p| (p - PHASE as i64).abs() <= tolerance).count();
+    close as f64 / phases.len() as f64
+}
+
+#[test]
+fn corrected_timebase_keeps_phase() {
+    for divider in [1, 10] {
+        let tags = drifting_tags(100_000, divider);
+        // The drift smears out the phase of the raw tags
+        assert!(in_phase(tags.iter().copied(), 1000, 4) < 0.5);
+
+        let recovery = ClockRecovery::new(1).divider(divider as u32).period(PERIOD);
+        let corrected: Vec<Tag> = recovery.apply(tags.iter().copied()).collect();
+        assert!(corrected.windows(2).all(|w| w[0].time <= w[1].time));
+        let offset = corrected.iter().find(|t| t.channel == 1).unwrap().time;
+        let fraction = in_phase(corrected.iter().copied(), offset, 4);
+        assert!(fraction > 0.99, "{} with divider {}", fraction, divider);
+    }
+}
+
+#[test]
+fn tracked_period_follows_drift() {
+    let tags = drifting_tags(20_000, 1);
+    let mut recovering = ClockRecovery::new(1).gain(0.03).start();
+    for t in tags.iter().copied().take_while(|t| t.time < 1_000_000) {
+        recovering.correct(t);
+    }
+    // The period in the tagger timebase scales with the slope of its drift
+    let (w, t) = (2. * std::f64::consts::PI / 2e6, 1e6);
+    let expected = PERIOD * (1. + 1e-4 * ((w * t).sin() + w * t * (w * t).cos()));
+    let period = recovering.tracked_period().unwrap();
+    assert!((period / expected - 1.).abs() < 2e-5, "{} vs {}", period, expected);
+}
+
+/// Tags before two clock tags have been seen can't be corrected
+#[test]
+fn needs_two_clock_tags() {
+    let tag = |(time, channel)| Tag { time, channel };
+    let tags = [(0, 2), (10, 1), (20, 2), (90, 1), (100, 2)].map(tag);
+    let corrected: Vec<Tag> = ClockRecovery::new(1).period(160.).apply(tags).collect();
+    assert_eq!(corrected, [(170, 1), (190, 2)].map(tag));
+}
+
+#[test]
+#[should_panic(expected = "clock divider must be positive")]
+fn zero_divider() {
+    ClockRecovery::new(1).divider(0);
+}
@@ -46,4 +46,25 @@ pub fn load_g2_histogram() -> Vec<Bin<f64, f64>> {
         .from_reader(rdr);
     let hist: Vec<Bin<f64, f64>> = de::histogram_tsv(&mut crdr).unwrap();
     return hist;
-}
\ No newline at end of file
+}
+
+/// Seeded pseudorandom numbers for test fixtures, from a 64-bit linear
+/// congruential generator (Knuth's MMIX constants)
+pub struct Lcg(u64);
+
+impl Lcg {
+    pub fn new(seed: u64) -> Self {
+        Lcg(seed)
+    }
+
+    /// Next state, whose high bits are the most random
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        self.0
+    }
+
+    /// Uniform in (0, 1), so safe to take the logarithm of
+    pub fn uniform(&mut self) -> f64 {
+        ((self.next_u64() >> 11) as f64 + 0.5) / (1u64 << 53) as f64
+    }
+}
@@ -0,0 +1,76 @@
+use tagtools::filter::Filter;
+use tagtools::{pat, Tag};
+
+#[allow(dead_code)]
+mod common;
+
+fn tags(pairs: &[(i64, u8)]) -> Vec<Tag> {
+    pairs.iter().map(|&(time, channel)| Tag { time, channel }).collect()
+}
+
+#[test]
+fn empty_filter_keeps_everything() {
+    let tags = common::load_test_data();
+    let filter = Filter::new();
+    assert!(filter.is_empty());
+    assert_eq!(tags, filter.apply(tags.iter().copied()).collect::<Vec<_>>());
+}
+
+#[test]
+fn channels_and_time_range() {
+    let tags = common::load_test_data();
+    let (from, to) = (tags[1000].time, tags[200_000].time);
+    let kept: Vec<Tag> = Filter::new()
+        .channels(&[3, 15])
+        .from(from)
+        .to(to)
+        .apply(tags.iter().copied())
+        .collect();
+    let expected: Vec<Tag> = tags
+        .iter()
+        .copied()
+        .filter(|t| (t.channel == 3 || t.channel == 15) && t.time >= from && t.time < to)
+        .collect();
+    assert!(!expected.is_empty());
+    assert_eq!(expected, kept);
+}
+
+#[test]
+fn gate_and_veto() {
+    let tags = tags(&[(0, 2), (100, 1), (105, 2), (110, 3), (120, 2), (200, 1), (250, 2), (260, 2)]);
+
+    // Within [5, 15) after channel 1, triggers kept
+    let gated: Vec<Tag> = Filter::new().gate(1, 5, 10).apply(tags.iter().copied()).collect();
+    assert_eq!(gated, self::tags(&[(100, 1), (105, 2), (110, 3), (200, 1)]));
+
+    // The complement, apart from the triggers
+    let vetoed: Vec<Tag> = Filter::new().veto(1, 5, 10).apply(tags.iter().copied()).collect();
+    assert_eq!(vetoed, self::tags(&[(0, 2), (100, 1), (120, 2), (200, 1), (250, 2), (260, 2)]));
+
+    // Windows after successive triggers overlap
+    let gated: Vec<Tag> = Filter::new().gate(1, 0, 60).channels(&[2]).apply(tags.iter().copied()).collect();
+    assert_eq!(gated, self::tags(&[(105, 2), (120, 2), (250, 2)]));
+}
+
+#[test]
+fn dead_time_matches_pat() {
+    let tags = common::load_test_data();
+    let filtered: Vec<Tag> = Filter::new().dead_time(640).apply(tags.iter().copied()).collect();
+    let expected: Vec<Tag> = pat::dead_time_filter(tags.iter().copied(), 640).collect();
+    assert!(expected.len() < tags.len());
+    assert_eq!(expected, filtered);
+}
+
+/// Filtering chunk by chunk with one state is the same as filtering the stream
+#[test]
+fn chunked_filtering() {
+    let tags = common::load_test_data();
+    let filter = Filter::new().gate(3, 0, 64).veto(15, 0, 8).channels(&[3, 4, 15]);
+    let streamed: Vec<Tag> = filter.apply(tags.iter().copied()).collect();
+    let mut filtering = filter.start();
+    let mut chunked = Vec::new();
+    for chunk in tags.chunks(7919) {
+        chunked.extend(chunk.iter().copied().filter(|t| filtering.keep(t)));
+    }
+    assert_eq!(streamed, chunked);
+}
@@ -0,0 +1,87 @@
+use tagtools::cfg::Run;
+use tagtools::fit::{self, Model, Point};
+
+#[allow(dead_code)]
+mod common;
+
+/// Points of `model` with `params` at `xs`, with deterministic noise of about
+/// the Poisson size
+fn noisy_points(model: Model, params: [f64; 4], xs: impl Iterator<Item = f64>) -> Vec<Point> {
+    let mut rng = common::Lcg::new(11);
+    xs.map(|x| {
+        let y = model.eval(&params, x);
+        // Sum of uniforms has unit variance when scaled by 2
+        let noise = (0..6).map(|_| rng.uniform() - 0.5).sum::<f64>() * 2f64.sqrt();
+        Point { x, y: y + noise * y.sqrt(), err: y.sqrt() }
+    })
+    .collect()
+}
+
+#[test]
+fn fit_models() {
+    for (model, params) in [
+        (Model::Gaussian, [10_000., 0.9, 1.5, 0.8]),
+        (Model::Sinc, [5_000., 0.7, -2., 1.2]),
+        (Model::Fringe, [2_000., 0.95, 0.3, 2.5]),
+    ] {
+        let xs = (0..81).map(|i| -6. + i as f64 * 0.15);
+        let points = noisy_points(model, params, xs);
+        let fit = fit::fit(model, &points).unwrap();
+        assert_eq!(77, fit.dof);
+        assert!(fit.chi_squared / (fit.dof as f64) < 2., "{:?}", fit);
+        for (estimate, truth) in [fit.amplitude, fit.visibility, fit.center, fit.width].iter().zip(params) {
+            assert!(estimate.err > 0., "{:?}", fit);
+            assert!((estimate.value - truth).abs() < 4. * estimate.err, "{:?} vs {:?}", fit, params);
+        }
+        for p in &points {
+            assert!((fit.eval(p.x) - p.y).abs() < 5. * p.err);
+        }
+    }
+}
+
+#[test]
+fn fit_needs_points() {
+    let points = noisy_points(Model::Gaussian, [100., 0.5, 0., 1.], (0..4).map(f64::from));
+    assert!(fit::fit(Model::Gaussian, &points).is_err());
+}
+
+#[test]
+fn scan_points_from_runs() {
+    let run = |json: &str| -> Run { serde_json::from_str(json).unwrap() };
+    let runs = vec![
+        (0.5, run(r#"{"duration": 200000000, "coincidences": [
+            {"channels_counts": [1, 3, 1, 10]}, {"channels_counts": [2, 1, 1, 400]}
+        ]}"#)),
+        (1.0, run(r#"{"duration": 100000000, "coincidences": [
+            {"channels_counts_subtracted": [1, 2, 1, 100, 91.5]}
+        ]}"#)),
+    ];
+
+    let points = fit::scan_points(&runs, 1, 2, None, false, false).unwrap();
+    assert_eq!(points, [Point { x: 0.5, y: 400., err: 20. }, Point { x: 1.0, y: 100., err: 10. }]);
+
+    // Rates in runs of 1 s and 0.5 s
+    let points = fit::scan_points(&runs, 1, 2, None, false, true).unwrap();
+    assert_eq!(points, [Point { x: 0.5, y: 400., err: 20. }, Point { x: 1.0, y: 200., err: 20. }]);
+
+    // Only the second run recorded subtracted counts
+    assert!(fit::scan_points(&runs, 1, 2, None, true, false).is_err());
+    let points = fit::scan_points(&runs[1..], 2, 1, None, true, false).unwrap();
+    assert_eq!(points, [Point { x: 1.0, y: 91.5, err: 10. }]);
+
+    // The same pair in several windows must be told apart
+    let runs = vec![(0., run(r#"{"coincidences": [
+        {"channels_counts": [1, 2, 4, 100]},
+        {"channels_counts": [1, 2, 16, 300]},
+        {"channels_counts_subtracted": [1, 2, 16, 300, 250.0]}
+    ]}"#))];
+    assert!(fit::scan_points(&runs, 1, 2, None, false, false).is_err());
+    let points = fit::scan_points(&runs, 1, 2, Some(4), false, false).unwrap();
+    assert_eq!(points, [Point { x: 0., y: 100., err: 10. }]);
+    let points = fit::scan_points(&runs, 1, 2, Some(16), false, false).unwrap();
+    assert_eq!(points, [Point { x: 0., y: 300., err: 300f64.sqrt() }]);
+    // Subtracted counts were only recorded in one window
+    let points = fit::scan_points(&runs, 1, 2, None, true, false).unwrap();
+    assert_eq!(points, [Point { x: 0., y: 250., err: 300f64.sqrt() }]);
+    assert!(fit::scan_points(&runs, 1, 2, Some(8), false, false).is_err());
+}
@@ -0,0 +1,72 @@
+use std::io::Cursor;
+use tagtools::index::Index;
+use tagtools::{de, ser, Tag};
+
+fn tags() -> Vec<Tag> {
+    (0..10_000)
+        .map(|i| Tag { time: 7 * i + i % 3, channel: (i % 5 + 1) as u8 })
+        .collect()
+}
+
+fn indexed(tags: &[Tag], batch: usize) -> Vec<u8> {
+    let mut wtr = ser::TagWriter::indexed(Vec::new(), batch).unwrap();
+    for chunk in tags.chunks(333) {
+        wtr.write(chunk).unwrap();
+    }
+    wtr.finish().unwrap()
+}
+
+/// Indexed files remain readable as plain .tags.zst
+#[test]
+fn indexed_readable_unindexed() {
+    let tags = tags();
+    let b = indexed(&tags, 1000);
+    assert_eq!(tags, de::tags(&*b).unwrap());
+    let chunks: Vec<Vec<Tag>> = de::tag_chunks(&*b)
+        .unwrap()
+        .collect::<anyhow::Result<_>>()
+        .unwrap();
+    assert_eq!(10, chunks.len());
+}
+
+/// The index describes every frame
+#[test]
+fn index_frames() {
+    let tags = tags();
+    let b = indexed(&tags, 1000);
+    let index = Index::read(&mut Cursor::new(&b)).unwrap().unwrap();
+    assert_eq!(10, index.frames.len());
+    let mut offset = 0;
+    for (frame, chunk) in index.frames.iter().zip(tags.chunks(1000)) {
+        assert_eq!(offset, frame.offset);
+        assert_eq!(chunk[0].time, frame.first);
+        assert_eq!(chunk[999].time, frame.last);
+        assert_eq!(1000, frame.counts.iter().sum::<u64>());
+        offset += frame.size;
+    }
+    assert_eq!([2000, 2000, 2000, 2000, 2000], index.counts()[..5]);
+
+    let mut plain = Vec::new();
+    ser::tags(&mut plain, &tags).unwrap();
+    assert_eq!(None, Index::read(&mut Cursor::new(&plain)).unwrap());
+}
+
+/// Time ranges read the same tags from indexed and unindexed files
+#[test]
+fn tags_range_indexed_vs_unindexed() {
+    let tags = tags();
+    let b = indexed(&tags, 1000);
+    let mut plain = Vec::new();
+    for chunk in tags.chunks(500) {
+        ser::tags(&mut plain, chunk).unwrap();
+    }
+    for (t0, t1) in [(0, 1), (-100, 5), (6999, 7001), (12_345, 54_321), (0, 70_000), (69_990, 80_000), (80_000, 90_000)] {
+        let expected: Vec<Tag> = tags
+            .iter()
+            .copied()
+            .filter(|t| t.time >= t0 && t.time < t1)
+            .collect();
+        assert_eq!(expected, de::tags_range(Cursor::new(&b), t0, t1).unwrap());
+        assert_eq!(expected, de::tags_range(Cursor::new(&plain), t0, t1).unwrap());
+    }
+}
@@ -1,4 +1,4 @@
-use tagtools::{pat, Tag};
+use tagtools::{bit, de, pat, ser, Tag, COUNTER_STEP, TSTEP};
 
 mod common;
 
@@ -52,6 +52,42 @@ fn coincidence_histogram_vs_other_code() {
     }
 }
 
+/// Stream tags from a multi-message .tags.zst buffer into the histogram
+#[test]
+fn coincidence_histogram_iter_streaming() {
+    let tags = common::load_test_data();
+    let mut b: Vec<u8> = Vec::new();
+    for chunk in tags.chunks(10_000) {
+        ser::tags(&mut b, chunk).unwrap();
+    }
+    for win in [1, 3] {
+        let histogram = pat::coincidence_histogram(&tags, 3, 15, win, -64, 64);
+        let streamed = itertools::process_results(de::tags_iter(&*b).unwrap(), |tags| {
+            pat::coincidence_histogram_iter(tags, 3, 15, win, -64, 64)
+        })
+        .unwrap();
+        assert_eq!(histogram, streamed);
+    }
+}
+
+/// The single-pass engine must agree with one histogram per pair.
+#[test]
+fn coincidence_histograms_vs_single() {
+    let tags = common::load_test_data();
+    let mut specs = Vec::new();
+    for (ch_a, ch_b) in [(3, 15), (15, 3), (3, 3), (1, 15), (2, 4)] {
+        for (win, min_delay, max_delay) in [(1, -64, 64), (3, -20, 40), (2, 5, 30), (1, -30, -4), (4, 0, 0)] {
+            specs.push(pat::HistogramSpec { ch_a, ch_b, win, min_delay, max_delay });
+        }
+    }
+    let histograms = pat::coincidence_histograms(tags.iter().copied(), &specs);
+    assert_eq!(histograms.len(), specs.len());
+    for (s, h) in specs.iter().zip(&histograms) {
+        let single = pat::coincidence_histogram(&tags, s.ch_a, s.ch_b, s.win, s.min_delay, s.max_delay);
+        assert_eq!(&single, h, "{:?}", s);
+    }
+}
+
 /// Compare calculation of g2 against results from known-good code.
 #[test]
 fn g2_histogram_vs_other_code() {
@@ -284,3 +320,610 @@ fn coincidence_intersection_vs_histogram_delay_win() {
         }
     }
 }
+
+/// Compare two-channel n-fold coincidences against the pairwise algorithm
+#[test]
+fn coincidence_nfold_vs_intersection() {
+    let tags = common::load_test_data();
+    let patmask = bit::chans_to_mask(&[3, 15]);
+    for win in [1, 2, 3, 4] {
+        for delay in (0..=64).step_by(win as usize) {
+            assert_eq!(
+                pat::coincidence_intersection(&tags, 3, 15, win, delay),
+                pat::coincidence_nfold(&tags, patmask, win, &[0, delay]),
+            );
+        }
+    }
+}
+
+/// Count three- and four-fold coincidences in synthetic data with known delays
+#[test]
+fn coincidence_nfold_synthetic() {
+    let mut tags = Vec::new();
+    for i in 0..1000i64 {
+        let t0 = 1000 * i;
+        // Every event on channel 1 has a partner on channel 2 at +10
+        tags.push(Tag { time: t0, channel: 1 });
+        tags.push(Tag { time: t0 + 10, channel: 2 });
+        // Every other event has a partner on channel 5 at +20
+        if i % 2 == 0 {
+            tags.push(Tag { time: t0 + 20, channel: 5 });
+        }
+        // Every fifth event has a partner on channel 7 at -3
+        if i % 5 == 0 {
+            tags.push(Tag { time: t0 - 3, channel: 7 });
+        }
+    }
+    tags.sort();
+
+    let pair = bit::chans_to_mask(&[1, 2]);
+    let triple = bit::chans_to_mask(&[1, 2, 5]);
+    let quad = bit::chans_to_mask(&[1, 2, 5, 7]);
+
+    assert_eq!(1000, pat::coincidence_nfold(&tags, pair, 1, &[0, 10]));
+    assert_eq!(500, pat::coincidence_nfold(&tags, triple, 1, &[0, 10, 20]));
+    assert_eq!(100, pat::coincidence_nfold(&tags, quad, 1, &[0, 10, 20, -3]));
+    // Wrong delay on one channel
+    assert_eq!(0, pat::coincidence_nfold(&tags, triple, 1, &[0, 10, 19]));
+    // Window wide enough to catch the wrong delay
+    assert_eq!(500, pat::coincidence_nfold(&tags, triple, 40, &[0, 10, 19]));
+}
+
+/// Patterns counted in one pass match the per-pattern algorithms
+#[test]
+fn pattern_counts_vs_nfold() {
+    let tags = common::load_test_data();
+    let mut patterns = Vec::new();
+    for win in [1, 2, 7] {
+        for chans in [&[3][..], &[15], &[3, 15], &[3, 15, 16], &[1, 3, 15]] {
+            patterns.push((bit::chans_to_mask(chans), win));
+        }
+    }
+    let counts = pat::pattern_counts(tags.iter().copied(), &patterns);
+    for (&(mask, win), count) in patterns.iter().zip(counts) {
+        let expected = match bit::mask_to_chans(mask)[..] {
+            [ch] => pat::singles(&tags, ch),
+            [ch_a, ch_b] => pat::coincidence(&tags, ch_a, ch_b, win, 0),
+            ref chans => pat::coincidence_nfold(&tags, mask, win, &vec![0; chans.len()]),
+        };
+        assert_eq!(expected, count, "mask {:#b} win {}", mask, win);
+    }
+    assert!(pat::coincidence(&tags, 3, 15, 2, 0) > 0);
+}
+
+/// Multi-stop lifetime histogram is the non-negative half of the coincidence histogram
+#[test]
+fn lifetime_histogram_vs_coincidence_histogram() {
+    let tags = common::load_test_data();
+    let lifetime = pat::lifetime_histogram(&tags, 3, &[15], 1, 64, true);
+    let histogram = pat::coincidence_histogram(&tags, 3, 15, 1, -64, 64);
+    assert_eq!(65, lifetime.len());
+    for (d, c) in lifetime {
+        assert_eq!(histogram[&d], c, "delay {}", d);
+    }
+}
+
+/// Wider bins sum the unit bins, and several stop channels sum each channel
+#[test]
+fn lifetime_histogram_bins_and_stops() {
+    let tags = common::load_test_data();
+    let unit = pat::lifetime_histogram(&tags, 3, &[15], 1, 127, true);
+    for bin in [2, 4, 8] {
+        let binned = pat::lifetime_histogram(&tags, 3, &[15], bin, 127, true);
+        assert_eq!(128 / bin as usize, binned.len());
+        for (&d, &c) in &binned {
+            assert_eq!(unit.range(d..d + bin).map(|(_, &c)| c).sum::<u64>(), c);
+        }
+    }
+
+    let h_15 = pat::lifetime_histogram(&tags, 3, &[15], 1, 64, true);
+    let h_16 = pat::lifetime_histogram(&tags, 3, &[16], 1, 64, true);
+    let both = pat::lifetime_histogram(&tags, 3, &[15, 16], 1, 64, true);
+    for (d, c) in both {
+        assert_eq!(h_15[&d] + h_16[&d], c);
+    }
+}
+
+/// Single-stop counts at most one stop per sync, and never more than multi-stop
+#[test]
+fn lifetime_histogram_single_stop() {
+    let tags = common::load_test_data();
+    let single = pat::lifetime_histogram(&tags, 3, &[15], 1, 640, false);
+    let multi = pat::lifetime_histogram(&tags, 3, &[15], 1, 640, true);
+    assert!(single.values().sum::<u64>() <= pat::singles(&tags, 3));
+    assert!(single.values().sum::<u64>() <= multi.values().sum::<u64>());
+    for (d, c) in &single {
+        assert!(c <= &multi[d]);
+    }
+
+    let streamed = pat::lifetime_histogram_iter(tags.iter().copied(), 3, &[15], 1, 640, false);
+    assert_eq!(single, streamed);
+}
+
+/// A long run of sync tags without stops, as with a dark detector, leaves only
+/// the last syncs in range of a final stop
+#[test]
+fn lifetime_histogram_sync_only() {
+    let syncs = (0..1_000_000i64).map(|i| Tag { time: 100 * i, channel: 1 });
+    let histogram = pat::lifetime_histogram_iter(syncs.clone(), 1, &[2], 10, 990, true);
+    assert_eq!(100, histogram.len());
+    assert_eq!(0, histogram.values().sum::<u64>());
+
+    let stop = Tag { time: 100 * 1_000_000, channel: 2 };
+    let histogram = pat::lifetime_histogram_iter(syncs.chain([stop]), 1, &[2], 10, 990, true);
+    assert_eq!(9, histogram.values().sum::<u64>());
+    for d in (100..=900).step_by(100) {
+        assert_eq!(1, histogram[&d]);
+    }
+}
+
+/// Recover a known delay distribution from synthetic start-stop data
+#[test]
+fn lifetime_histogram_synthetic() {
+    let mut tags = Vec::new();
+    for i in 0..1000i64 {
+        let t0 = 1000 * i;
+        tags.push(Tag { time: t0, channel: 1 });
+        // Two stops per sync, at delays 10 + i % 5 and 200
+        tags.push(Tag { time: t0 + 10 + i % 5, channel: 2 });
+        tags.push(Tag { time: t0 + 200, channel: 2 });
+    }
+    tags.sort();
+
+    let single = pat::lifetime_histogram(&tags, 1, &[2], 1, 999, false);
+    for d in 10..15 {
+        assert_eq!(200, single[&d]);
+    }
+    assert_eq!(0, single[&200]);
+    assert_eq!(1000, single.values().sum::<u64>());
+
+    let multi = pat::lifetime_histogram(&tags, 1, &[2], 1, 999, true);
+    assert_eq!(1000, multi[&200]);
+    assert_eq!(2000, multi.values().sum::<u64>());
+
+    // Out of range stops are not counted
+    let short = pat::lifetime_histogram(&tags, 1, &[2], 5, 100, true);
+    assert_eq!(1000, short[&10]);
+    assert_eq!(1000, short.values().sum::<u64>());
+
+    // A stop at the same time as the sync counts at zero delay in either order
+    let same = [
+        Tag { time: 0, channel: 2 },
+        Tag { time: 0, channel: 1 },
+        Tag { time: 5, channel: 2 },
+    ];
+    assert_eq!(1, pat::lifetime_histogram(&same, 1, &[2], 1, 10, false)[&0]);
+    assert_eq!(0, pat::lifetime_histogram(&same, 1, &[2], 1, 10, false)[&5]);
+    assert_eq!(1, pat::lifetime_histogram(&same, 1, &[2], 1, 10, true)[&5]);
+}
+
+/// Accidentals estimated from singles rates and off-peak bins agree
+#[test]
+fn accidentals_singles_vs_offpeak() {
+    let tags = common::load_test_data();
+    for win in [1, 2, 4] {
+        let delay = 26 / win * win;
+        let singles = pat::car_singles(&tags, 3, 15, win, delay);
+        let offpeak = pat::car_offpeak(&tags, 3, 15, win, delay, 20, 200);
+        assert_eq!(singles.coincidences, offpeak.coincidences);
+        let (a, b) = (singles.accidentals, offpeak.accidentals);
+        assert!((a.value - b.value).abs() < 3. * (a.err.powi(2) + b.err.powi(2)).sqrt());
+        // The correlation peak stands well above the accidentals
+        assert!(singles.ratio().value > 10.);
+        assert!(singles.true_coincidences().value > 0.9 * singles.coincidences as f64);
+    }
+}
+
+/// Check accidental and CAR arithmetic on known inputs
+#[test]
+fn accidentals_car_arithmetic() {
+    let a = pat::accidentals_singles_counts(100, 400, 2, 1000);
+    assert!((a.value - 80.).abs() < 1e-9);
+    assert!((a.err - 80. * (0.01f64 + 0.0025).sqrt()).abs() < 1e-9);
+    assert_eq!(pat::Estimate::default(), pat::accidentals_singles_counts(0, 400, 2, 1000));
+    assert_eq!(pat::Estimate::default(), pat::accidentals_singles(&[], 1, 2, 1));
+
+    let histogram: std::collections::BTreeMap<i64, u64> =
+        [(-2, 4), (-1, 6), (0, 100), (1, 2), (2, 8)].into_iter().collect();
+    let b = pat::accidentals_offpeak(&histogram, 0, 1);
+    assert!((b.value - 5.).abs() < 1e-9);
+    assert!((b.err - 20f64.sqrt() / 4.).abs() < 1e-9);
+    let c = pat::accidentals_offpeak(&histogram, 0, 2);
+    assert!((c.value - 6.).abs() < 1e-9);
+
+    let car = pat::Car { coincidences: 100, accidentals: b };
+    assert!((car.true_coincidences().value - 95.).abs() < 1e-9);
+    assert!((car.true_coincidences().err - (100. + b.err.powi(2)).sqrt()).abs() < 1e-9);
+    assert!((car.ratio().value - 20.).abs() < 1e-9);
+    assert!((car.ratio().err - 20. * (0.01 + (b.err / 5.).powi(2)).sqrt()).abs() < 1e-9);
+}
+
+/// Find the known coincidence peak in the test data
+#[test]
+fn find_peak_test_data() {
+    let tags = common::load_test_data();
+    let histogram = pat::coincidence_histogram(&tags, 3, 15, 1, -64, 64);
+    let peak = pat::find_peak(&histogram).unwrap();
+    assert_eq!(26, peak.delay);
+    assert_eq!(76, peak.counts);
+    assert!((peak.centroid - 25.6).abs() < 0.5, "{:?}", peak);
+
+    let peaks = pat::calibrate_delays(&tags, 3, &[3, 15], 1, 64);
+    assert_eq!(vec![15], peaks.keys().copied().collect::<Vec<_>>());
+    assert_eq!(peak, peaks[&15]);
+}
+
+/// Calibrate delays on synthetic data with known offsets
+#[test]
+fn calibrate_delays_synthetic() {
+    let mut tags = Vec::new();
+    for i in 0..1000i64 {
+        let t0 = 10_000 * i;
+        tags.push(Tag { time: t0, channel: 1 });
+        // Symmetric jitter about +37
+        tags.push(Tag { time: t0 + 36 + i % 3, channel: 2 });
+        // Half a bin between -13 and -12
+        tags.push(Tag { time: t0 - 13 + i % 2, channel: 3 });
+        // Uncorrelated with channel 1
+        tags.push(Tag { time: t0 + 5_000 + (i * 7919) % 4_000, channel: 4 });
+    }
+    tags.sort();
+
+    let peaks = pat::calibrate_delays(&tags, 1, &[1, 2, 3, 4], 1, 100);
+    assert_eq!(vec![2, 3], peaks.keys().copied().collect::<Vec<_>>());
+    assert!((peaks[&2].centroid - 37.).abs() < 1e-2);
+    assert!((peaks[&3].centroid + 12.5).abs() < 1e-9);
+
+    let current = [(1, 0), (2, 100), (3, 0)].into_iter().collect();
+    let delays = pat::aligned_delays(1, &peaks, &current);
+    let expected: std::collections::BTreeMap<u8, u32> =
+        [(1, 0), (2, 63), (3, 13)].into_iter().collect();
+    assert_eq!(expected, delays);
+
+    // Channel 2 lags and has no delay to take away, so delay the rest instead
+    let delays = pat::aligned_delays(1, &peaks, &std::collections::BTreeMap::new());
+    let expected: std::collections::BTreeMap<u8, u32> =
+        [(1, 37), (2, 0), (3, 50)].into_iter().collect();
+    assert_eq!(expected, delays);
+}
+
+/// g2 normalized to an explicit duration matches the span-based g2, with errors
+#[test]
+fn g2_duration_vs_g2() {
+    let tags = common::load_test_data();
+    let span = (tags.last().unwrap().time - tags.first().unwrap().time) as f64;
+    let duration = (span * TSTEP / COUNTER_STEP).round() as u64;
+    let g2 = pat::g2(&tags, 3, 15, 1, -64, 64);
+    let g2_duration = pat::g2_duration(&tags, 3, 15, 1, -64, 64, duration);
+    let histogram = pat::coincidence_histogram(&tags, 3, 15, 1, -64, 64);
+    assert_eq!(g2.len(), g2_duration.len());
+    for (d, g) in g2_duration {
+        assert!((g.value - g2[&d]).abs() < 1e-6 * g2[&d].max(1.));
+        let c = histogram[&d] as f64;
+        assert!((g.err - g.value / c.sqrt()).abs() < 1e-9 || c == 0.);
+    }
+
+    // Twice the duration doubles g2
+    let g2_double = pat::g2_duration(&tags, 3, 15, 1, -64, 64, 2 * duration);
+    assert!((g2_double[&26].value - 2. * g2[&26]).abs() < 1e-6);
+}
+
+/// g2 is empty rather than panicking when it is undefined
+#[test]
+fn g2_empty_input() {
+    assert!(pat::g2(&[], 1, 2, 1, -10, 10).is_empty());
+    assert!(pat::g2_duration(&[], 1, 2, 1, -10, 10, 1000).is_empty());
+
+    let one_channel: Vec<Tag> = (0..100).map(|i| Tag { time: 100 * i, channel: 1 }).collect();
+    assert!(pat::g2(&one_channel, 1, 2, 1, -10, 10).is_empty());
+    assert!(pat::g2_duration(&one_channel, 1, 2, 1, -10, 10, 1000).is_empty());
+
+    let tags = common::load_test_data();
+    assert!(pat::g2_duration(&tags, 3, 15, 1, -10, 10, 0).is_empty());
+}
+
+/// Pseudorandom uncorrelated tags at a mean spacing, from a fixed seed
+fn uncorrelated_tags(channel: u8, spacing: i64, n: usize, seed: u64) -> Vec<Tag> {
+    let mut rng = common::Lcg::new(seed);
+    let mut time = 0;
+    (0..n)
+        .map(|_| {
+            time += 1 + (rng.next_u64() >> 33) as i64 % (2 * spacing);
+            Tag { time, channel }
+        })
+        .collect()
+}
+
+/// Cross-check the triple histogram against brute force on synthetic triples
+#[test]
+fn coincidence_histogram_3_synthetic() {
+    let mut tags = Vec::new();
+    tags.extend(uncorrelated_tags(1, 100, 2000, 1));
+    tags.extend(uncorrelated_tags(2, 100, 2000, 2));
+    tags.extend(uncorrelated_tags(3, 100, 2000, 3));
+    // Triples with channel 2 at +10 and channel 3 at -5 relative to channel 1
+    for i in 0..500i64 {
+        let t0 = 400 * i + 7;
+        tags.push(Tag { time: t0, channel: 1 });
+        tags.push(Tag { time: t0 + 10, channel: 2 });
+        tags.push(Tag { time: t0 - 5, channel: 3 });
+    }
+    tags.sort();
+
+    for win in [1, 3] {
+        let max_delay = 30;
+        let histogram = pat::coincidence_histogram_3(&tags, 1, 2, 3, win, max_delay);
+        let max_win = max_delay / win;
+        assert_eq!((2 * max_win + 1).pow(2) as usize, histogram.len());
+
+        let mut brute = std::collections::BTreeMap::new();
+        let of = |ch| tags.iter().filter(move |t| t.channel == ch).map(|t| t.time / win);
+        for a in of(1) {
+            for b in of(2).filter(|b| (b - a).abs() <= max_win) {
+                for c in of(3).filter(|c| (c - a).abs() <= max_win) {
+                    *brute.entry(((b - a) * win, (c - a) * win)).or_insert(0) += 1;
+                }
+            }
+        }
+        for (d, &c) in &histogram {
+            assert_eq!(brute.get(d).copied().unwrap_or(0), c, "{:?}", d);
+        }
+        assert_eq!(brute.values().sum::<u64>(), histogram.values().sum::<u64>());
+    }
+
+    // The triples stand out of the g3 background of uncorrelated events
+    let g3 = pat::g3(&tags, 1, 2, 3, 1, 30);
+    let peak = g3.iter().max_by(|x, y| x.1.value.total_cmp(&y.1.value)).unwrap();
+    assert_eq!(&(10, -5), peak.0);
+    let background = g3.iter().filter(|(&(ab, ac), _)| ab.abs() > 15 && ac.abs() > 15);
+    let (n, sum) = background.fold((0., 0.), |(n, sum), (_, g)| (n + 1., sum + g.value));
+    assert!((sum / n - 1.).abs() < 0.2, "{}", sum / n);
+}
+
+/// g3 is empty rather than panicking when it is undefined
+#[test]
+fn g3_empty_input() {
+    assert!(pat::g3(&[], 1, 2, 3, 1, 10).is_empty());
+    let two_channels: Vec<Tag> = (0..100)
+        .map(|i| Tag { time: 100 * i, channel: 1 + (i % 2) as u8 })
+        .collect();
+    assert!(pat::g3(&two_channels, 1, 2, 3, 1, 10).is_empty());
+}
+
+/// A rate trace sums to the counts over the whole run, and has no gaps
+#[test]
+fn rate_trace_totals() {
+    use pat::TracePattern::{Pair, Single};
+    let tags = common::load_test_data();
+    let patterns = [Single(3), Single(15), Pair(3, 15), Pair(3, 3)];
+    let (win, bin) = (2, 1_000_000);
+    let trace: Vec<(i64, Vec<u64>)> = pat::rate_trace(tags.iter().copied(), &patterns, win, bin).collect();
+
+    let first = tags.first().unwrap().time / bin * bin;
+    let last = tags.last().unwrap().time / bin * bin;
+    assert_eq!(trace.iter().map(|(t, _)| *t).collect::<Vec<_>>(), (first..=last).step_by(bin as usize).collect::<Vec<_>>());
+    assert!(trace.len() > 1);
+
+    // Totals are those recorded for the same patterns by tagsave and tagview
+    let total = |i: usize| trace.iter().map(|(_, c)| c[i]).sum::<u64>();
+    let masks = [&[3][..], &[15], &[3, 15], &[3]].map(|chans| (bit::chans_to_mask(chans), win));
+    let counts = pat::pattern_counts(tags.iter().copied(), &masks);
+    assert_eq!(pat::singles(&tags, 3), total(0));
+    assert_eq!((0..4).map(total).collect::<Vec<_>>(), counts);
+}
+
+/// Rate trace bins and windows before time zero
+#[test]
+fn rate_trace_negative_times() {
+    use pat::TracePattern::{Pair, Single};
+    let tags = [
+        Tag { time: -7, channel: 1 },
+        Tag { time: -5, channel: 2 },
+        Tag { time: -1, channel: 2 },
+        Tag { time: 1, channel: 1 },
+        Tag { time: 25, channel: 2 },
+    ];
+    let trace: Vec<(i64, Vec<u64>)> = pat::rate_trace(tags, &[Single(1), Pair(2, 1)], 4, 10).collect();
+    assert_eq!(trace, [(-10, vec![1, 1]), (0, vec![1, 0]), (10, vec![0, 0]), (20, vec![0, 0])]);
+}
+
+/// Trace patterns parse and print as given on the command line
+#[test]
+fn trace_patterns_from_args() {
+    use pat::TracePattern::{self, Pair, Single};
+    let pairs: Vec<TracePattern> = ["1,2", " 3 , 15"].iter().map(|s| s.parse().unwrap()).collect();
+    assert_eq!(pairs, [Pair(1, 2), Pair(3, 15)]);
+    assert_eq!(pairs.iter().map(|p| p.to_string()).collect::<Vec<_>>(), ["1,2", "3,15"]);
+    assert!("1,x".parse::<TracePattern>().is_err());
+    assert!("256".parse::<TracePattern>().is_err());
+
+    let patterns = TracePattern::from_args(&[4], &pairs).unwrap();
+    assert_eq!(patterns, [Single(4), Pair(1, 2), Pair(3, 15)]);
+    assert!(TracePattern::from_args(&[], &[]).is_err());
+    assert!(TracePattern::from_args(&[], &[Single(1)]).is_err());
+
+    let tags = [Tag { time: 10, channel: 1 }, Tag { time: 11, channel: 2 }];
+    let mut trace = Vec::new();
+    let each = |t, counts| {
+        trace.push((t, counts));
+        Ok(())
+    };
+    let bin = pat::rate_trace_seconds(tags.iter().copied().map(Ok), &patterns, 2, 1e-6, each).unwrap();
+    assert_eq!(bin, 6400);
+    assert_eq!(trace, [(0, vec![0, 1, 0])]);
+    let ignore = |_, _| Ok(());
+    assert!(pat::rate_trace_seconds(tags.iter().copied().map(Ok), &patterns, 0, 1e-6, ignore).is_err());
+    assert!(pat::rate_trace_seconds(tags.iter().copied().map(Ok), &patterns, 2, 1e-12, ignore).is_err());
+    // Errors of the callback stop the trace
+    let fail = |_, _| anyhow::bail!("stop");
+    assert!(pat::rate_trace_seconds(tags.iter().copied().map(Ok), &patterns, 2, 1e-6, fail).is_err());
+}
+
+/// Allan deviation of series with known answers
+#[test]
+fn allan_deviation_known_series() {
+    let tau0 = 0.5;
+
+    // Constant rates are perfectly stable
+    let constant = pat::allan_deviation(&[3.; 64], tau0);
+    assert_eq!(constant.iter().map(|a| a.tau).collect::<Vec<_>>(), [0.5, 1., 2., 4., 8., 16.]);
+    assert!(constant.iter().all(|a| a.adev == 0.));
+
+    // A linear drift of a per bin separates adjacent averages of m bins by a * m
+    let drift: Vec<f64> = (0..100).map(|i| 2. * i as f64).collect();
+    for a in pat::allan_deviation(&drift, tau0) {
+        let m = a.tau / tau0;
+        assert!((a.adev - 2. * m / 2f64.sqrt()).abs() < 1e-9, "{:?}", a);
+    }
+
+    // White noise averages down as 1 / sqrt(m)
+    let mut rng = common::Lcg::new(1);
+    let noise: Vec<f64> = (0..1 << 14).map(|_| rng.uniform() - 0.5).collect();
+    let sigma = (1f64 / 12.).sqrt();
+    for a in pat::allan_deviation(&noise, tau0).iter().take(8) {
+        let m = a.tau / tau0;
+        assert!(a.lo <= a.adev && a.adev <= a.hi, "{:?}", a);
+        assert!((a.adev * m.sqrt() / sigma - 1.).abs() < 0.15, "{:?}", a);
+    }
+
+    assert!(pat::allan_deviation(&[1.], tau0).is_empty());
+}
+
+/// Poisson detections on one channel with a dead time, and afterpulses with
+/// probability `p` uniformly distributed over `afterpulse_range` after them
+fn afterpulsing_tags(rate: f64, dead_time: i64, p: f64, afterpulse_range: i64, n: usize) -> Vec<Tag> {
+    let mut rng = common::Lcg::new(7);
+    let mut uniform = || rng.uniform();
+    let mut times = Vec::with_capacity(n);
+    let mut time = 0;
+    for _ in 0..n {
+        time += (-uniform().ln() / rate) as i64;
+        times.push(time);
+        if uniform() < p {
+            times.push(time + dead_time + (uniform() * afterpulse_range as f64) as i64);
+        }
+    }
+    times.sort_unstable();
+    // The dead time follows every detection, afterpulse or not
+    pat::dead_time_filter(times.into_iter().map(|time| Tag { time, channel: 1 }), dead_time).collect()
+}
+
+/// Dead time and afterpulsing probability are recovered from synthetic data
+#[test]
+fn afterpulsing_synthetic() {
+    let (dead_time, p) = (3200, 0.05);
+    let tags = afterpulsing_tags(1e-5, dead_time, p, 6400, 200_000);
+    assert!(tags.windows(2).all(|w| w[1].time - w[0].time >= dead_time));
+
+    let histograms = pat::interarrival_histograms(tags.iter().copied(), 320, 100_000);
+    let histogram = &histograms[&1];
+    let a = pat::afterpulsing(histogram, tags.len() as u64, 20_000).unwrap();
+    assert_eq!(dead_time, a.dead_time);
+    assert!((a.probability.value - p).abs() < 5. * a.probability.err, "{:?}", a);
+
+    // Without afterpulses, there is no excess
+    let tags = afterpulsing_tags(1e-5, dead_time, 0., 6400, 200_000);
+    let histograms = pat::interarrival_histograms(tags.iter().copied(), 320, 100_000);
+    let a = pat::afterpulsing(&histograms[&1], tags.len() as u64, 20_000).unwrap();
+    assert!(a.probability.value.abs() < 5. * a.probability.err, "{:?}", a);
+}
+
+/// Folding into a fixed period recovers the pulse and phase of each tag
+#[test]
+fn fold_pulses_fixed() {
+    let period = 84.2;
+    let offset = 1000;
+    let tags: Vec<Tag> = (0..10_000i64)
+        .map(|k| Tag { time: offset + (k as f64 * period).ceil() as i64 + k % 7 * 10, channel: 1 + (k % 3) as u8 })
+        .collect();
+    let clock = pat::PulseClock::Fixed { period, offset };
+    let folded: Vec<pat::PulseTag> = pat::fold_pulses(tags.iter().copied(), clock).collect();
+    assert_eq!(tags.len(), folded.len());
+    for (k, p) in folded.iter().enumerate() {
+        let k = k as i64;
+        assert_eq!(k, p.pulse);
+        assert!((p.phase - k % 7 * 10).abs() <= 1, "{:?}", p);
+    }
+
+    // Phase histograms hold every tag, in bins from 0
+    let histograms = pat::phase_histograms(tags.iter().copied(), clock, 4);
+    assert_eq!(histograms.keys().copied().collect::<Vec<_>>(), [1, 2, 3]);
+    for (ch, h) in &histograms {
+        assert_eq!(Some(&0), h.keys().next());
+        assert_eq!(pat::singles(&tags, *ch), h.values().sum::<u64>());
+    }
+}
+
+/// Folding against a drifting, divided clock channel with a missing clock tag
+#[test]
+fn fold_pulses_clock_channel() {
+    let divider = 4;
+    // The period drifts from 100 to 101 time units over the pulses
+    let start = |k: i64| 5000 + (k as f64 * (100. + k as f64 / 20_000.)).round() as i64;
+    let mut tags = Vec::new();
+    for k in 0..10_000i64 {
+        if k % divider == 0 && k != 4000 {
+            tags.push(Tag { time: start(k), channel: 1 });
+        }
+        if k % 3 != 0 {
+            tags.push(Tag { time: start(k) + 20 + 40 * (k % 2), channel: 2 });
+        }
+    }
+    tags.sort();
+
+    let clock = pat::PulseClock::Channel { channel: 1, divider: divider as u32 };
+    let folded: Vec<pat::PulseTag> = pat::fold_pulses(tags.iter().copied(), clock)
+        .filter(|p| p.tag.channel == 2)
+        .collect();
+    // Tags before the second clock tag are dropped
+    assert_eq!(folded[0].pulse, divider);
+    for p in &folded {
+        assert_eq!(p.tag.time, start(p.pulse) + 20 + 40 * (p.pulse % 2), "{:?}", p);
+        assert!((p.phase - (20 + 40 * (p.pulse % 2))).abs() <= 2, "{:?}", p);
+    }
+
+    // Early and late time bins alternate
+    let edges = [10, 40, 70];
+    let binned: Vec<pat::TimeBinTag> = pat::assign_time_bins(tags.iter().copied(), clock, &edges)
+        .filter(|b| b.tag.channel == 2)
+        .collect();
+    assert_eq!(folded.len(), binned.len());
+    assert!(binned.iter().all(|b| b.bin as i64 == b.pulse % 2));
+    let early_only = [10, 40];
+    assert!(pat::assign_time_bins(tags.iter().copied(), clock, &early_only)
+        .filter(|b| b.tag.channel == 2)
+        .all(|b| b.pulse % 2 == 0 && b.bin == 0));
+}
+
+/// Jitter of the clock tags is averaged out of the phases, rather than carried
+/// into every following pulse
+#[test]
+fn fold_pulses_jittered_clock() {
+    let divider = 4;
+    let mut rng = common::Lcg::new(18);
+    // The period drifts from 1000 to 1001 time units over the pulses
+    let start = |k: i64| 5000. + k as f64 * (1000. + k as f64 / 200_000.);
+    let mut tags = Vec::new();
+    for k in 0..100_000i64 {
+        if k % divider == 0 {
+            // Clock tags jitter uniformly by up to 40 time units either way
+            let jitter = 80. * (rng.uniform() - 0.5);
+            tags.push(Tag { time: (start(k) + jitter).round() as i64, channel: 1 });
+        }
+        tags.push(Tag { time: (start(k) + 300.).round() as i64, channel: 2 });
+    }
+    tags.sort();
+
+    let clock = pat::PulseClock::Channel { channel: 1, divider: divider as u32 };
+    // Skip the pulses while the loop settles
+    let phases: Vec<f64> = pat::fold_pulses(tags.iter().copied(), clock)
+        .filter(|p| p.tag.channel == 2 && p.pulse > 4000)
+        .map(|p| p.phase as f64)
+        .collect();
+    let n = phases.len() as f64;
+    let mean = phases.iter().sum::<f64>() / n;
+    let sd = (phases.iter().map(|p| (p - mean).powi(2)).sum::<f64>() / n).sqrt();
+    // Clock tags alone scatter by 80 / sqrt(12), about 23
+    assert!((mean - 300.).abs() < 2., "mean {}", mean);
+    assert!(sd < 8., "sd {}", sd);
+}
@@ -0,0 +1,130 @@
+use std::io::Cursor;
+use tagtools::ptu::{self, Value};
+use tagtools::{de, ser, Tag};
+
+/// Header tag with an 8-byte value
+fn entry(buf: &mut Vec<u8>, name: &str, idx: i32, typ: u32, value: [u8; 8]) {
+    let mut ident = [0u8; 32];
+    ident[..name.len()].copy_from_slice(name.as_bytes());
+    buf.extend_from_slice(&ident);
+    buf.extend_from_slice(&idx.to_le_bytes());
+    buf.extend_from_slice(&typ.to_le_bytes());
+    buf.extend_from_slice(&value);
+}
+
+/// A PTU file with a typical header and T2 `records`
+fn ptu_file(record_type: u32, resolution: f64, records: &[u32], count: bool) -> Vec<u8> {
+    let mut buf = b"PQTTTR\0\0".to_vec();
+    buf.extend_from_slice(b"1.0.00\0\0");
+    let comment = b"a comment\0\0\0\0\0\0\0";
+    entry(&mut buf, "File_Comment", -1, 0x4001FFFF, (comment.len() as i64).to_le_bytes());
+    buf.extend_from_slice(comment);
+    entry(&mut buf, "HW_InpChan_Offset", 0, 0x10000008, 25i64.to_le_bytes());
+    entry(&mut buf, "HW_InpChan_Offset", 1, 0x10000008, (-30i64).to_le_bytes());
+    entry(&mut buf, "Measurement_SubMode", -1, 0x10000008, 2i64.to_le_bytes());
+    entry(&mut buf, "TTResultFormat_TTTRRecType", -1, 0x10000008, (record_type as i64).to_le_bytes());
+    if count {
+        entry(&mut buf, "TTResult_NumberOfRecords", -1, 0x10000008, (records.len() as i64).to_le_bytes());
+    }
+    entry(&mut buf, "MeasDesc_Resolution", -1, 0x20000008, resolution.to_le_bytes());
+    entry(&mut buf, "Header_End", -1, 0xFFFF0008, [0; 8]);
+    for r in records {
+        buf.extend_from_slice(&r.to_le_bytes());
+    }
+    buf
+}
+
+/// HydraHarp-style T2 record
+fn hh(special: bool, channel: u32, time: u32) -> u32 {
+    (special as u32) << 31 | channel << 25 | time
+}
+
+#[test]
+fn hydraharp_v2_records() {
+    let records = [
+        hh(false, 0, 100),
+        hh(true, 0, 200), // sync
+        hh(false, 3, 300),
+        hh(true, 0x3F, 1), // one overflow
+        hh(false, 1, 5),
+        hh(true, 2, 10), // marker
+        hh(true, 0x3F, 3), // three overflows
+        hh(false, 0, 7),
+    ];
+    // MultiHarp, 5 ps resolution
+    let file = ptu_file(0x00010207, 5e-12, &records, true);
+    let rdr = ptu::reader(Cursor::new(&file)).unwrap();
+    assert_eq!(Some(&Value::String("a comment".into())), rdr.header().get("File_Comment"));
+    assert_eq!(Some(&Value::Int(-30)), rdr.header().get("HW_InpChan_Offset(1)"));
+    assert_eq!(5e-12, rdr.resolution());
+
+    // Times in 5 ps to 156.25 ps, rounded down
+    let tstep = |t: i64| t * 5000 / 156250;
+    let wrap = 1i64 << 25;
+    let tags: Vec<Tag> = rdr.map(Result::unwrap).collect();
+    assert_eq!(tags, [
+        Tag { time: tstep(100), channel: 1 },
+        Tag { time: tstep(300), channel: 4 },
+        Tag { time: tstep(wrap + 5), channel: 2 },
+        Tag { time: tstep(4 * wrap + 7), channel: 1 },
+    ]);
+
+    let rdr = ptu::reader(Cursor::new(&file)).unwrap().sync_channel(9).marker_channel(10);
+    let channels: Vec<u8> = rdr.map(|t| t.unwrap().channel).collect();
+    assert_eq!(channels, [1, 9, 4, 2, 10, 1]);
+}
+
+#[test]
+fn hydraharp_v1_and_picoharp_overflows() {
+    // Version 1 HydraHarp records always overflow by the same amount
+    let records = [hh(false, 0, 10), hh(true, 0x3F, 5), hh(false, 0, 10)];
+    let file = ptu_file(0x00010204, 1e-12, &records, true);
+    let times: Vec<i64> = ptu::reader(Cursor::new(&file)).unwrap().map(|t| t.unwrap().time).collect();
+    assert_eq!(times, [10 * 1000 / 156250, (33552000 + 10) * 1000 / 156250]);
+
+    // PicoHarp records, without a record count so they end with the file
+    let ph = |channel: u32, time: u32| channel << 28 | time;
+    let records = [ph(2, 1000), ph(0xF, 0), ph(0, 20), ph(0xF, 4 | 1 << 4), ph(1, 30)];
+    let file = ptu_file(0x00010203, 4e-12, &records, false);
+    let tags: Vec<Tag> = ptu::reader(Cursor::new(&file)).unwrap().map(Result::unwrap).collect();
+    let tstep = |t: i64| t * 4000 / 156250;
+    assert_eq!(tags, [
+        Tag { time: tstep(1000), channel: 3 },
+        Tag { time: tstep(210698240 + 20), channel: 1 },
+        Tag { time: tstep(210698240 + 30), channel: 2 },
+    ]);
+}
+
+#[test]
+fn unsupported_files() {
+    // T3 mode
+    assert!(ptu::reader(Cursor::new(ptu_file(0x00010304, 1e-12, &[], true))).is_err());
+    assert!(ptu::reader(Cursor::new(b"HydraHarp 2.0\0\0\0".to_vec())).is_err());
+    // A corrupt string length runs past the end of the file
+    let mut file = b"PQTTTR\0\0".to_vec();
+    file.extend_from_slice(b"1.0.00\0\0");
+    entry(&mut file, "File_Comment", -1, 0x4001FFFF, (1i64 << 60).to_le_bytes());
+    file.extend_from_slice(b"a comment\0");
+    assert!(ptu::reader(Cursor::new(file)).is_err());
+    // Records cut short of the count in the header
+    let mut file = ptu_file(0x00010207, 5e-12, &[hh(false, 0, 1), hh(false, 0, 2)], true);
+    file.truncate(file.len() - 2);
+    let tags: Vec<_> = ptu::reader(Cursor::new(&file)).unwrap().collect();
+    assert_eq!(2, tags.len());
+    assert!(tags[0].is_ok() && tags[1].is_err());
+}
+
+/// Converted tags survive a round trip through the binary format
+#[test]
+fn ptu_to_tags() {
+    let records: Vec<u32> = (0..100_000u32).map(|i| hh(false, i % 4, (i * 37) % (1 << 25))).collect();
+    let tags: Vec<Tag> = ptu::reader(Cursor::new(ptu_file(0x00010207, 5e-12, &records, true)))
+        .unwrap()
+        .map(Result::unwrap)
+        .collect();
+    assert_eq!(records.len(), tags.len());
+    let mut buf = Vec::new();
+    ser::tags(&mut buf, &tags).unwrap();
+    let read: Vec<Tag> = de::tags_iter(&*buf).unwrap().map(Result::unwrap).collect();
+    assert_eq!(tags, read);
+}
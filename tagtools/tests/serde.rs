@@ -1,6 +1,9 @@
 use tagtools::Tag;
 use tagtools::{ser, de};
 
+#[allow(dead_code)]
+mod common;
+
 /// Serialize and deserialize tags written as one message to the buffer
 #[test]
 fn serde_one_message() {
@@ -87,3 +90,417 @@ fn serde_two_full_lists() {
     let tags2 = de::tags(&*b).unwrap();
     assert_eq!(&tags, &tags2);
 }
+
+/// Stream tags written as multiple messages, one chunk per message
+#[test]
+fn serde_stream_chunks() {
+    let tags: Vec<Tag> = (0..1000)
+        .map(|i| Tag { time: 6 * i, channel: (i % 4 + 1) as u8 })
+        .collect();
+    let mut b: Vec<u8> = Vec::new();
+    for chunk in tags.chunks(64) {
+        ser::tags(&mut b, chunk).unwrap();
+    }
+    let chunks: Vec<Vec<Tag>> = de::tag_chunks(&*b)
+        .unwrap()
+        .collect::<anyhow::Result<_>>()
+        .unwrap();
+    assert_eq!(tags.chunks(64).count(), chunks.len());
+    assert_eq!(tags, chunks.concat());
+    let tags2: Vec<Tag> = de::tags_iter(&*b)
+        .unwrap()
+        .collect::<anyhow::Result<_>>()
+        .unwrap();
+    assert_eq!(tags, tags2);
+}
+
+/// A truncated stream yields its complete messages, then one error
+#[test]
+fn serde_stream_truncated() {
+    let tags = [
+        Tag { time:  0, channel: 1 },
+        Tag { time:  6, channel: 2 },
+    ];
+    let mut b: Vec<u8> = Vec::new();
+    ser::tags_uncompressed(&mut b, &tags[..1]).unwrap();
+    ser::tags_uncompressed(&mut b, &tags[1..]).unwrap();
+    b.truncate(b.len() - 8);
+    let mut chunks = de::TagChunks::new(&*b);
+    assert_eq!(tags[..1], chunks.next().unwrap().unwrap());
+    assert!(chunks.next().unwrap().is_err());
+    assert!(chunks.next().is_none());
+}
+
+/// Write tags in batches with one long-lived writer and read them back
+#[test]
+fn serde_tag_writer() {
+    let tags: Vec<Tag> = (0..100)
+        .map(|i| Tag { time: 6 * i, channel: (i % 4 + 1) as u8 })
+        .collect();
+    let mut b: Vec<u8> = Vec::new();
+    let mut wtr = ser::TagWriter::new(&mut b, 16).unwrap();
+    for chunk in tags.chunks(5) {
+        wtr.write(chunk).unwrap();
+    }
+    wtr.finish().unwrap();
+    let lens: Vec<usize> = de::tag_chunks(&*b)
+        .unwrap()
+        .map(|chunk| chunk.unwrap().len())
+        .collect();
+    assert_eq!(vec![16, 16, 16, 16, 16, 16, 4], lens);
+    assert_eq!(tags, de::tags(&*b).unwrap());
+}
+
+/// Tags written before a flush can be read before the writer is finished
+#[test]
+fn serde_tag_writer_flush() {
+    let tags: Vec<Tag> = (0..10)
+        .map(|i| Tag { time: 6 * i, channel: 1 })
+        .collect();
+    let path = std::env::temp_dir().join(format!("tagtools-flush-{}.tags.zst", std::process::id()));
+    let f = std::fs::File::create(&path).unwrap();
+    let mut wtr = ser::TagWriter::new(f, 1000).unwrap();
+    wtr.write(&tags[..4]).unwrap();
+    wtr.flush().unwrap();
+    let mut chunks = de::tag_chunks(std::fs::File::open(&path).unwrap()).unwrap();
+    assert_eq!(tags[..4], chunks.next().unwrap().unwrap());
+    wtr.write(&tags[4..]).unwrap();
+    wtr.finish().unwrap();
+    assert_eq!(tags, de::tags(std::fs::File::open(&path).unwrap()).unwrap());
+    std::fs::remove_file(&path).unwrap();
+}
+
+fn metadata() -> tagtools::cfg::Metadata {
+    use tagtools::cfg::{ChannelSettings, Run, RunLimit};
+    let run = Run {
+        description: String::from("metadata"),
+        limit: Some(RunLimit::Duration(std::time::Duration::from_secs(2))),
+        ..Default::default()
+    };
+    let channel_settings = vec![
+        ChannelSettings { channel: 1, invert: Some(true), delay: Some(20), threshold: Some(-0.5) },
+        ChannelSettings { channel: 2, invert: None, delay: None, threshold: Some(0.25) },
+    ];
+    tagtools::cfg::Metadata::new("v1.2.3", run, channel_settings)
+}
+
+/// Metadata is read back from plain and indexed files, which stay readable
+#[test]
+fn serde_metadata() {
+    let tags = vec![
+        Tag { time:  0, channel: 1 },
+        Tag { time:  6, channel: 2 },
+        Tag { time: 12, channel: 1 },
+    ];
+    let meta = metadata();
+    for indexed in [false, true] {
+        let mut wtr = if indexed {
+            ser::TagWriter::indexed(Vec::new(), 2).unwrap()
+        } else {
+            ser::TagWriter::new(Vec::new(), 2).unwrap()
+        };
+        wtr.write_metadata(&meta).unwrap();
+        wtr.write(&tags).unwrap();
+        assert!(wtr.write_metadata(&meta).is_err());
+        let b = wtr.finish().unwrap();
+        assert_eq!(Some(meta.clone()), de::metadata(&*b).unwrap());
+        assert_eq!(tags, de::tags(&*b).unwrap());
+        assert_eq!(tags[1..], de::tags_range(std::io::Cursor::new(&b), 1, 100).unwrap());
+    }
+    let mut b: Vec<u8> = Vec::new();
+    ser::tags(&mut b, &tags).unwrap();
+    assert_eq!(None, de::metadata(&*b).unwrap());
+    assert_eq!(None, de::metadata(&[][..]).unwrap());
+}
+
+/// Tags written in record batches to Arrow IPC and Parquet files are read back
+#[test]
+#[cfg(feature = "columnar")]
+fn serde_columnar() {
+    let tags: Vec<Tag> = (0..2500)
+        .map(|i| Tag { time: 6 * i - 100, channel: (i % 4 + 1) as u8 })
+        .collect();
+    for format in [ser::Columnar::Arrow, ser::Columnar::Parquet] {
+        let mut wtr = ser::ColumnWriter::new(Vec::new(), format, 1000).unwrap();
+        for chunk in tags.chunks(300) {
+            wtr.write(chunk).unwrap();
+        }
+        let b = wtr.finish().unwrap();
+        let path = std::env::temp_dir().join(format!("tagtools-columnar-{}-{:?}", std::process::id(), format));
+        std::fs::write(&path, &b).unwrap();
+        let f = std::fs::File::open(&path).unwrap();
+        let chunks: Vec<Vec<Tag>> = match format {
+            ser::Columnar::Arrow => de::arrow_chunks(f).unwrap().map(Result::unwrap).collect(),
+            ser::Columnar::Parquet => de::parquet_chunks(f).unwrap().map(Result::unwrap).collect(),
+        };
+        std::fs::remove_file(&path).unwrap();
+        if format == ser::Columnar::Arrow {
+            let lens: Vec<usize> = chunks.iter().map(Vec::len).collect();
+            assert_eq!(vec![1000, 1000, 500], lens);
+        }
+        assert_eq!(tags, chunks.concat());
+    }
+}
+
+/// Columns other than those of `ser::tag_schema` are rejected
+#[test]
+#[cfg(feature = "columnar")]
+fn serde_columnar_schema() {
+    use arrow_array::{Int32Array, Int64Array, RecordBatch};
+    use std::sync::Arc;
+
+    let time = Arc::new(Int64Array::from(vec![0, 6, 12]));
+    let channel = Arc::new(Int32Array::from(vec![1, 2, 1]));
+    let batch = RecordBatch::try_from_iter([("time", time as _), ("channel", channel as _)]).unwrap();
+    let mut wtr = arrow_ipc::writer::FileWriter::try_new(Vec::new(), &batch.schema()).unwrap();
+    wtr.write(&batch).unwrap();
+    wtr.finish().unwrap();
+    let b = wtr.into_inner().unwrap();
+    let mut chunks = de::arrow_chunks(std::io::Cursor::new(b)).unwrap();
+    let err = chunks.next().unwrap().unwrap_err();
+    assert!(err.to_string().contains("channel column is Int32"), "{}", err);
+    assert!(chunks.next().is_none());
+}
+
+/// Header dict and data of a `.npy` file
+fn parse_npy(b: &[u8]) -> (&str, &[u8]) {
+    assert_eq!(b"\x93NUMPY\x01\x00", &b[..8]);
+    let len = u16::from_le_bytes([b[8], b[9]]) as usize;
+    assert_eq!(0, (10 + len) % 64);
+    let header = std::str::from_utf8(&b[10..10 + len]).unwrap();
+    assert!(header.ends_with('\n'));
+    (header.trim_end(), &b[10 + len..])
+}
+
+/// Tags are written as a packed structured array
+#[test]
+fn serde_npy_tags() {
+    let tags = vec![
+        Tag { time: -6, channel: 1 },
+        Tag { time: 1 << 40, channel: 255 },
+    ];
+    let mut b: Vec<u8> = Vec::new();
+    ser::npy(&mut b, ser::NpyArray::Tags(&tags)).unwrap();
+    let (header, data) = parse_npy(&b);
+    assert_eq!(
+        "{'descr': [('time', '<i8'), ('channel', '|u1')], 'fortran_order': False, 'shape': (2,), }",
+        header,
+    );
+    let mut expected = Vec::new();
+    for t in &tags {
+        expected.extend_from_slice(&t.time.to_le_bytes());
+        expected.push(t.channel);
+    }
+    assert_eq!(expected, data);
+}
+
+/// A histogram is written as a zip archive of `.npy` files, with entries
+/// found from the central directory at the end
+#[test]
+fn serde_npz_histogram() {
+    let histogram: std::collections::BTreeMap<i64, u64> = [(-2, 5), (0, 12), (2, 7)].into();
+    let mut b: Vec<u8> = Vec::new();
+    ser::npz_histogram(&mut b, &histogram).unwrap();
+
+    let u16_at = |i: usize| u16::from_le_bytes([b[i], b[i + 1]]) as usize;
+    let u32_at = |i: usize| u32::from_le_bytes(b[i..i + 4].try_into().unwrap()) as usize;
+    let end = b.len() - 22;
+    assert_eq!(0x06054b50, u32_at(end));
+    assert_eq!(2, u16_at(end + 10));
+    let mut entry = u32_at(end + 16);
+    let mut arrays = Vec::new();
+    for _ in 0..2 {
+        assert_eq!(0x02014b50, u32_at(entry));
+        let name_len = u16_at(entry + 28);
+        let name = std::str::from_utf8(&b[entry + 46..entry + 46 + name_len]).unwrap();
+        let (crc, size) = (u32_at(entry + 16) as u32, u32_at(entry + 24));
+        let local = u32_at(entry + 42);
+        assert_eq!(0x04034b50, u32_at(local));
+        let start = local + 30 + u16_at(local + 26);
+        let data = &b[start..start + size];
+        assert_eq!(crc, crc32fast::hash(data));
+        arrays.push((name.to_string(), data.to_vec()));
+        entry += 46 + name_len;
+    }
+
+    let (names, data): (Vec<String>, Vec<Vec<u8>>) = arrays.into_iter().unzip();
+    assert_eq!(vec!["delay.npy", "counts.npy"], names);
+    let (header, delay) = parse_npy(&data[0]);
+    assert_eq!("{'descr': '<i8', 'fortran_order': False, 'shape': (3,), }", header);
+    let delay: Vec<i64> = delay.chunks(8).map(|c| i64::from_le_bytes(c.try_into().unwrap())).collect();
+    assert_eq!(vec![-2, 0, 2], delay);
+    let (header, counts) = parse_npy(&data[1]);
+    assert_eq!("{'descr': '<u8', 'fortran_order': False, 'shape': (3,), }", header);
+    let counts: Vec<u64> = counts.chunks(8).map(|c| u64::from_le_bytes(c.try_into().unwrap())).collect();
+    assert_eq!(vec![5, 12, 7], counts);
+}
+
+/// Delta-encoded files are read back like Cap'n Proto ones, indexed or not
+#[test]
+fn serde_delta() {
+    let mut tags: Vec<Tag> = (0..1000)
+        .map(|i| Tag { time: 6 * i + (i * i) % 7, channel: (i % 4 + 1) as u8 })
+        .collect();
+    // Out of order and extreme times still round-trip
+    tags.extend([
+        Tag { time: 5000, channel: 2 },
+        Tag { time: i64::MIN, channel: 255 },
+        Tag { time: i64::MAX, channel: 0 },
+        Tag { time: 7000, channel: 1 },
+    ]);
+    let meta = metadata();
+    for indexed in [false, true] {
+        let wtr = if indexed {
+            ser::TagWriter::indexed(Vec::new(), 100).unwrap()
+        } else {
+            ser::TagWriter::new(Vec::new(), 100).unwrap()
+        };
+        let mut wtr = wtr.encoding(ser::Encoding::Delta);
+        wtr.write_metadata(&meta).unwrap();
+        for chunk in tags.chunks(30) {
+            wtr.write(chunk).unwrap();
+        }
+        let b = wtr.finish().unwrap();
+        assert_eq!(Some(meta.clone()), de::metadata(&*b).unwrap());
+        assert_eq!(tags, de::tags(&*b).unwrap());
+        let lens: Vec<usize> = de::tag_chunks(&*b).unwrap().map(|c| c.unwrap().len()).collect();
+        assert_eq!(vec![100; 10].into_iter().chain([4]).collect::<Vec<_>>(), lens);
+        if indexed {
+            let range = de::tags_range(std::io::Cursor::new(&b), 600, 1200).unwrap();
+            assert_eq!(tags[100..200], range);
+        }
+    }
+}
+
+/// Cap'n Proto messages and delta blocks may follow each other in one stream
+#[test]
+fn serde_delta_mixed() {
+    let tags: Vec<Tag> = (0..10)
+        .map(|i| Tag { time: 6 * i, channel: 1 })
+        .collect();
+    let mut b: Vec<u8> = Vec::new();
+    ser::tags_uncompressed(&mut b, &tags[..3]).unwrap();
+    tagtools::delta::write_block(&mut b, &tags[3..7]).unwrap();
+    tagtools::delta::write_block(&mut b, &[]).unwrap();
+    ser::tags_uncompressed(&mut b, &tags[7..]).unwrap();
+    let chunks: Vec<Vec<Tag>> = de::TagChunks::new(&*b).map(Result::unwrap).collect();
+    assert_eq!(vec![&tags[..3], &tags[3..7], &[][..], &tags[7..]], chunks);
+    assert_eq!(tags, de::tags_uncompressed(&mut &*b).unwrap());
+}
+
+/// Truncated or unknown delta blocks are errors
+#[test]
+fn serde_delta_invalid() {
+    let tags: Vec<Tag> = (0..10)
+        .map(|i| Tag { time: 1000 * i, channel: 1 })
+        .collect();
+    let mut b: Vec<u8> = Vec::new();
+    tagtools::delta::write_block(&mut b, &tags).unwrap();
+    for len in [2, 10, 25, b.len() - 1] {
+        let mut chunks = de::TagChunks::new(&b[..len]);
+        assert!(chunks.next().unwrap().is_err(), "truncated to {}", len);
+        assert!(chunks.next().is_none());
+    }
+    let mut unknown = b.clone();
+    unknown[4] = 2;
+    let err = de::TagChunks::new(&*unknown).next().unwrap().unwrap_err();
+    assert!(err.to_string().contains("version 2"), "{}", err);
+}
+
+/// Decoding across threads yields the chunks of every kind of frame in order
+#[test]
+fn serde_parallel() {
+    let tags: Vec<Tag> = (0..10_000)
+        .map(|i| Tag { time: 6 * i, channel: (i % 4 + 1) as u8 })
+        .collect();
+    let mut files = Vec::new();
+    // Indexed with metadata, in both encodings, with a frame per batch
+    for encoding in [ser::Encoding::Capnp, ser::Encoding::Delta] {
+        let mut wtr = ser::TagWriter::indexed(Vec::new(), 100).unwrap().encoding(encoding);
+        wtr.write_metadata(&metadata()).unwrap();
+        wtr.write(&tags).unwrap();
+        files.push(wtr.finish().unwrap());
+    }
+    // Concatenated streaming frames, with checksums
+    let mut b: Vec<u8> = Vec::new();
+    for chunk in tags.chunks(700) {
+        let mut zwtr = zstd::stream::write::Encoder::new(&mut b, 3).unwrap();
+        zwtr.include_checksum(true).unwrap();
+        ser::tags_uncompressed(&mut zwtr, chunk).unwrap();
+        zwtr.finish().unwrap();
+    }
+    files.push(b);
+    // One frame
+    let mut b: Vec<u8> = Vec::new();
+    ser::tags(&mut b, &tags).unwrap();
+    files.push(b);
+
+    for b in &files {
+        let chunks: Vec<Vec<Tag>> = de::tag_chunks(&**b).unwrap().map(Result::unwrap).collect();
+        for threads in [1, 3, 0] {
+            let par: Vec<Vec<Tag>> = de::tag_chunks_par(&**b, threads).unwrap().map(Result::unwrap).collect();
+            assert_eq!(chunks, par);
+        }
+        assert_eq!(tags, de::tags_par(&**b, 2).unwrap());
+    }
+
+    // Stopping early leaves no threads waiting
+    let mut chunks = de::tag_chunks_par(&*files[0], 4).unwrap();
+    assert_eq!(tags[..100], chunks.next().unwrap().unwrap());
+    drop(chunks);
+}
+
+/// A frame too large to hand to a worker whole is decoded as it is read, in
+/// order with the frames around it
+#[test]
+fn serde_parallel_large_frame() {
+    // Pseudo-random times, so that the middle frame compresses poorly
+    let mut rng = common::Lcg::new(1);
+    let mut time = 0;
+    let tags: Vec<Tag> = (0..2_000_000)
+        .map(|_| {
+            let x = rng.next_u64();
+            time += (x >> 40) as i64;
+            Tag { time, channel: (x >> 36) as u8 % 16 + 1 }
+        })
+        .collect();
+    let mut b: Vec<u8> = Vec::new();
+    ser::tags(&mut b, &tags[..1000]).unwrap();
+    let small = b.len();
+    ser::tags(&mut b, &tags[1000..1_999_000]).unwrap();
+    // Larger than the limit of a frame for a worker
+    assert!(b.len() - small > 1 << 22);
+    let mut wtr = ser::TagWriter::indexed(&mut b, 100).unwrap();
+    wtr.write(&tags[1_999_000..]).unwrap();
+    wtr.finish().unwrap();
+
+    let chunks: Vec<Vec<Tag>> = de::tag_chunks(&*b).unwrap().map(Result::unwrap).collect();
+    let par: Vec<Vec<Tag>> = de::tag_chunks_par(&*b, 2).unwrap().map(Result::unwrap).collect();
+    assert_eq!(chunks, par);
+    assert_eq!(tags, de::tags_par(&*b, 3).unwrap());
+
+    // Cut short in the large frame
+    b.truncate(small + (1 << 22) + 1000);
+    let mut chunks = de::tag_chunks_par(&*b, 2).unwrap();
+    assert_eq!(tags[..1000], chunks.next().unwrap().unwrap());
+    assert!(chunks.any(|chunk| chunk.is_err()));
+}
+
+/// A truncated stream yields its complete frames, then one error
+#[test]
+fn serde_parallel_truncated() {
+    let tags: Vec<Tag> = (0..1000)
+        .map(|i| Tag { time: 6 * i, channel: 1 })
+        .collect();
+    let mut wtr = ser::TagWriter::indexed(Vec::new(), 100).unwrap();
+    wtr.write(&tags).unwrap();
+    let mut b = wtr.finish().unwrap();
+    let index = tagtools::index::Index::read(&mut std::io::Cursor::new(&b)).unwrap().unwrap();
+    b.truncate(index.frames[5].offset as usize + 10);
+    let mut chunks = de::tag_chunks_par(&*b, 2).unwrap();
+    for i in 0..5 {
+        assert_eq!(tags[100 * i..100 * (i + 1)], chunks.next().unwrap().unwrap());
+    }
+    assert!(chunks.next().unwrap().is_err());
+    assert!(chunks.next().is_none());
+}
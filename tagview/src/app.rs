@@ -10,11 +10,12 @@ use std::sync::Arc;
 use std::time::Duration;
 use tagtools::cfg::Single;
 
-use tagtools::{bit::BitOps, cfg, Tag, THRESHOLD_MAX, THRESHOLD_MIN};
+use tagtools::{bit::BitOps, cfg, pat, Tag, THRESHOLD_MAX, THRESHOLD_MIN};
 
 use crate::client::{ClientHandle, ClientMessage};
 use crate::save;
 use crate::save::SaveHandle;
+use crate::GIT_VERSION;
 use crate::settings_client::{
     RawChannelSetting, RawSingleChannelState, SettingsClientHandle, SettingsMessage,
 };
@@ -321,6 +322,7 @@ impl<'a> App<'a> {
                     }
                 }
             }
+            'k' if self.tabs.index == 1 && self.live_settings => self.calibrate_delays(),
             'x' => {
                 if self.tabs.index == 1 && self.live_settings == false {
                     self.live_settings = true;
@@ -479,6 +481,49 @@ impl<'a> App<'a> {
         }
     }
 
+    /// Align the delays of the other channels to the selected channel, from the
+    /// coincidence peaks in the latest tags
+    fn calibrate_delays(&mut self) {
+        let tags = self.tags.lock().clone();
+        if tags.is_empty() {
+            self.flags.insert(String::from("Delay calibration needs tags: set save_tags in the runfile"));
+            return;
+        }
+        let state = self.settings_state.as_mut().unwrap();
+        let reference = state.channel_settings[state.index].ch;
+        let channels: Vec<u8> = state.channel_settings.iter().map(|rs| rs.ch).collect();
+        let current = state.channel_settings.iter().map(|rs| (rs.ch, rs.del)).collect();
+        let peaks = pat::calibrate_delays(&tags, reference, &channels, 1, pat::CALIBRATION_RANGE);
+        let delays = pat::aligned_delays(reference, &peaks, &current);
+        for rs in state.channel_settings.iter_mut() {
+            match delays.get(&rs.ch) {
+                Some(&del) if del != rs.del => {
+                    let (respond_to, response) = flume::bounded(1);
+                    let _ = self.settings_handle.sender.send(SettingsMessage::Set {
+                        setting: RawChannelSetting::Delay((rs.ch, del)),
+                        respond_to,
+                    });
+                    match response.recv_timeout(INTERACTIVE_TIMEOUT) {
+                        Ok(()) => rs.del = del,
+                        Err(RecvTimeoutError::Timeout) => {
+                            self.flags.insert(String::from("Set delay timeout"));
+                        }
+                        Err(RecvTimeoutError::Disconnected) => {
+                            self.should_quit = true;
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        self.flags.insert(format!(
+            "Delays of {} of {} channels aligned to channel {}",
+            peaks.len(),
+            channels.len() - 1,
+            reference,
+        ));
+    }
+
     pub fn on_ctrlr(&mut self) {
         match self.save {
             true => {
@@ -490,6 +535,24 @@ impl<'a> App<'a> {
             }
             false => {
                 self.save = true;
+                let channel_settings = match &self.settings_state {
+                    Some(state) => state
+                        .channel_settings
+                        .iter()
+                        .map(|rs| cfg::ChannelSettings {
+                            channel: rs.ch,
+                            invert: Some(rs.inv),
+                            delay: Some(rs.del),
+                            threshold: Some(rs.thr),
+                        })
+                        .collect(),
+                    None => self.config.channel_settings.clone(),
+                };
+                let meta = cfg::Metadata::new(GIT_VERSION, self.config.clone(), channel_settings);
+                self.save_handle
+                    .sender
+                    .send(save::SaveMessage::Metadata(Box::new(meta)))
+                    .unwrap();
             }
         }
     }
@@ -198,11 +198,14 @@ impl Client {
                         cfg::Coincidence::Channels((ch_a, ch_b)) => {
                             pats.push((chans_to_mask(&[ch_a, ch_b]), WIN_DEFAULT));
                         },
-                        cfg::Coincidence::ChannelsWin((ch_a, ch_b, win)) => {
+                        // Rates are shown without accidentals subtracted
+                        cfg::Coincidence::ChannelsWin((ch_a, ch_b, win))
+                        | cfg::Coincidence::ChannelsWinSubtracted((ch_a, ch_b, win)) => {
                             pats.push((chans_to_mask(&[ch_a, ch_b]), win));
                         },
                         // Ignore recorded data
                         cfg::Coincidence::ChannelsCounts(_) => {},
+                        cfg::Coincidence::ChannelsCountsSubtracted(_) => {},
                     }
                 }
                 
@@ -7,6 +7,8 @@ pub mod ui;
 
 use argh::FromArgs;
 
+pub const GIT_VERSION: &str = git_version::git_version!();
+
 #[derive(Debug, FromArgs, Clone)]
 /// TUI tool to interactively view singles and coincidence rates, tune input
 /// parameters, and save modified settings for later use.
@@ -17,12 +17,10 @@ use tagview::{
     save::SaveHandle,
     settings_client::SettingsClientHandle,
     timer::TimerHandle,
-    ui, Cli,
+    ui, Cli, GIT_VERSION,
 };
 use tui::{backend::CrosstermBackend, Terminal};
 
-const GIT_VERSION: &str = git_version::git_version!();
-
 /// Timetag visualization client
 ///
 /// ## Structure
@@ -172,5 +170,7 @@ fn main() -> Result<()> {
             break;
         }
     }
+    // Write out any tags still buffered for saving
+    app.save_handle.finish()?;
     Ok(())
 }
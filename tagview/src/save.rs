@@ -6,7 +6,11 @@ use std::fs;
 use std::path;
 use std::sync::Arc;
 use std::thread;
-use tagtools::{Tag, ser};
+use std::time::{Duration, Instant};
+use tagtools::{cfg, Tag, ser};
+
+/// Longest time between flushes of the tags saved so far to the file
+const FLUSH_INTERVAL: Duration = Duration::from_secs(5);
 
 pub struct SaveTags {
     pub tags: Arc<Mutex<Vec<Tag>>>,
@@ -15,65 +19,101 @@ pub struct SaveTags {
 
 pub enum SaveMessage {
     Save(SaveTags),
+    /// Metadata to write at the start of the next file
+    Metadata(Box<cfg::Metadata>),
     Reset,
 }
 
 pub struct SaveHandle {
     pub sender: flume::Sender<SaveMessage>,
+    thread: thread::JoinHandle<Result<()>>,
 }
 
 impl SaveHandle {
     pub fn new() -> Self {
         let (sender, receiver) = flume::unbounded();
         
-    thread::spawn(move || {
+    let thread = thread::spawn(move || -> Result<()> {
         let mut curpath: Option<path::PathBuf> = None;
-        let mut file: Option<fs::File> = None;
-        loop {
-            if let Ok(st) = receiver.recv() {
-                match st {
-                    SaveMessage::Save(st) => {
-                        let tags = st.tags.lock();
-                        let t = &*tags;
-                        update_and_write_file(st.path, &mut curpath, &mut file, &t)
-                            .context("file io error")
-                            .unwrap();
-                    },
-                    SaveMessage::Reset => {
-                        drop(file);
-                        file = None;
-                    },
-                }
-
+        let mut file: Option<ser::TagWriter<fs::File>> = None;
+        let mut meta: Option<Box<cfg::Metadata>> = None;
+        let mut flushed = Instant::now();
+        while let Ok(st) = receiver.recv() {
+            match st {
+                SaveMessage::Save(st) => {
+                    let tags = st.tags.lock();
+                    let t = &*tags;
+                    update_and_write_file(st.path, &mut curpath, &mut file, &meta, t, &mut flushed)
+                        .context("file io error")?;
+                },
+                SaveMessage::Metadata(m) => {
+                    meta = Some(m);
+                },
+                SaveMessage::Reset => {
+                    finish_file(&mut file).context("file io error")?;
+                },
             }
         }
+        finish_file(&mut file).context("file io error")
     });
-    SaveHandle { sender }
+    SaveHandle { sender, thread }
+    }
+
+    /// Write out any buffered tags and wait for the save thread to exit,
+    /// returning the error that stopped it early, if any
+    pub fn finish(self) -> Result<()> {
+        drop(self.sender);
+        match self.thread.join() {
+            Ok(result) => result,
+            Err(_) => bail!("save thread panicked"),
+        }
     }
 }
 
 fn update_and_write_file(
     newpath: Option<path::PathBuf>,
     curpath: &mut Option<path::PathBuf>,
-    f: &mut Option<fs::File>,
+    f: &mut Option<ser::TagWriter<fs::File>>,
+    meta: &Option<Box<cfg::Metadata>>,
     tags: &[Tag],
+    flushed: &mut Instant,
 ) -> Result<()>
 {
     match f {
         Some(_) => {
             if newpath != *curpath {
-                update_file(f, newpath)?;
+                finish_file(f)?;
+                update_file(f, newpath, meta)?;
             }
         },
         None => {
-            update_file(f, newpath)?;
+            update_file(f, newpath, meta)?;
         },
     }
-    ser::tags(f.as_mut().unwrap(), &tags)?;
+    let wtr = f.as_mut().unwrap();
+    wtr.write(tags)?;
+    // Batches fill up between flushes, and a killed acquisition loses at most
+    // the tags since the last one
+    if flushed.elapsed() >= FLUSH_INTERVAL {
+        wtr.flush()?;
+        *flushed = Instant::now();
+    }
     Ok(())
 }
 
-fn update_file(f: &mut Option<fs::File>, newpath: Option<path::PathBuf>) -> Result<()> {
+/// Finish the current file, if any, so the next save starts a new one
+fn finish_file(f: &mut Option<ser::TagWriter<fs::File>>) -> Result<()> {
+    if let Some(wtr) = f.take() {
+        wtr.finish()?;
+    }
+    Ok(())
+}
+
+fn update_file(
+    f: &mut Option<ser::TagWriter<fs::File>>,
+    newpath: Option<path::PathBuf>,
+    meta: &Option<Box<cfg::Metadata>>,
+) -> Result<()> {
     let mut path: path::PathBuf;
     match newpath {
         Some(p) => {
@@ -88,7 +128,11 @@ fn update_file(f: &mut Option<fs::File>, newpath: Option<path::PathBuf>) -> Resu
     if path.exists() {
         bail!("file already exists");
     } else {
-        *f = Some(fs::File::create(path)?);
+        let mut wtr = ser::TagWriter::indexed(fs::File::create(path)?, ser::BATCH_DEFAULT)?;
+        if let Some(m) = meta {
+            wtr.write_metadata(m)?;
+        }
+        *f = Some(wtr);
     }
     Ok(())
 }
\ No newline at end of file
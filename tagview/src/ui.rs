@@ -89,6 +89,11 @@ fn draw_settings_tab<B: Backend>(f: &mut Frame<B>, app: &mut App, area: Rect) {
                     "save modified runfile",
                     Style::default().add_modifier(Modifier::DIM),
                 ),
+                Span::raw("  k "),
+                Span::styled(
+                    "align delays to channel",
+                    Style::default().add_modifier(Modifier::DIM),
+                ),
                 Span::raw("  Color key: "),
                 Span::styled(
                     "unchanged ",
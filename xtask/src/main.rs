@@ -11,7 +11,7 @@ use std::{
 
 const GIT_VERSION: &str = git_version::git_version!();
 
-const BINARY_TARGETS: [&'static str; 7] = [
+const BINARY_TARGETS: [&'static str; 16] = [
     "tagsave",
     "tagview",
     "tagstream",
@@ -19,6 +19,15 @@ const BINARY_TARGETS: [&'static str; 7] = [
     "txt2tags",
     "checkrun",
     "coincidence_histogram",
+    "lifetime_histogram",
+    "g2",
+    "g3",
+    "rate_trace",
+    "allan_deviation",
+    "afterpulsing",
+    "phase_histogram",
+    "hom_fit",
+    "ptu2tags",
 ];
 
 // Executables that statically link proprietary vendor code